@@ -0,0 +1,170 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// the cost of a straight step, costs are kept in whole numbers so the queue can sort them
+const STEP_COST: u32 = 100;
+
+// the shortest path between two cells of a grid, by A*. which cells can be walked on is
+// up to the caller, and a diagonal step costs `diagonal_cost` straight ones
+pub struct AStar {
+    width: i32,
+    height: i32,
+    walkable: Vec<bool>,
+    diagonal_cost: u32,
+    // the steps still to take, the next one last
+    path: Vec<(i32, i32)>,
+}
+
+impl AStar {
+    // a grid with nothing walkable, until told otherwise
+    pub fn new(width: i32, height: i32, diagonal_cost: f32) -> Self {
+        AStar {
+            width,
+            height,
+            walkable: vec![false; (width * height) as usize],
+            diagonal_cost: (diagonal_cost * STEP_COST as f32).round() as u32,
+            path: vec![],
+        }
+    }
+
+    fn idx(&self, x: i32, y: i32) -> Option<usize> {
+        let inside = x >= 0 && y >= 0 && x < self.width && y < self.height;
+        inside.then(|| (y * self.width + x) as usize)
+    }
+
+    pub fn set_walkable(&mut self, x: i32, y: i32, walkable: bool) {
+        if let Some(idx) = self.idx(x, y) {
+            self.walkable[idx] = walkable;
+        }
+    }
+
+    fn is_walkable(&self, x: i32, y: i32) -> bool {
+        self.idx(x, y).is_some_and(|idx| self.walkable[idx])
+    }
+
+    // a guess at the cost from one cell to another that is never too high: straight steps
+    // for the difference, diagonal ones for the rest
+    fn estimate(&self, (x, y): (i32, i32), (to_x, to_y): (i32, i32)) -> u32 {
+        let (dx, dy) = ((to_x - x).unsigned_abs(), (to_y - y).unsigned_abs());
+        let (diagonal, straight) = (dx.min(dy), dx.max(dy) - dx.min(dy));
+        diagonal * self.diagonal_cost.min(2 * STEP_COST) + straight * STEP_COST
+    }
+
+    // look for a path, and keep it for walk_one_step. the goal has to be walkable, the
+    // start doesn't. false if there is no way there
+    pub fn find(&mut self, from: (i32, i32), to: (i32, i32)) -> bool {
+        self.path.clear();
+        let (Some(start), Some(goal)) = (self.idx(from.0, from.1), self.idx(to.0, to.1)) else {
+            return false;
+        };
+        if !self.walkable[goal] {
+            return false;
+        }
+        let mut costs = vec![None; self.walkable.len()];
+        let mut came_from = vec![None; self.walkable.len()];
+        let mut queue = BinaryHeap::new();
+        costs[start] = Some(0);
+        queue.push(Reverse((self.estimate(from, to), 0, from)));
+        while let Some(Reverse((_, cost, (x, y)))) = queue.pop() {
+            if (x, y) == to {
+                break;
+            }
+            if costs[(y * self.width + x) as usize].is_some_and(|best| best < cost) {
+                continue; // already reached this cell by a cheaper route
+            }
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if (dx, dy) == (0, 0) || !self.is_walkable(nx, ny) {
+                        continue;
+                    }
+                    let step = if dx != 0 && dy != 0 {
+                        self.diagonal_cost
+                    } else {
+                        STEP_COST
+                    };
+                    let new_cost = cost + step;
+                    let next = (ny * self.width + nx) as usize;
+                    if costs[next].is_none_or(|best| new_cost < best) {
+                        costs[next] = Some(new_cost);
+                        came_from[next] = Some((x, y));
+                        let priority = new_cost + self.estimate((nx, ny), to);
+                        queue.push(Reverse((priority, new_cost, (nx, ny))));
+                    }
+                }
+            }
+        }
+        if costs[goal].is_none() || start == goal {
+            return start == goal;
+        }
+        // walk back from the goal, which leaves the next step at the end
+        let mut cell = to;
+        while cell != from {
+            self.path.push(cell);
+            let Some(previous) = came_from[(cell.1 * self.width + cell.0) as usize] else {
+                break;
+            };
+            cell = previous;
+        }
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.path.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.path.is_empty()
+    }
+
+    // the next cell of the path found, taking it off the path
+    pub fn walk_one_step(&mut self) -> Option<(i32, i32)> {
+        self.path.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 7x5 open grid with a wall down the middle, open only at the bottom
+    fn walled_grid() -> AStar {
+        let mut path = AStar::new(7, 5, 1.41);
+        for y in 0..5 {
+            for x in 0..7 {
+                path.set_walkable(x, y, x != 3 || y == 4);
+            }
+        }
+        path
+    }
+
+    #[test]
+    fn paths_go_around_walls() {
+        let mut path = walled_grid();
+        assert!(path.find((1, 0), (5, 0)));
+        let mut cells = vec![];
+        while let Some(cell) = path.walk_one_step() {
+            cells.push(cell);
+        }
+        assert_eq!(cells.last(), Some(&(5, 0)));
+        // every step is to a neighbouring open cell, through the gap at the bottom
+        let mut from = (1, 0);
+        for &(x, y) in &cells {
+            assert!((x - from.0).abs() <= 1 && (y - from.1).abs() <= 1);
+            assert!(path.is_walkable(x, y));
+            from = (x, y);
+        }
+        assert!(cells.contains(&(3, 4)));
+        // down the side, through the gap and back up again
+        assert_eq!(cells.len(), 8);
+    }
+
+    #[test]
+    fn no_path_through_a_closed_wall() {
+        let mut path = walled_grid();
+        path.set_walkable(3, 4, false);
+        assert!(!path.find((1, 0), (5, 0)));
+        assert!(path.is_empty());
+        assert_eq!(path.walk_one_step(), None);
+    }
+}
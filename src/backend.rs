@@ -0,0 +1,625 @@
+// where the game is drawn and where its input comes from. the game composes every frame
+// into an offscreen console and hands it to a backend: the tcod window, with the "tcod"
+// feature, or a plain terminal through crossterm, with the "terminal" feature. everything
+// else, the consoles, FOV and pathfinding, is the game's own, so a terminal-only build
+// doesn't need tcod or SDL at all
+#[cfg(feature = "tcod")]
+use std::path::Path;
+#[cfg(feature = "tcod")]
+use std::thread;
+#[cfg(feature = "tcod")]
+use std::time::Instant;
+use std::time::Duration;
+
+use crate::colors::Color;
+use crate::console::Offscreen;
+#[cfg(feature = "tcod")]
+use crate::tileset::Tileset;
+
+#[cfg(feature = "terminal")]
+use std::io::{self, Stdout, Write};
+
+#[cfg(feature = "terminal")]
+use crossterm::{cursor, event, execute, queue, style, terminal};
+
+// how long the tcod window sleeps between looking for input, while waiting for some
+#[cfg(feature = "tcod")]
+const WAIT_SLEEP_MS: u64 = 10;
+
+// the keys the game knows about, besides printable characters, which are all Text
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyCode {
+    // no key at all, e.g. in a frame without input
+    #[default]
+    NoKey,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    Backspace,
+    Tab,
+    Enter,
+    Escape,
+    Spacebar,
+    NumPad0,
+    NumPad1,
+    NumPad2,
+    NumPad3,
+    NumPad4,
+    NumPad5,
+    NumPad6,
+    NumPad7,
+    NumPad8,
+    NumPad9,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    Text,
+}
+
+// a key press, the same for every backend. printable characters come as KeyCode::Text,
+// already shifted (so '>' and not Shift+'.'), everything else keeps its own code
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Key {
+    pub code: KeyCode,
+    // the character for KeyCode::Text, '\0' for the rest
+    pub printable: char,
+    pub alt: bool,
+}
+
+impl Key {
+    #[cfg(any(feature = "tcod", feature = "terminal"))]
+    fn text(printable: char, alt: bool) -> Self {
+        Key {
+            code: KeyCode::Text,
+            printable,
+            alt,
+        }
+    }
+}
+
+// where the mouse is, in cells, and which buttons went down with this event
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Mouse {
+    pub cx: isize,
+    pub cy: isize,
+    pub lbutton_pressed: bool,
+    pub rbutton_pressed: bool,
+    pub mbutton_pressed: bool,
+    pub wheel_up: bool,
+    pub wheel_down: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Input {
+    Key(Key),
+    Mouse(Mouse),
+}
+
+pub trait Backend {
+    // the size of the screen, in cells
+    fn dimensions(&self) -> (i32, i32);
+
+    fn put_char(&mut self, x: i32, y: i32, c: char, fg: Color);
+
+    fn set_bg(&mut self, x: i32, y: i32, bg: Color);
+
+    // show everything drawn since the last flush
+    fn flush(&mut self);
+
+    // the next key press or mouse event, if there is one. this also keeps the frame
+    // rate down, so calling it once per frame is enough
+    fn poll_event(&mut self) -> Option<Input>;
+
+    // block until a key is pressed
+    fn wait_key(&mut self) -> Key;
+
+    // block until a key is pressed or the mouse does something. when the window closes
+    // it gives up, with an empty key
+    fn wait_event(&mut self) -> Input;
+
+    // like wait_event, but only for so long
+    fn wait_event_timeout(&mut self, timeout: Duration) -> Option<Input>;
+
+    fn window_closed(&self) -> bool;
+
+    fn toggle_fullscreen(&mut self) {}
+
+    fn save_screenshot(&mut self, _path: &str) -> Result<(), String> {
+        Err("screenshots aren't supported here".to_string())
+    }
+
+    // draw a whole frame and show it
+    fn present(&mut self, screen: &Offscreen) {
+        let (width, height) = self.dimensions();
+        for y in 0..height {
+            for x in 0..width {
+                self.set_bg(x, y, screen.get_char_background(x, y));
+                let fg = screen.get_char_foreground(x, y);
+                self.put_char(x, y, screen.get_char(x, y), fg);
+            }
+        }
+        self.flush();
+    }
+}
+
+#[cfg(feature = "tcod")]
+pub struct TcodBackend {
+    root: tcod::console::Root,
+}
+
+#[cfg(feature = "tcod")]
+impl TcodBackend {
+    // `fps` caps how often flush shows a frame, 0 for no cap
+    pub fn new(width: i32, height: i32, fps: i32, tileset: &Tileset) -> Self {
+        let root = tcod::console::Root::initializer()
+            .font(&tileset.font, tileset.font_layout())
+            .font_type(tcod::console::FontType::Greyscale)
+            .font_dimensions(tileset.columns, tileset.rows)
+            .size(width, height)
+            .title("Rust/libtcod tutorial")
+            .init();
+        tcod::system::set_fps(fps);
+        TcodBackend { root }
+    }
+}
+
+#[cfg(feature = "tcod")]
+fn tcod_color(color: Color) -> tcod::colors::Color {
+    tcod::colors::Color::new(color.r, color.g, color.b)
+}
+
+#[cfg(feature = "tcod")]
+impl Backend for TcodBackend {
+    fn dimensions(&self) -> (i32, i32) {
+        use tcod::console::Console;
+        (self.root.width(), self.root.height())
+    }
+
+    fn put_char(&mut self, x: i32, y: i32, c: char, fg: Color) {
+        use tcod::console::Console;
+        self.root.set_char(x, y, c);
+        self.root.set_char_foreground(x, y, tcod_color(fg));
+    }
+
+    fn set_bg(&mut self, x: i32, y: i32, bg: Color) {
+        use tcod::console::{BackgroundFlag, Console};
+        self.root.set_char_background(x, y, tcod_color(bg), BackgroundFlag::Set);
+    }
+
+    fn flush(&mut self) {
+        self.root.flush();
+    }
+
+    fn poll_event(&mut self) -> Option<Input> {
+        use tcod::input::{KEY_PRESS, MOUSE, check_for_event};
+        convert_event(check_for_event(KEY_PRESS | MOUSE)?.1)
+    }
+
+    fn wait_key(&mut self) -> Key {
+        use tcod::input::KeyCode as TcodKey;
+        let key = self.root.wait_for_keypress(true);
+        match key.code {
+            TcodKey::Char => Key::text(key.printable, key.alt),
+            TcodKey::Text => Key::text(key.text().chars().next().unwrap_or('\0'), key.alt),
+            code => Key {
+                code: key_code(code).unwrap_or_default(),
+                printable: '\0',
+                alt: key.alt,
+            },
+        }
+    }
+
+    fn wait_event(&mut self) -> Input {
+        // the binding has no blocking wait for both keys and the mouse, so look every few
+        // milliseconds and sleep in between, which leaves the CPU all but idle
+        while !self.root.window_closed() {
+            if let Some(input) = self.poll_event() {
+                return input;
+            }
+            thread::sleep(Duration::from_millis(WAIT_SLEEP_MS));
+        }
+        Input::Key(Key::default())
+    }
+
+    fn wait_event_timeout(&mut self, timeout: Duration) -> Option<Input> {
+        let start = Instant::now();
+        while !self.root.window_closed() {
+            if let Some(input) = self.poll_event() {
+                return Some(input);
+            }
+            if start.elapsed() >= timeout {
+                break;
+            }
+            thread::sleep(Duration::from_millis(WAIT_SLEEP_MS));
+        }
+        None
+    }
+
+    fn window_closed(&self) -> bool {
+        self.root.window_closed()
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let fullscreen = self.root.is_fullscreen();
+        self.root.set_fullscreen(!fullscreen);
+    }
+
+    // dump the window, as last flushed, to a PNG file. libtcod doesn't report whether
+    // that worked, so check for the file afterwards
+    fn save_screenshot(&mut self, path: &str) -> Result<(), String> {
+        tcod::system::save_screenshot(path);
+        if Path::new(path).is_file() {
+            Ok(())
+        } else {
+            Err(format!("{} was not written", path))
+        }
+    }
+}
+
+// the game's code for a tcod one, None for the keys the game has no use for
+#[cfg(feature = "tcod")]
+fn key_code(code: tcod::input::KeyCode) -> Option<KeyCode> {
+    use tcod::input::KeyCode as TcodKey;
+    let code = match code {
+        TcodKey::Up => KeyCode::Up,
+        TcodKey::Down => KeyCode::Down,
+        TcodKey::Left => KeyCode::Left,
+        TcodKey::Right => KeyCode::Right,
+        TcodKey::Home => KeyCode::Home,
+        TcodKey::End => KeyCode::End,
+        TcodKey::PageUp => KeyCode::PageUp,
+        TcodKey::PageDown => KeyCode::PageDown,
+        TcodKey::Insert => KeyCode::Insert,
+        TcodKey::Delete => KeyCode::Delete,
+        TcodKey::Backspace => KeyCode::Backspace,
+        TcodKey::Tab => KeyCode::Tab,
+        TcodKey::Enter | TcodKey::NumPadEnter => KeyCode::Enter,
+        TcodKey::Escape => KeyCode::Escape,
+        TcodKey::Spacebar => KeyCode::Spacebar,
+        TcodKey::NumPad0 => KeyCode::NumPad0,
+        TcodKey::NumPad1 => KeyCode::NumPad1,
+        TcodKey::NumPad2 => KeyCode::NumPad2,
+        TcodKey::NumPad3 => KeyCode::NumPad3,
+        TcodKey::NumPad4 => KeyCode::NumPad4,
+        TcodKey::NumPad5 => KeyCode::NumPad5,
+        TcodKey::NumPad6 => KeyCode::NumPad6,
+        TcodKey::NumPad7 => KeyCode::NumPad7,
+        TcodKey::NumPad8 => KeyCode::NumPad8,
+        TcodKey::NumPad9 => KeyCode::NumPad9,
+        TcodKey::F1 => KeyCode::F1,
+        TcodKey::F2 => KeyCode::F2,
+        TcodKey::F3 => KeyCode::F3,
+        TcodKey::F4 => KeyCode::F4,
+        TcodKey::F5 => KeyCode::F5,
+        TcodKey::F6 => KeyCode::F6,
+        TcodKey::F7 => KeyCode::F7,
+        TcodKey::F8 => KeyCode::F8,
+        TcodKey::F9 => KeyCode::F9,
+        TcodKey::F10 => KeyCode::F10,
+        TcodKey::F11 => KeyCode::F11,
+        TcodKey::F12 => KeyCode::F12,
+        _ => return None,
+    };
+    Some(code)
+}
+
+// a tcod event as input for the game, None for the ones it doesn't care about
+#[cfg(feature = "tcod")]
+fn convert_event(event: tcod::input::Event) -> Option<Input> {
+    use tcod::input::{Event, KeyCode as TcodKey};
+    match event {
+        Event::Mouse(mouse) => Some(Input::Mouse(Mouse {
+            cx: mouse.cx,
+            cy: mouse.cy,
+            lbutton_pressed: mouse.lbutton_pressed,
+            rbutton_pressed: mouse.rbutton_pressed,
+            mbutton_pressed: mouse.mbutton_pressed,
+            wheel_up: mouse.wheel_up,
+            wheel_down: mouse.wheel_down,
+        })),
+        // a character arrives twice, first as Char and then as Text. only the text
+        // has the shifted character, so that's the one to keep
+        Event::Key(key) if key.code == TcodKey::Char => None,
+        Event::Key(key) if key.code == TcodKey::Text => {
+            let printable = key.text().chars().next().unwrap_or('\0');
+            Some(Input::Key(Key::text(printable, key.alt)))
+        }
+        Event::Key(key) => Some(Input::Key(Key {
+            code: key_code(key.code)?,
+            printable: '\0',
+            alt: key.alt,
+        })),
+    }
+}
+
+#[cfg(feature = "terminal")]
+#[derive(Clone, Copy, PartialEq)]
+struct Cell {
+    c: char,
+    fg: Color,
+    bg: Color,
+}
+
+// draws into a terminal with true colors, one character per cell. the terminal has to be
+// at least as big as the screen
+#[cfg(feature = "terminal")]
+pub struct TerminalBackend {
+    out: Stdout,
+    width: i32,
+    height: i32,
+    // the frame being drawn, and what the terminal shows right now. only the cells that
+    // differ are sent on flush, None forces a cell to be redrawn
+    cells: Vec<Cell>,
+    shown: Vec<Option<Cell>>,
+    // the mouse position, kept between events since the terminal only reports changes
+    mouse: Mouse,
+    // Ctrl+C, as there is no window to close
+    closed: bool,
+    // how long poll_event waits for input, one frame
+    frame: Duration,
+}
+
+#[cfg(feature = "terminal")]
+impl TerminalBackend {
+    // `fps` is how many frames a second poll_event allows for, 0 for as many as it can
+    pub fn new(width: i32, height: i32, fps: i32) -> Result<Self, String> {
+        let (columns, rows) = terminal::size().map_err(|err| err.to_string())?;
+        if i32::from(columns) < width || i32::from(rows) < height {
+            return Err(format!(
+                "The terminal is {}x{}, but the game needs at least {}x{}",
+                columns, rows, width, height
+            ));
+        }
+        terminal::enable_raw_mode().map_err(|err| err.to_string())?;
+        let mut out = io::stdout();
+        execute!(
+            out,
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            event::EnableMouseCapture
+        )
+        .map_err(|err| err.to_string())?;
+
+        let blank = Cell {
+            c: ' ',
+            fg: Color::new(255, 255, 255),
+            bg: Color::new(0, 0, 0),
+        };
+        let size = (width * height) as usize;
+        Ok(TerminalBackend {
+            out,
+            width,
+            height,
+            cells: vec![blank; size],
+            shown: vec![None; size],
+            mouse: Default::default(),
+            closed: false,
+            frame: Duration::from_millis(if fps > 0 { 1000 / fps as u64 } else { 0 }),
+        })
+    }
+
+    fn idx(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            Some((y * self.width + x) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn key(&mut self, key: event::KeyEvent) -> Option<Key> {
+        use crossterm::event::{KeyCode as TermKey, KeyEventKind, KeyModifiers};
+        use KeyCode::*;
+
+        if key.kind == KeyEventKind::Release {
+            return None;
+        }
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        let code = match key.code {
+            TermKey::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.closed = true;
+                return None;
+            }
+            TermKey::Char(' ') => Spacebar,
+            TermKey::Char(c) => return Some(Key::text(c, alt)),
+            TermKey::Up => Up,
+            TermKey::Down => Down,
+            TermKey::Left => Left,
+            TermKey::Right => Right,
+            TermKey::Home => Home,
+            TermKey::End => End,
+            TermKey::PageUp => PageUp,
+            TermKey::PageDown => PageDown,
+            TermKey::Insert => Insert,
+            TermKey::Delete => Delete,
+            TermKey::Backspace => Backspace,
+            TermKey::Tab => Tab,
+            TermKey::Enter => Enter,
+            TermKey::Esc => Escape,
+            TermKey::F(n) => match n {
+                1 => F1,
+                2 => F2,
+                3 => F3,
+                4 => F4,
+                5 => F5,
+                6 => F6,
+                7 => F7,
+                8 => F8,
+                9 => F9,
+                10 => F10,
+                11 => F11,
+                12 => F12,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(Key {
+            code,
+            printable: '\0',
+            alt,
+        })
+    }
+
+    fn mouse(&mut self, mouse: event::MouseEvent) -> Mouse {
+        use crossterm::event::{MouseButton, MouseEventKind};
+
+        self.mouse.cx = mouse.column as isize;
+        self.mouse.cy = mouse.row as isize;
+        let mut result = self.mouse;
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => result.lbutton_pressed = true,
+            MouseEventKind::Down(MouseButton::Right) => result.rbutton_pressed = true,
+            MouseEventKind::Down(MouseButton::Middle) => result.mbutton_pressed = true,
+            MouseEventKind::ScrollUp => result.wheel_up = true,
+            MouseEventKind::ScrollDown => result.wheel_down = true,
+            _ => {}
+        }
+        result
+    }
+
+    fn read_event(&mut self) -> Option<Input> {
+        // a terminal that can't be read from any more is as good as closed
+        let Ok(event) = event::read() else {
+            self.closed = true;
+            return None;
+        };
+        match event {
+            event::Event::Key(key) => self.key(key).map(Input::Key),
+            event::Event::Mouse(mouse) => Some(Input::Mouse(self.mouse(mouse))),
+            event::Event::Resize(..) => {
+                // the terminal may have cleared itself, so draw everything again
+                let _ = execute!(self.out, terminal::Clear(terminal::ClearType::All));
+                self.shown.iter_mut().for_each(|cell| *cell = None);
+                self.flush();
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "terminal")]
+impl Backend for TerminalBackend {
+    fn dimensions(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    fn put_char(&mut self, x: i32, y: i32, c: char, fg: Color) {
+        if let Some(idx) = self.idx(x, y) {
+            // an empty console cell holds '\0', which a terminal won't print
+            let c = if c == '\0' { ' ' } else { c };
+            self.cells[idx].c = c;
+            self.cells[idx].fg = fg;
+        }
+    }
+
+    fn set_bg(&mut self, x: i32, y: i32, bg: Color) {
+        if let Some(idx) = self.idx(x, y) {
+            self.cells[idx].bg = bg;
+        }
+    }
+
+    fn flush(&mut self) {
+        let rgb = |color: Color| style::Color::Rgb {
+            r: color.r,
+            g: color.g,
+            b: color.b,
+        };
+        // the cursor and colors left by the previous cell, so runs of changed cells with
+        // the same colors don't repeat them. a terminal that went away can't be drawn
+        // on, so errors are dropped here
+        let mut cursor_at = None;
+        let mut colors = None;
+        for (idx, cell) in self.cells.iter().enumerate() {
+            if self.shown[idx] == Some(*cell) {
+                continue;
+            }
+            if cursor_at != Some(idx) {
+                let x = (idx as i32 % self.width) as u16;
+                let y = (idx as i32 / self.width) as u16;
+                let _ = queue!(self.out, cursor::MoveTo(x, y));
+            }
+            if colors != Some((cell.fg, cell.bg)) {
+                let _ = queue!(
+                    self.out,
+                    style::SetForegroundColor(rgb(cell.fg)),
+                    style::SetBackgroundColor(rgb(cell.bg))
+                );
+                colors = Some((cell.fg, cell.bg));
+            }
+            let _ = queue!(self.out, style::Print(cell.c));
+            // printing in the last column wraps, so only trust the cursor within a row
+            cursor_at = Some(idx + 1).filter(|next| next % self.width as usize != 0);
+            self.shown[idx] = Some(*cell);
+        }
+        let _ = self.out.flush();
+    }
+
+    fn poll_event(&mut self) -> Option<Input> {
+        if event::poll(self.frame).unwrap_or(false) {
+            self.read_event()
+        } else {
+            None
+        }
+    }
+
+    fn wait_key(&mut self) -> Key {
+        while !self.closed {
+            if let Some(Input::Key(key)) = self.read_event() {
+                return key;
+            }
+        }
+        Key::default()
+    }
+
+    fn wait_event(&mut self) -> Input {
+        while !self.closed {
+            if let Some(input) = self.read_event() {
+                return input;
+            }
+        }
+        Input::Key(Key::default())
+    }
+
+    fn wait_event_timeout(&mut self, timeout: Duration) -> Option<Input> {
+        if event::poll(timeout).unwrap_or(false) {
+            self.read_event()
+        } else {
+            None
+        }
+    }
+
+    fn window_closed(&self) -> bool {
+        self.closed
+    }
+}
+
+// give the terminal back the way it was found
+#[cfg(feature = "terminal")]
+impl Drop for TerminalBackend {
+    fn drop(&mut self) {
+        let _ = execute!(
+            self.out,
+            style::ResetColor,
+            event::DisableMouseCapture,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
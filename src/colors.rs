@@ -0,0 +1,65 @@
+// 24-bit colors, and the named ones the game uses. they have the same values as libtcod's,
+// so the game looks the same whether or not it is built with tcod
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+}
+
+// the color `coefficient` of the way from one to the other
+pub fn lerp(from: Color, to: Color, coefficient: f32) -> Color {
+    let channel = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * coefficient) as u8;
+    Color {
+        r: channel(from.r, to.r),
+        g: channel(from.g, to.g),
+        b: channel(from.b, to.b),
+    }
+}
+
+pub const BLACK: Color = Color::new(0, 0, 0);
+pub const WHITE: Color = Color::new(255, 255, 255);
+pub const GREY: Color = Color::new(127, 127, 127);
+pub const LIGHT_GREY: Color = Color::new(159, 159, 159);
+
+pub const RED: Color = Color::new(255, 0, 0);
+pub const LIGHT_RED: Color = Color::new(255, 63, 63);
+pub const DARK_RED: Color = Color::new(191, 0, 0);
+pub const DARKER_RED: Color = Color::new(127, 0, 0);
+pub const LIGHT_CRIMSON: Color = Color::new(255, 63, 111);
+
+pub const ORANGE: Color = Color::new(255, 127, 0);
+pub const DARKER_ORANGE: Color = Color::new(127, 63, 0);
+pub const AMBER: Color = Color::new(255, 191, 0);
+pub const DARKER_AMBER: Color = Color::new(127, 95, 0);
+pub const GOLD: Color = Color::new(229, 191, 0);
+
+pub const YELLOW: Color = Color::new(255, 255, 0);
+pub const LIGHT_YELLOW: Color = Color::new(255, 255, 63);
+
+pub const GREEN: Color = Color::new(0, 255, 0);
+pub const LIGHT_GREEN: Color = Color::new(63, 255, 63);
+pub const DARK_GREEN: Color = Color::new(0, 191, 0);
+
+pub const LIGHT_CYAN: Color = Color::new(63, 255, 255);
+pub const SKY: Color = Color::new(0, 191, 255);
+pub const LIGHT_SKY: Color = Color::new(63, 207, 255);
+
+pub const BLUE: Color = Color::new(0, 0, 255);
+pub const LIGHT_BLUE: Color = Color::new(63, 63, 255);
+pub const DARK_BLUE: Color = Color::new(0, 0, 191);
+pub const DARKER_BLUE: Color = Color::new(0, 0, 127);
+pub const DARKEST_BLUE: Color = Color::new(0, 0, 63);
+
+pub const VIOLET: Color = Color::new(127, 0, 255);
+pub const LIGHT_VIOLET: Color = Color::new(159, 63, 255);
+
+pub const SEPIA: Color = Color::new(127, 101, 63);
+pub const LIGHT_SEPIA: Color = Color::new(158, 134, 100);
+pub const DARKER_SEPIA: Color = Color::new(63, 50, 31);
@@ -0,0 +1,310 @@
+// a grid of character cells to compose a frame in, the way libtcod's offscreen consoles
+// work: each cell has a character and a foreground and background color. the game draws
+// into these consoles and blits them together, and only the finished frame goes to the
+// backend
+use crate::colors::{BLACK, Color, WHITE, lerp};
+use crate::wrap_text;
+
+// how a new background color combines with the one already in a cell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BackgroundFlag {
+    // leave the background alone
+    None,
+    // replace it
+    Set,
+    // lighten it, the way light shone on it would
+    Screen,
+}
+
+impl BackgroundFlag {
+    fn apply(self, old: Color, new: Color) -> Color {
+        let screen = |old: u8, new: u8| 255 - ((255 - old as u32) * (255 - new as u32) / 255) as u8;
+        match self {
+            BackgroundFlag::None => old,
+            BackgroundFlag::Set => new,
+            BackgroundFlag::Screen => Color {
+                r: screen(old.r, new.r),
+                g: screen(old.g, new.g),
+                b: screen(old.b, new.b),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAlignment {
+    Left,
+    // centered on the given x
+    Center,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Cell {
+    c: char,
+    fg: Color,
+    bg: Color,
+}
+
+pub struct Offscreen {
+    width: i32,
+    height: i32,
+    cells: Vec<Cell>,
+    // what put_char, print and rect draw with
+    foreground: Color,
+    background: Color,
+}
+
+impl Offscreen {
+    pub fn new(width: i32, height: i32) -> Self {
+        let mut console = Offscreen {
+            width,
+            height,
+            cells: vec![],
+            foreground: WHITE,
+            background: BLACK,
+        };
+        console.clear();
+        console
+    }
+
+    fn cell(&self, x: i32, y: i32) -> Option<&Cell> {
+        self.index(x, y).map(|index| &self.cells[index])
+    }
+
+    fn cell_mut(&mut self, x: i32, y: i32) -> Option<&mut Cell> {
+        self.index(x, y).map(|index| &mut self.cells[index])
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        let inside = x >= 0 && y >= 0 && x < self.width && y < self.height;
+        inside.then(|| (y * self.width + x) as usize)
+    }
+
+    // blank every cell, in the default colors
+    pub fn clear(&mut self) {
+        let blank = Cell {
+            c: ' ',
+            fg: self.foreground,
+            bg: self.background,
+        };
+        self.cells = vec![blank; (self.width * self.height) as usize];
+    }
+
+    pub fn set_default_foreground(&mut self, color: Color) {
+        self.foreground = color;
+    }
+
+    pub fn set_default_background(&mut self, color: Color) {
+        self.background = color;
+    }
+
+    // drawing outside of the console does nothing, for all of these
+    pub fn put_char(&mut self, x: i32, y: i32, c: char, flag: BackgroundFlag) {
+        let (foreground, background) = (self.foreground, self.background);
+        if let Some(cell) = self.cell_mut(x, y) {
+            cell.c = c;
+            cell.fg = foreground;
+            cell.bg = flag.apply(cell.bg, background);
+        }
+    }
+
+    pub fn set_char(&mut self, x: i32, y: i32, c: char) {
+        if let Some(cell) = self.cell_mut(x, y) {
+            cell.c = c;
+        }
+    }
+
+    pub fn set_char_foreground(&mut self, x: i32, y: i32, color: Color) {
+        if let Some(cell) = self.cell_mut(x, y) {
+            cell.fg = color;
+        }
+    }
+
+    pub fn set_char_background(&mut self, x: i32, y: i32, color: Color, flag: BackgroundFlag) {
+        if let Some(cell) = self.cell_mut(x, y) {
+            cell.bg = flag.apply(cell.bg, color);
+        }
+    }
+
+    // outside of the console, these read as a blank black cell
+    pub fn get_char(&self, x: i32, y: i32) -> char {
+        self.cell(x, y).map_or(' ', |cell| cell.c)
+    }
+
+    pub fn get_char_foreground(&self, x: i32, y: i32) -> Color {
+        self.cell(x, y).map_or(BLACK, |cell| cell.fg)
+    }
+
+    pub fn get_char_background(&self, x: i32, y: i32) -> Color {
+        self.cell(x, y).map_or(BLACK, |cell| cell.bg)
+    }
+
+    // fill a rectangle with the default background, and blank it too if `clear`
+    pub fn rect(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        clear: bool,
+        flag: BackgroundFlag,
+    ) {
+        for y in y..y + height {
+            for x in x..x + width {
+                if clear {
+                    self.set_char(x, y, ' ');
+                }
+                self.set_char_background(x, y, self.background, flag);
+            }
+        }
+    }
+
+    // print text in the default foreground, one line per line of the text
+    pub fn print_ex<T: AsRef<str>>(
+        &mut self,
+        x: i32,
+        y: i32,
+        flag: BackgroundFlag,
+        alignment: TextAlignment,
+        text: T,
+    ) {
+        for (line_y, line) in (y..).zip(text.as_ref().split('\n')) {
+            self.print_line(x, line_y, flag, alignment, line);
+        }
+    }
+
+    // like print_ex, but wrapped to the width of the rectangle and cut off below it.
+    // returns how many lines it took
+    #[allow(clippy::too_many_arguments)]
+    pub fn print_rect_ex<T: AsRef<str>>(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        flag: BackgroundFlag,
+        alignment: TextAlignment,
+        text: T,
+    ) -> i32 {
+        let lines = wrap_lines(text.as_ref(), width);
+        for (line_y, line) in (y..y + height).zip(&lines) {
+            self.print_line(x, line_y, flag, alignment, line);
+        }
+        lines.len() as i32
+    }
+
+    // how many lines print_rect_ex would take for the text
+    pub fn get_height_rect(&self, width: i32, height: i32, text: &str) -> i32 {
+        (wrap_lines(text, width).len() as i32).min(height)
+    }
+
+    fn print_line(
+        &mut self,
+        x: i32,
+        y: i32,
+        flag: BackgroundFlag,
+        alignment: TextAlignment,
+        line: &str,
+    ) {
+        let start = match alignment {
+            TextAlignment::Left => x,
+            TextAlignment::Center => x - line.chars().count() as i32 / 2,
+        };
+        for (x, c) in (start..).zip(line.chars()) {
+            self.put_char(x, y, c, flag);
+        }
+    }
+}
+
+// the lines a text wraps to, keeping the line breaks it already has. no text at all takes
+// no lines
+fn wrap_lines(text: &str, width: i32) -> Vec<String> {
+    if text.is_empty() {
+        return vec![];
+    }
+    text.split('\n')
+        .flat_map(|line| wrap_text(line, width.max(1) as usize))
+        .collect()
+}
+
+// copy a part of one console onto another. with alphas below 1 the source is blended
+// over what is there, like a see-through window
+pub fn blit(
+    source: &Offscreen,
+    (source_x, source_y): (i32, i32),
+    (width, height): (i32, i32),
+    destination: &mut Offscreen,
+    (destination_x, destination_y): (i32, i32),
+    foreground_alpha: f32,
+    background_alpha: f32,
+) {
+    for dy in 0..height {
+        for dx in 0..width {
+            let Some(&from) = source.cell(source_x + dx, source_y + dy) else {
+                continue;
+            };
+            let Some(to) = destination.cell_mut(destination_x + dx, destination_y + dy) else {
+                continue;
+            };
+            if foreground_alpha >= 1.0 && background_alpha >= 1.0 {
+                *to = from;
+                continue;
+            }
+            // the characters blend with the background as it is after this, like libtcod
+            to.bg = lerp(to.bg, from.bg, background_alpha);
+            if from.c == ' ' {
+                // what shows through is only dimmed by the window's background
+                to.fg = lerp(to.fg, from.bg, background_alpha);
+            } else if to.c == ' ' || to.c == from.c {
+                let below = if to.c == ' ' { to.bg } else { to.fg };
+                to.fg = lerp(below, from.fg, foreground_alpha);
+                to.c = from.c;
+            } else if foreground_alpha < 0.5 {
+                // the character below still wins, but fades
+                to.fg = lerp(to.fg, to.bg, foreground_alpha * 2.0);
+            } else {
+                to.c = from.c;
+                to.fg = lerp(to.bg, from.fg, (foreground_alpha - 0.5) * 2.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 3x1 console in the given colors, with the text at its left
+    fn console(text: &str, fg: Color, bg: Color) -> Offscreen {
+        let mut console = Offscreen::new(3, 1);
+        console.set_default_foreground(fg);
+        console.set_default_background(bg);
+        console.clear();
+        console.print_ex(0, 0, BackgroundFlag::None, TextAlignment::Left, text);
+        console
+    }
+
+    #[test]
+    fn blit_blends_like_libtcod() {
+        let below = console("ab", Color::new(0, 200, 0), Color::new(200, 0, 0));
+        let window = console("xb", Color::new(250, 250, 250), Color::new(0, 0, 100));
+        // what libtcod's own blit leaves, for a half see-through background and a few
+        // foreground alphas: the characters, then their colors
+        let cases = [
+            (1.0, "xb ", [(250, 250, 250), (250, 250, 250), (0, 100, 50)]),
+            (0.7, "xb ", [(160, 99, 130), (175, 235, 175), (0, 100, 50)]),
+            (0.3, "ab ", [(60, 79, 30), (75, 215, 75), (0, 100, 50)]),
+        ];
+        for (alpha, text, foregrounds) in cases {
+            let mut screen = Offscreen::new(3, 1);
+            blit(&below, (0, 0), (3, 1), &mut screen, (0, 0), 1.0, 1.0);
+            blit(&window, (0, 0), (3, 1), &mut screen, (0, 0), alpha, 0.5);
+            for (x, (c, (r, g, b))) in (0..).zip(text.chars().zip(foregrounds)) {
+                assert_eq!(screen.get_char(x, 0), c, "alpha {}, x {}", alpha, x);
+                let fg = screen.get_char_foreground(x, 0);
+                assert_eq!(fg, Color::new(r, g, b), "alpha {}, x {}", alpha, x);
+                assert_eq!(screen.get_char_background(x, 0), Color::new(100, 0, 50));
+            }
+        }
+    }
+}
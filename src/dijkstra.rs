@@ -0,0 +1,117 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::map::Map;
+
+// the cost of the cheapest walk from every tile to a goal, such as the player. monsters
+// chasing the goal just step to whichever neighbouring tile has the lowest value
+#[derive(Clone, Debug, Default)]
+pub struct DijkstraMap {
+    // where the distances were computed from, None before the first update
+    origin: Option<(i32, i32)>,
+    width: i32,
+    height: i32,
+    // None for tiles the goal can't be reached from, like walls
+    distances: Vec<Option<i32>>,
+}
+
+impl DijkstraMap {
+    pub fn new(origin: (i32, i32), map: &Map) -> Self {
+        let mut distances = vec![None; map.tiles.len()];
+        let mut queue = BinaryHeap::new();
+        if map.in_bounds(origin.0, origin.1) {
+            distances[map.idx(origin.0, origin.1)] = Some(0);
+            queue.push(Reverse((0, origin)));
+        }
+
+        // expand from the cheapest tile found so far, in all eight directions. stepping
+        // onto a tile costs its move_cost, so routes avoid slow terrain when they can
+        while let Some(Reverse((distance, (x, y)))) = queue.pop() {
+            if distances[map.idx(x, y)].is_some_and(|best| best < distance) {
+                continue; // already reached this tile by a cheaper route
+            }
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (nx, ny) = (x + dx, y + dy);
+                    let Some(tile) = map.get(nx, ny) else {
+                        continue;
+                    };
+                    if tile.blocked {
+                        continue;
+                    }
+                    let new_distance = distance + tile.kind.move_cost();
+                    let known = &mut distances[map.idx(nx, ny)];
+                    if known.is_none_or(|best| new_distance < best) {
+                        *known = Some(new_distance);
+                        queue.push(Reverse((new_distance, (nx, ny))));
+                    }
+                }
+            }
+        }
+
+        DijkstraMap {
+            origin: Some(origin),
+            width: map.width,
+            height: map.height,
+            distances,
+        }
+    }
+
+    // recompute the distances, but only if the goal moved since last time
+    pub fn update(&mut self, origin: (i32, i32), map: &Map) {
+        if self.origin != Some(origin) {
+            *self = DijkstraMap::new(origin, map);
+        }
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Option<i32> {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            self.distances[(y * self.width + x) as usize]
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Tile;
+
+    // an open room with a wall down the middle, which has a gap at the bottom
+    fn walled_map() -> Map {
+        let mut map = Map::new(9, 6, Tile::empty());
+        for y in 0..5 {
+            *map.get_mut(4, y).unwrap() = Tile::wall();
+        }
+        map
+    }
+
+    #[test]
+    fn walls_have_no_distance() {
+        let distances = DijkstraMap::new((1, 1), &walled_map());
+        assert_eq!(distances.get(1, 1), Some(0));
+        for y in 0..5 {
+            assert_eq!(distances.get(4, y), None);
+        }
+        assert_eq!(distances.get(-1, 0), None);
+    }
+
+    #[test]
+    fn distances_go_down_towards_the_origin() {
+        let distances = DijkstraMap::new((1, 1), &walled_map());
+        // go downhill from the other side of the wall, the way a monster would
+        let (mut x, mut y) = (7, 1);
+        let mut distance = distances.get(x, y).unwrap();
+        while distance > 0 {
+            let (next, (nx, ny)) = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+                .filter_map(|(nx, ny)| distances.get(nx, ny).map(|next| (next, (nx, ny))))
+                .min()
+                .unwrap();
+            assert!(next < distance, "({}, {}) doesn't lead closer", x, y);
+            (distance, x, y) = (next, nx, ny);
+        }
+        assert_eq!((x, y), (1, 1));
+    }
+}
@@ -0,0 +1,1035 @@
+// which cells can be seen from a spot, on a grid of cells that can or can't be seen
+// through. the game keeps one of these for the player, built from the level's tiles. the
+// algorithms are libtcod 1.6's, step for step, so the view is the same as it was when
+// the game used tcod's own map. the radius is 0 for no limit
+use crate::line::Line;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FovAlgorithm {
+    // a ray to every cell on the edge of the view, stopped by the first wall on the way
+    Basic,
+    // diamond walls raycasting, which lets the view slip past the corners of walls
+    Diamond,
+    // recursive shadowcasting
+    Shadow,
+    // precise permissive fov, where a cell is seen if any line from the viewer's cell to
+    // it is clear. the 0 to 8 is how much of the viewer's cell the lines may start from
+    Permissive(u8),
+    // mingos' restrictive precise angle shadowcasting, which keeps what's behind pillars
+    // in their shadow
+    Restrictive,
+}
+
+pub struct FovMap {
+    width: i32,
+    height: i32,
+    transparent: Vec<bool>,
+    in_fov: Vec<bool>,
+}
+
+impl FovMap {
+    // a map that can't be seen through anywhere, until told otherwise
+    pub fn new(width: i32, height: i32) -> Self {
+        let size = (width * height) as usize;
+        FovMap {
+            width,
+            height,
+            transparent: vec![false; size],
+            in_fov: vec![false; size],
+        }
+    }
+
+    fn idx(&self, x: i32, y: i32) -> Option<usize> {
+        let inside = x >= 0 && y >= 0 && x < self.width && y < self.height;
+        inside.then(|| (y * self.width + x) as usize)
+    }
+
+    pub fn set_transparent(&mut self, x: i32, y: i32, transparent: bool) {
+        if let Some(idx) = self.idx(x, y) {
+            self.transparent[idx] = transparent;
+        }
+    }
+
+    // anything off the map blocks the view and is never seen
+    fn is_transparent(&self, x: i32, y: i32) -> bool {
+        self.idx(x, y).is_some_and(|idx| self.transparent[idx])
+    }
+
+    pub fn is_in_fov(&self, x: i32, y: i32) -> bool {
+        self.idx(x, y).is_some_and(|idx| self.in_fov[idx])
+    }
+
+    fn see(&mut self, x: i32, y: i32) {
+        if let Some(idx) = self.idx(x, y) {
+            self.in_fov[idx] = true;
+        }
+    }
+
+    // work out what can be seen from the given cell. with `light_walls` the walls at the
+    // edge of what is seen are seen too, otherwise only the cells that can be seen through
+    pub fn compute_fov(
+        &mut self,
+        x: i32,
+        y: i32,
+        radius: i32,
+        light_walls: bool,
+        algorithm: FovAlgorithm,
+    ) {
+        self.in_fov.iter_mut().for_each(|seen| *seen = false);
+        if self.idx(x, y).is_none() {
+            return;
+        }
+        self.see(x, y);
+        match algorithm {
+            FovAlgorithm::Basic => self.basic(x, y, radius, light_walls),
+            FovAlgorithm::Diamond => self.diamond(x, y, radius, light_walls),
+            FovAlgorithm::Shadow => self.shadow(x, y, radius, light_walls),
+            FovAlgorithm::Permissive(level) => {
+                Permissive::compute(self, (x, y), radius, light_walls, level.min(8))
+            }
+            FovAlgorithm::Restrictive => {
+                for quadrant in [(1, 1), (1, -1), (-1, 1), (-1, -1)] {
+                    self.restrictive((x, y), radius, light_walls, quadrant, false);
+                    self.restrictive((x, y), radius, light_walls, quadrant, true);
+                }
+            }
+        }
+    }
+
+    // the square the radius reaches, clipped to the map, with the far ends left out
+    fn bounds(&self, x: i32, y: i32, radius: i32) -> (i32, i32, i32, i32) {
+        if radius > 0 {
+            let (min_x, min_y) = ((x - radius).max(0), (y - radius).max(0));
+            let max_x = (x + radius + 1).min(self.width);
+            let max_y = (y + radius + 1).min(self.height);
+            (min_x, min_y, max_x, max_y)
+        } else {
+            (0, 0, self.width, self.height)
+        }
+    }
+
+    fn basic(&mut self, x: i32, y: i32, radius: i32, light_walls: bool) {
+        let (min_x, min_y, max_x, max_y) = self.bounds(x, y, radius);
+        let r2 = radius * radius;
+        // around the edge of the square. libtcod's last two sides run on to the map's
+        // edge rather than the square's, which only matters where the radius cuts them
+        for to_x in min_x..max_x {
+            self.cast_ray((x, y), (to_x, min_y), r2, light_walls);
+        }
+        for to_y in min_y + 1..max_y {
+            self.cast_ray((x, y), (max_x - 1, to_y), r2, light_walls);
+        }
+        for to_x in 0..max_x - 1 {
+            self.cast_ray((x, y), (to_x, max_y - 1), r2, light_walls);
+        }
+        for to_y in 1..max_y - 1 {
+            self.cast_ray((x, y), (min_x, to_y), r2, light_walls);
+        }
+        if light_walls {
+            self.light_walls_behind(x, y, (min_x, min_y, max_x, max_y));
+        }
+    }
+
+    fn cast_ray(&mut self, from: (i32, i32), to: (i32, i32), r2: i32, light_walls: bool) {
+        let mut blocked = false;
+        for (cell_x, cell_y) in Line::new(from, to) {
+            let (dx, dy) = (cell_x - from.0, cell_y - from.1);
+            if r2 > 0 && dx * dx + dy * dy > r2 {
+                return;
+            }
+            // the ray goes on into the first wall, and stops there
+            if blocked {
+                return;
+            }
+            blocked = !self.is_transparent(cell_x, cell_y);
+            if light_walls || !blocked {
+                self.see(cell_x, cell_y);
+            }
+        }
+    }
+
+    // rays only reach the first wall they hit head on, so light the walls just past the
+    // open cells that were seen, on the side away from the viewer
+    fn light_walls_behind(&mut self, x: i32, y: i32, bounds: (i32, i32, i32, i32)) {
+        let (min_x, min_y, max_x, max_y) = bounds;
+        self.light_walls_in((min_x, min_y), (x, y), (-1, -1));
+        self.light_walls_in((x, min_y), (max_x - 1, y), (1, -1));
+        self.light_walls_in((min_x, y), (x, max_y - 1), (-1, 1));
+        self.light_walls_in((x, y), (max_x - 1, max_y - 1), (1, 1));
+    }
+
+    fn light_walls_in(&mut self, (x0, y0): (i32, i32), (x1, y1): (i32, i32), (dx, dy): (i32, i32)) {
+        for cell_x in x0..=x1 {
+            for cell_y in y0..=y1 {
+                if !self.is_in_fov(cell_x, cell_y) || !self.is_transparent(cell_x, cell_y) {
+                    continue;
+                }
+                let (wall_x, wall_y) = (cell_x + dx, cell_y + dy);
+                let x_inside = (x0..=x1).contains(&wall_x);
+                let y_inside = (y0..=y1).contains(&wall_y);
+                for ((x, y), inside) in [
+                    ((wall_x, cell_y), x_inside),
+                    ((cell_x, wall_y), y_inside),
+                    ((wall_x, wall_y), x_inside && y_inside),
+                ] {
+                    if inside && !self.is_transparent(x, y) {
+                        self.see(x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    // rays spread out from the viewer one cell at a time, each fed by the one or two cells
+    // next to it on the way back. a wall leaves the rays behind it obscured for as long as
+    // their error terms say they are in its shadow
+    fn diamond(&mut self, x: i32, y: i32, radius: i32, light_walls: bool) {
+        let mut rays = vec![Ray::default(); self.in_fov.len()];
+        let mut perimeter = vec![];
+        let origin = (y * self.width + x) as usize;
+        self.expand_perimeter(&mut rays, &mut perimeter, (x, y), origin);
+        let r2 = radius * radius;
+        let mut next = 0;
+        while next < perimeter.len() {
+            let ray = perimeter[next];
+            next += 1;
+            let (ray_x, ray_y) = (rays[ray].x, rays[ray].y);
+            let distance = if r2 > 0 {
+                ray_x * ray_x + ray_y * ray_y
+            } else {
+                0
+            };
+            if distance <= r2 {
+                self.merge_input(&mut rays, ray);
+                if !rays[ray].ignore {
+                    self.expand_perimeter(&mut rays, &mut perimeter, (x, y), ray);
+                }
+            } else {
+                rays[ray].ignore = true;
+            }
+        }
+        for (seen, ray) in self.in_fov.iter_mut().zip(&rays) {
+            *seen = ray.added && !ray.ignore && !ray.is_obscure();
+        }
+        self.see(x, y);
+        if light_walls {
+            let bounds = self.bounds(x, y, radius);
+            self.light_walls_behind(x, y, bounds);
+        }
+    }
+
+    // queue the rays one step further out from the given one
+    fn expand_perimeter(
+        &self,
+        rays: &mut [Ray],
+        perimeter: &mut Vec<usize>,
+        origin: (i32, i32),
+        from: usize,
+    ) {
+        let (x, y) = (rays[from].x, rays[from].y);
+        let mut steps = vec![];
+        if x >= 0 {
+            steps.push((x + 1, y));
+        }
+        if x <= 0 {
+            steps.push((x - 1, y));
+        }
+        if y >= 0 {
+            steps.push((x, y + 1));
+        }
+        if y <= 0 {
+            steps.push((x, y - 1));
+        }
+        for (ray_x, ray_y) in steps {
+            let Some(ray) = self.idx(origin.0 + ray_x, origin.1 + ray_y) else {
+                continue;
+            };
+            rays[ray].x = ray_x;
+            rays[ray].y = ray_y;
+            if ray_y == y {
+                rays[ray].x_input = Some(from);
+            } else {
+                rays[ray].y_input = Some(from);
+            }
+            if !rays[ray].added {
+                rays[ray].added = true;
+                perimeter.push(ray);
+            }
+        }
+    }
+
+    // how obscured a ray is, from the rays that feed it
+    fn merge_input(&self, rays: &mut [Ray], ray: usize) {
+        let x_input = rays[ray].x_input.map(|input| rays[input]);
+        let y_input = rays[ray].y_input.map(|input| rays[input]);
+        let r = &mut rays[ray];
+        if let Some(input) = x_input
+            && (input.obscurity_x != 0 || input.obscurity_y != 0)
+            && (input.error_x > 0 && r.obscurity_x == 0
+                || input.error_y <= 0 && input.obscurity_y > 0 && input.error_x > 0)
+        {
+            r.error_x = input.error_x - input.obscurity_y;
+            r.error_y = input.error_y + input.obscurity_y;
+            r.obscurity_x = input.obscurity_x;
+            r.obscurity_y = input.obscurity_y;
+        }
+        if let Some(input) = y_input
+            && (input.obscurity_x != 0 || input.obscurity_y != 0)
+            && (input.error_y > 0 && r.obscurity_y == 0
+                || input.error_x <= 0 && input.obscurity_x > 0 && input.error_y > 0)
+        {
+            r.error_y = input.error_y - input.obscurity_x;
+            r.error_x = input.error_x + input.obscurity_x;
+            r.obscurity_x = input.obscurity_x;
+            r.obscurity_y = input.obscurity_y;
+        }
+        r.ignore = match (x_input, y_input) {
+            (Some(x_input), Some(y_input)) => x_input.is_obscure() && y_input.is_obscure(),
+            (Some(input), None) | (None, Some(input)) => input.is_obscure(),
+            (None, None) => false,
+        };
+        if !r.ignore && !self.transparent[ray] {
+            // a wall starts a shadow of its own
+            r.error_x = r.x.abs();
+            r.obscurity_x = r.x.abs();
+            r.error_y = r.y.abs();
+            r.obscurity_y = r.y.abs();
+        }
+    }
+
+    fn shadow(&mut self, x: i32, y: i32, radius: i32, light_walls: bool) {
+        // no limit is as far as the furthest corner of the map
+        let radius = if radius > 0 {
+            radius
+        } else {
+            let (far_x, far_y) = ((self.width - x).max(x), (self.height - y).max(y));
+            ((far_x * far_x + far_y * far_y) as f64).sqrt() as i32 + 1
+        };
+        for octant in OCTANTS {
+            self.cast_light((x, y), radius, (1, 1.0, 0.0), octant, light_walls);
+        }
+    }
+
+    // light the cells of one octant from the given row on, between two slopes
+    fn cast_light(
+        &mut self,
+        (x, y): (i32, i32),
+        radius: i32,
+        (row, mut start, end): (i32, f32, f32),
+        [xx, xy, yx, yy]: [i32; 4],
+        light_walls: bool,
+    ) {
+        if start < end {
+            return;
+        }
+        let mut next_start = 0.0;
+        for distance in row..=radius {
+            let dy = -distance;
+            let mut blocked = false;
+            for dx in -distance..=0 {
+                let Some(idx) = self.idx(x + dx * xx + dy * xy, y + dx * yx + dy * yy) else {
+                    continue;
+                };
+                let left_slope = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+                let right_slope = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+                if start < right_slope {
+                    continue;
+                } else if end > left_slope {
+                    break;
+                }
+                let wall = !self.transparent[idx];
+                if dx * dx + dy * dy <= radius * radius && (light_walls || !wall) {
+                    self.in_fov[idx] = true;
+                }
+                if blocked {
+                    if wall {
+                        next_start = right_slope;
+                    } else {
+                        blocked = false;
+                        start = next_start;
+                    }
+                } else if wall && distance < radius {
+                    // the wall casts a shadow, and the view goes on past either side of it
+                    blocked = true;
+                    let rows = (distance + 1, start, left_slope);
+                    self.cast_light((x, y), radius, rows, [xx, xy, yx, yy], light_walls);
+                    next_start = right_slope;
+                }
+            }
+            if blocked {
+                break;
+            }
+        }
+    }
+
+    // one octant of the restrictive fov: the lines of cells going away from the viewer
+    // along y in the quadrant given by `(dx, dy)`, or along x if `by_column`. every cell
+    // of a line covers a range of angles, and a wall casts its range onto the lines
+    // behind it
+    fn restrictive(
+        &mut self,
+        (x, y): (i32, i32),
+        radius: i32,
+        light_walls: bool,
+        (dx, dy): (i32, i32),
+        by_column: bool,
+    ) {
+        // where the cells a step back towards the viewer are, straight back and
+        // diagonally. like libtcod, these are counted on the flat grid
+        let back = if by_column { -dx } else { -self.width * dy };
+        let back_diagonal = -self.width * dy - dx;
+        let (first, step, size) = if by_column {
+            (y, dy, self.height)
+        } else {
+            (x, dx, self.width)
+        };
+        let mut shadows: Vec<(f64, f64)> = vec![];
+        let mut shadows_before = 0;
+        let mut min_angle = 0.0;
+        let mut iteration = 1;
+        loop {
+            let (line, lines) = if by_column {
+                (x + dx * iteration, self.width)
+            } else {
+                (y + dy * iteration, self.height)
+            };
+            if line < 0 || line >= lines {
+                break;
+            }
+            let slopes_per_cell = 1.0 / iteration as f64;
+            let half_slopes = slopes_per_cell * 0.5;
+            let mut cell = ((min_angle + half_slopes) / slopes_per_cell) as i32;
+            let (min, max) = (
+                (first - iteration).max(0),
+                (first + iteration).min(size - 1),
+            );
+            let mut done = true;
+            let mut along = first + cell * step;
+            while along >= min && along <= max {
+                let (cell_x, cell_y) = if by_column {
+                    (line, along)
+                } else {
+                    (along, line)
+                };
+                let idx = (cell_y * self.width + cell_x) as usize;
+                let transparent = self.transparent[idx];
+                let center = cell as f64 * slopes_per_cell;
+                let (start_slope, end_slope) = (center - half_slopes, center + half_slopes);
+                let mut visible = true;
+                let mut extended = false;
+                if shadows_before > 0 {
+                    let seen_through = |offset: i32| {
+                        usize::try_from(idx as i32 + offset).is_ok_and(|i| {
+                            i < self.in_fov.len() && self.in_fov[i] && self.transparent[i]
+                        })
+                    };
+                    if !seen_through(back) && !seen_through(back_diagonal) {
+                        visible = false;
+                    } else {
+                        let mut shadow = 0;
+                        while shadow < shadows_before && visible {
+                            let (shadow_start, shadow_end) = shadows[shadow];
+                            if start_slope <= shadow_end && end_slope >= shadow_start {
+                                if transparent {
+                                    // an open cell is hidden once its center is
+                                    visible = center <= shadow_start || center >= shadow_end;
+                                } else if start_slope >= shadow_start && end_slope <= shadow_end {
+                                    // and a wall only when all of it is
+                                    visible = false;
+                                } else {
+                                    // a wall seen at the edge of a shadow makes it wider
+                                    shadows[shadow] =
+                                        (shadow_start.min(start_slope), shadow_end.max(end_slope));
+                                    extended = true;
+                                }
+                                // libtcod skips a shadow here along the columns, and so
+                                // does this, or the view wouldn't be the same
+                                if by_column {
+                                    shadow += 1;
+                                }
+                            }
+                            shadow += 1;
+                        }
+                    }
+                }
+                if visible {
+                    done = false;
+                    self.in_fov[idx] = true;
+                    if !transparent {
+                        if min_angle >= start_slope {
+                            min_angle = end_slope;
+                            // nothing is left to see on this line, nor past it
+                            if cell == iteration {
+                                done = true;
+                            }
+                        } else if !extended {
+                            shadows.push((start_slope, end_slope));
+                        }
+                        if !light_walls {
+                            self.in_fov[idx] = false;
+                        }
+                    }
+                }
+                cell += 1;
+                along += step;
+            }
+            if done || iteration == radius {
+                break;
+            }
+            iteration += 1;
+            shadows_before = shadows.len();
+        }
+    }
+}
+
+// a diamond fov ray, to one cell. obscurity is how far off the ray the wall shadowing it
+// is, and the errors how far into that shadow it is
+#[derive(Clone, Copy, Debug, Default)]
+struct Ray {
+    // where it goes, from the viewer
+    x: i32,
+    y: i32,
+    obscurity_x: i32,
+    obscurity_y: i32,
+    error_x: i32,
+    error_y: i32,
+    // the rays it carries on from, the one beside it along x and the one along y
+    x_input: Option<usize>,
+    y_input: Option<usize>,
+    // queued already
+    added: bool,
+    // can't be seen down, so not worth following
+    ignore: bool,
+}
+
+impl Ray {
+    fn is_obscure(&self) -> bool {
+        (self.error_x > 0 && self.error_x <= self.obscurity_x)
+            || (self.error_y > 0 && self.error_y <= self.obscurity_y)
+    }
+}
+
+// the four multipliers that turn the first octant into each of the eight
+const OCTANTS: [[i32; 4]; 8] = [
+    [1, 0, 0, 1],
+    [0, 1, 1, 0],
+    [0, -1, 1, 0],
+    [-1, 0, 0, 1],
+    [-1, 0, 0, -1],
+    [0, -1, -1, 0],
+    [0, 1, -1, 0],
+    [1, 0, 0, -1],
+];
+
+// permissive fov works in sixteenths of a cell, so lines can start off its center
+const STEP_SIZE: i32 = 16;
+
+// a line through two points, in sixteenths of a cell
+#[derive(Clone, Copy, Debug, Default)]
+struct ViewLine {
+    from: (i32, i32),
+    to: (i32, i32),
+}
+
+impl ViewLine {
+    // above 0 if the point is below the line, 0 if it is on it
+    fn relative_slope(&self, (x, y): (i32, i32)) -> i32 {
+        (self.to.1 - self.from.1) * (self.to.0 - x) - (self.to.0 - self.from.0) * (self.to.1 - y)
+    }
+
+    fn below(&self, point: (i32, i32)) -> bool {
+        self.relative_slope(point) > 0
+    }
+
+    fn above(&self, point: (i32, i32)) -> bool {
+        self.relative_slope(point) < 0
+    }
+
+    fn colinear(&self, point: (i32, i32)) -> bool {
+        self.relative_slope(point) == 0
+    }
+}
+
+// a corner a view's line had to bend around, and the one it bent around before that
+#[derive(Clone, Copy, Debug)]
+struct Bump {
+    point: (i32, i32),
+    parent: Option<usize>,
+}
+
+// the wedge between two lines that can still be seen down
+#[derive(Clone, Copy, Debug, Default)]
+struct View {
+    shallow: ViewLine,
+    steep: ViewLine,
+    shallow_bump: Option<usize>,
+    steep_bump: Option<usize>,
+}
+
+// jonathon duerig's precise permissive fov, one quadrant at a time. the views still
+// open are kept in order, from the shallowest to the steepest
+struct Permissive<'a> {
+    map: &'a mut FovMap,
+    origin: (i32, i32),
+    sign: (i32, i32),
+    light_walls: bool,
+    // the part of the viewer's cell the lines may start from
+    offset: i32,
+    limit: i32,
+    views: Vec<View>,
+    bumps: Vec<Bump>,
+    active: Vec<usize>,
+    current: usize,
+}
+
+impl Permissive<'_> {
+    fn compute(map: &mut FovMap, (x, y): (i32, i32), radius: i32, light_walls: bool, level: u8) {
+        let reach = |room: i32| if radius > 0 { room.min(radius) } else { room };
+        let (left, right) = (reach(x), reach(map.width - x - 1));
+        let (up, down) = (reach(y), reach(map.height - y - 1));
+        for (sign, extent) in [
+            ((1, 1), (right, down)),
+            ((1, -1), (right, up)),
+            ((-1, -1), (left, up)),
+            ((-1, 1), (left, down)),
+        ] {
+            let mut quadrant = Permissive {
+                map: &mut *map,
+                origin: (x, y),
+                sign,
+                light_walls,
+                offset: 8 - level as i32,
+                limit: 8 + level as i32,
+                views: vec![],
+                bumps: vec![],
+                active: vec![],
+                current: 0,
+            };
+            quadrant.check(extent);
+        }
+    }
+
+    fn check(&mut self, (extent_x, extent_y): (i32, i32)) {
+        self.views.push(View {
+            shallow: ViewLine {
+                from: (self.offset, self.limit),
+                to: (extent_x * STEP_SIZE, 0),
+            },
+            steep: ViewLine {
+                from: (self.limit, self.offset),
+                to: (0, extent_y * STEP_SIZE),
+            },
+            ..Default::default()
+        });
+        self.active.push(0);
+        // diagonal lines of cells, going outwards
+        for i in 1..=extent_x + extent_y {
+            if self.active.is_empty() {
+                break;
+            }
+            for j in (i - extent_x).max(0)..=i.min(extent_y) {
+                if self.active.is_empty() || self.current == self.active.len() {
+                    break;
+                }
+                self.visit(((i - j) * STEP_SIZE, j * STEP_SIZE));
+            }
+            self.current = 0;
+        }
+    }
+
+    fn visit(&mut self, (x, y): (i32, i32)) {
+        let top_left = (x, y + STEP_SIZE);
+        let bottom_right = (x + STEP_SIZE, y);
+        // skip the views that pass below the cell
+        while self.current < self.active.len() {
+            let view = &self.views[self.active[self.current]];
+            if view.steep.above(bottom_right) {
+                break;
+            }
+            self.current += 1;
+        }
+        if self.current == self.active.len() {
+            return;
+        }
+        let view_id = self.active[self.current];
+        let view = self.views[view_id];
+        if !view.shallow.below(top_left) || !self.is_blocked((x, y)) {
+            return;
+        }
+        if view.shallow.above(bottom_right) && view.steep.below(top_left) {
+            // the wall fills the whole view
+            self.active.remove(self.current);
+        } else if view.shallow.above(bottom_right) {
+            self.add_shallow_bump(top_left, view_id);
+            self.check_view(self.current);
+        } else if view.steep.below(top_left) {
+            self.add_steep_bump(bottom_right, view_id);
+            self.check_view(self.current);
+        } else {
+            // the wall is in the middle of the view and splits it in two
+            let view_index = self.current;
+            let shallower = self.views.len();
+            self.views.push(view);
+            self.active.insert(self.current, shallower);
+            let mut steeper = self.current + 1;
+            self.add_steep_bump(bottom_right, shallower);
+            if !self.check_view(self.current) {
+                steeper -= 1;
+            }
+            self.add_shallow_bump(top_left, self.active[steeper]);
+            self.check_view(steeper);
+            if view_index > self.active.len() {
+                self.current = self.active.len();
+            }
+        }
+    }
+
+    fn is_blocked(&mut self, (x, y): (i32, i32)) -> bool {
+        let cell_x = x * self.sign.0 / STEP_SIZE + self.origin.0;
+        let cell_y = y * self.sign.1 / STEP_SIZE + self.origin.1;
+        let blocked = !self.map.is_transparent(cell_x, cell_y);
+        if !blocked || self.light_walls {
+            self.map.see(cell_x, cell_y);
+        }
+        blocked
+    }
+
+    fn add_shallow_bump(&mut self, point: (i32, i32), view_id: usize) {
+        let view = &mut self.views[view_id];
+        view.shallow.to = point;
+        self.bumps.push(Bump {
+            point,
+            parent: view.shallow_bump,
+        });
+        view.shallow_bump = Some(self.bumps.len() - 1);
+        let mut bump = view.steep_bump;
+        while let Some(id) = bump {
+            if view.shallow.above(self.bumps[id].point) {
+                view.shallow.from = self.bumps[id].point;
+            }
+            bump = self.bumps[id].parent;
+        }
+    }
+
+    fn add_steep_bump(&mut self, point: (i32, i32), view_id: usize) {
+        let view = &mut self.views[view_id];
+        view.steep.to = point;
+        self.bumps.push(Bump {
+            point,
+            parent: view.steep_bump,
+        });
+        view.steep_bump = Some(self.bumps.len() - 1);
+        let mut bump = view.shallow_bump;
+        while let Some(id) = bump {
+            if view.steep.below(self.bumps[id].point) {
+                view.steep.from = self.bumps[id].point;
+            }
+            bump = self.bumps[id].parent;
+        }
+    }
+
+    // drop the view at the given place if it has closed down to a line through a corner
+    // of the part of the viewer's cell the lines start from. false if it was dropped
+    fn check_view(&mut self, at: usize) -> bool {
+        let View { shallow, steep, .. } = self.views[self.active[at]];
+        let closed = shallow.colinear(steep.from) && shallow.colinear(steep.to);
+        let through_corner = shallow.colinear((self.offset, self.limit))
+            || shallow.colinear((self.limit, self.offset));
+        if closed && through_corner {
+            self.active.remove(at);
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a small level with walls here and there, '#' for the walls
+    const MAP: [&str; 12] = [
+            "################",
+            "#..............#",
+            "#...#....#.....#",
+            "#...#....#..#..#",
+            "#........#.....#",
+            "#..##..........#",
+            "#......#.......#",
+            "##.#...#....####",
+            "#......#.......#",
+            "#...........#..#",
+            "#..#...........#",
+            "################",
+    ];
+
+    // what can be seen from (5, 4) on the map, drawn with '*' for the cells in view
+    fn view(algorithm: FovAlgorithm, radius: i32, light_walls: bool) -> Vec<String> {
+        let mut map = FovMap::new(16, 12);
+        for (y, row) in (0..).zip(MAP) {
+            for (x, c) in (0..).zip(row.chars()) {
+                map.set_transparent(x, y, c == '.');
+            }
+        }
+        map.compute_fov(5, 4, radius, light_walls, algorithm);
+        (0..12)
+            .zip(MAP)
+            .map(|(y, row)| {
+                let cells = (0..).zip(row.chars());
+                cells.map(|(x, c)| if map.is_in_fov(x, y) { '*' } else { c }).collect()
+            })
+            .collect()
+    }
+
+    // libtcod's own view of the map, with no radius and lit walls, then with a radius of
+    // 6 and unlit walls
+    #[test]
+    fn basic_matches_libtcod() {
+        let unlimited = [
+            "###*********####",
+            "*...*******....#",
+            "**..******.....#",
+            "**********..#..#",
+            "**********.....#",
+            "*************..#",
+            "*...************",
+            "##.*****.*******",
+            "#..*****...**..#",
+            "#..****.....****",
+            "#.******.......*",
+            "#********#######",
+        ];
+        assert_eq!(view(FovAlgorithm::Basic, 0, true), unlimited);
+        let radius = [
+            "################",
+            "#...*******....#",
+            "#*..#****#.....#",
+            "#***#****#..#..#",
+            "#********#.....#",
+            "#**##******....#",
+            "#...***#***....#",
+            "##.#***#.**.####",
+            "#..****#.......#",
+            "#..*****....#..#",
+            "#..#.*.........#",
+            "################",
+        ];
+        assert_eq!(view(FovAlgorithm::Basic, 6, false), radius);
+    }
+
+    // the same two views, by each of the other algorithms
+    #[test]
+    fn diamond_matches_libtcod() {
+        let unlimited = [
+            "**##********####",
+            "**...******....#",
+            "***.******.....#",
+            "**********..#..#",
+            "**********.....#",
+            "**.*************",
+            "*...************",
+            "##.*****.*******",
+            "#...****..****.#",
+            "#..****....**..#",
+            "#..*****.......#",
+            "###******#######",
+        ];
+        assert_eq!(view(FovAlgorithm::Diamond, 0, true), unlimited);
+        let radius = [
+            "################",
+            "#*...******....#",
+            "#**.#****#.....#",
+            "#***#****#..#..#",
+            "#********#.....#",
+            "#*.##******....#",
+            "#...***#***....#",
+            "##.#***#.**.####",
+            "#...***#.......#",
+            "#..****.....#..#",
+            "#..#.*.........#",
+            "################",
+        ];
+        assert_eq!(view(FovAlgorithm::Diamond, 6, false), radius);
+    }
+
+    #[test]
+    fn shadow_matches_libtcod() {
+        let unlimited = [
+            "####********####",
+            "#...*******....#",
+            "**..******.....#",
+            "**********..#..#",
+            "**********.....#",
+            "****************",
+            "#...************",
+            "##.#****.*******",
+            "#..*****...***.#",
+            "#..*****....****",
+            "#..*****......**",
+            "###******#######",
+        ];
+        assert_eq!(view(FovAlgorithm::Shadow, 0, true), unlimited);
+        let radius = [
+            "################",
+            "#...*******....#",
+            "#*..#****#.....#",
+            "#***#****#..#..#",
+            "#********#.....#",
+            "#**##******....#",
+            "#...***#***....#",
+            "##.#***#.**.####",
+            "#..****#.......#",
+            "#..*****....#..#",
+            "#..#.*.........#",
+            "################",
+        ];
+        assert_eq!(view(FovAlgorithm::Shadow, 6, false), radius);
+    }
+
+    #[test]
+    fn permissive_4_matches_libtcod() {
+        let unlimited = [
+            "####*******#####",
+            "#...*******....#",
+            "***.******.....#",
+            "**********..#..#",
+            "**********.....#",
+            "****************",
+            "#...************",
+            "##.*****.*******",
+            "#..*****..****.#",
+            "#.******...*****",
+            "#.******......**",
+            "##******########",
+        ];
+        assert_eq!(view(FovAlgorithm::Permissive(4), 0, true), unlimited);
+        let radius = [
+            "################",
+            "#...*******....#",
+            "#**.#****#.....#",
+            "#***#****#..#..#",
+            "#********#.....#",
+            "#**##*******...#",
+            "#...***#****...#",
+            "##.#***#.***####",
+            "#..****#..**...#",
+            "#.******...*#..#",
+            "#.*#****.......#",
+            "################",
+        ];
+        assert_eq!(view(FovAlgorithm::Permissive(4), 6, false), radius);
+    }
+
+    #[test]
+    fn permissive_8_matches_libtcod() {
+        let unlimited = [
+            "####*******#####",
+            "**..*******....#",
+            "***.******.....#",
+            "**********..#..#",
+            "**********.....#",
+            "****************",
+            "#...************",
+            "##.*****.*******",
+            "#..*****..****.#",
+            "#.******...*****",
+            "#.******......**",
+            "##******########",
+        ];
+        assert_eq!(view(FovAlgorithm::Permissive(8), 0, true), unlimited);
+        let radius = [
+            "################",
+            "#*..*******....#",
+            "#**.#****#.....#",
+            "#***#****#..#..#",
+            "#********#.....#",
+            "#**##*******...#",
+            "#...***#****...#",
+            "##.#***#.***####",
+            "#..****#..**...#",
+            "#.******...*#..#",
+            "#.*#****.......#",
+            "################",
+        ];
+        assert_eq!(view(FovAlgorithm::Permissive(8), 6, false), radius);
+    }
+
+    #[test]
+    fn restrictive_matches_libtcod() {
+        let unlimited = [
+            "####********####",
+            "#....******....#",
+            "**..******.....#",
+            "**********..#..#",
+            "**********.....#",
+            "**.*************",
+            "#...************",
+            "##.#****.*******",
+            "#..*****...***.#",
+            "#..****.....****",
+            "#.******.......*",
+            "##*******#######",
+        ];
+        assert_eq!(view(FovAlgorithm::Restrictive, 0, true), unlimited);
+        let radius = [
+            "################",
+            "#....******....#",
+            "#*..#****#.....#",
+            "#***#****#..#..#",
+            "#********#.....#",
+            "#*.##*******...#",
+            "#...***#****...#",
+            "##.#***#.***####",
+            "#..****#...*...#",
+            "#..****.....#..#",
+            "#.*#****.......#",
+            "################",
+        ];
+        assert_eq!(view(FovAlgorithm::Restrictive, 6, false), radius);
+    }
+
+    // an open room with a few pillars in it
+    fn pillared_map() -> FovMap {
+        let mut map = FovMap::new(16, 12);
+        for y in 0..12 {
+            for x in 0..16 {
+                map.set_transparent(x, y, true);
+            }
+        }
+        for (x, y) in [(5, 5), (8, 3), (9, 7), (3, 8), (11, 5), (6, 9), (12, 9), (4, 2)] {
+            map.set_transparent(x, y, false);
+        }
+        map
+    }
+
+    #[test]
+    fn pillars_cast_shadows() {
+        let mut map = pillared_map();
+        map.compute_fov(1, 5, 0, true, FovAlgorithm::Shadow);
+        assert!(map.is_in_fov(5, 5));
+        assert!(!map.is_in_fov(6, 5));
+        assert!(map.is_in_fov(15, 0));
+    }
+
+    #[test]
+    fn permissive_view_is_symmetric() {
+        let mut map = pillared_map();
+        let cells: Vec<_> = (0..12)
+            .flat_map(|y| (0..16).map(move |x| (x, y)))
+            .filter(|&(x, y)| map.is_transparent(x, y))
+            .collect();
+        // what each cell sees, in the order of the cells
+        let views: Vec<Vec<bool>> = cells
+            .iter()
+            .map(|&(x, y)| {
+                map.compute_fov(x, y, 0, false, FovAlgorithm::Permissive(8));
+                cells.iter().map(|&(x, y)| map.is_in_fov(x, y)).collect()
+            })
+            .collect();
+        for a in 0..cells.len() {
+            for b in 0..cells.len() {
+                assert_eq!(views[a][b], views[b][a], "{:?} and {:?}", cells[a], cells[b]);
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    CHARACTER_SCREEN_WIDTH, Game, GameState, PLAYER, Tcod, collect_gold,
+    compute_fov, debug_spawn_monster, drop_item, level_up_xp, next_level, pick_item_up,
+    previous_level, take_loot, throw_item, use_item,
+};
+use crate::backend::{Key, KeyCode};
+use crate::colors::*;
+use crate::keys::Action;
+use crate::object::{Object, player_move_or_attack};
+use crate::render::{confirm, inventory_menu, message_log, minimap, msgbox, render_all};
+
+// move a cursor over the map with the movement keys, and have the panel say what's
+// under it. leaving with Escape doesn't take a turn
+fn look(tcod: &mut Tcod, game: &mut Game, objects: &[Object]) {
+    let mut cursor = objects[PLAYER].pos();
+    while !tcod.backend.window_closed() {
+        tcod.look = Some(cursor);
+        tcod.con.clear();
+        render_all(tcod, game, objects, false);
+        tcod.backend.present(&tcod.screen);
+        let key = tcod.replay.wait_key(&mut *tcod.backend);
+        let action = tcod.key_bindings.action_for(key);
+        if key.code == KeyCode::Escape || action == Some(Action::Look) {
+            break;
+        }
+        if let Some((dx, dy)) = action.and_then(|action| action.direction()) {
+            cursor.0 = (cursor.0 + dx).clamp(0, game.map.width - 1);
+            cursor.1 = (cursor.1 + dy).clamp(0, game.map.height - 1);
+        }
+    }
+    tcod.look = None;
+}
+
+// result of handling one keypress
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayerAction {
+    // the player moved, attacked or otherwise spent their turn
+    TookTurn,
+    // nothing happened that the monsters should react to
+    DidntTakeTurn,
+    Exit,
+}
+
+pub fn handle_keys(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<Object>) -> PlayerAction {
+    use KeyCode::*;
+
+    // the window can go away while waiting for a key, and then there is nothing left to
+    // play on: leave right away, which still saves the game
+    if tcod.backend.window_closed() {
+        return PlayerAction::Exit;
+    }
+    let key = tcod.key;
+    if key.code == NoKey {
+        return PlayerAction::DidntTakeTurn;
+    }
+    // a dead player can still toggle fullscreen or quit, but not move
+    let playing = game.state == GameState::Playing;
+    let action = tcod.key_bindings.action_for(key);
+    match (key, action) {
+        (
+            Key {
+                code: Enter,
+                alt: true,
+                ..
+            },
+            _,
+        ) => {
+            // Alt+Enter: toggle fullscreen
+            tcod.backend.toggle_fullscreen();
+        }
+        // exit game, but not by accident in the middle of a run
+        (Key { code: Escape, .. }, _) if !playing || confirm("Save and quit?", tcod) => {
+            return PlayerAction::Exit;
+        }
+        (Key { code: Escape, .. }, _) => {}
+        (_, Some(Action::Screenshot)) => {
+            // save a screenshot, named after the current time so older ones are kept
+            let millis = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_millis());
+            let path = format!("screenshot_{}.png", millis);
+            match tcod.backend.save_screenshot(&path) {
+                Ok(()) => game
+                    .messages
+                    .add_message(format!("Screenshot saved to {}.", path), LIGHT_GREEN),
+                Err(err) => game
+                    .messages
+                    .add_message(format!("Could not save the screenshot: {}", err), RED),
+            }
+        }
+        // movement keys: walk, or attack or open whatever is in the way
+        (_, Some(action)) if playing && action.direction().is_some() => {
+            if let Some((dx, dy)) = action.direction() {
+                let (x, y) = (objects[PLAYER].x + dx, objects[PLAYER].y + dy);
+                if player_move_or_attack(dx, dy, game, objects) {
+                    // something like a door opening changes what can be seen from here
+                    if let Some(&tile) = game.map.get(x, y) {
+                        tcod.fov.set_transparent(x, y, !tile.block_sight);
+                    }
+                    let (player_x, player_y) = objects[PLAYER].pos();
+                    compute_fov(tcod, player_x, player_y);
+                }
+                collect_gold(game, objects);
+            }
+            return PlayerAction::TookTurn;
+        }
+        // rest in place: do nothing, but the monsters still get their turn
+        (_, Some(Action::Wait)) if playing => return PlayerAction::TookTurn,
+        (_, Some(Action::PickUp)) if playing => {
+            // pick up an item, or else whatever the remains under the player were carrying
+            let item_id = objects
+                .iter()
+                .position(|object| object.pos() == objects[PLAYER].pos() && object.item.is_some())
+                .or_else(|| take_loot(objects));
+            if let Some(item_id) = item_id {
+                return pick_item_up(item_id, game, objects);
+            }
+        }
+        (_, Some(Action::Descend)) if playing => {
+            // go down stairs, if the player is on them
+            let player_on_stairs = objects
+                .iter()
+                .any(|object| object.pos() == objects[PLAYER].pos() && object.name == "stairs");
+            if player_on_stairs && let Err(err) = next_level(tcod, game, objects) {
+                game.messages
+                    .add_message(format!("The way down is blocked: {}", err), RED);
+            }
+        }
+        (_, Some(Action::Ascend)) if playing => {
+            // go back up, if the player is on the stairs they came down
+            let player_on_stairs = objects.iter().any(|object| {
+                object.pos() == objects[PLAYER].pos() && object.name == "up stairs"
+            });
+            if player_on_stairs && let Err(err) = previous_level(tcod, game, objects) {
+                game.messages
+                    .add_message(format!("The way up is blocked: {}", err), RED);
+            }
+        }
+        (_, Some(Action::Inventory)) if playing => {
+            // show the inventory: if an item is selected, use it
+            let inventory_index = inventory_menu(
+                game,
+                &objects[PLAYER],
+                "Press the key next to an item to use it, or any other to cancel.\n",
+                tcod,
+            );
+            if let Some(inventory_index) = inventory_index {
+                return use_item(inventory_index, tcod, game, objects);
+            }
+        }
+        (_, Some(Action::Drop)) if playing => {
+            // show the inventory: if an item is selected, drop it
+            let inventory_index = inventory_menu(
+                game,
+                &objects[PLAYER],
+                "Press the key next to an item to drop it, or any other to cancel.\n",
+                tcod,
+            );
+            if let Some(inventory_index) = inventory_index {
+                return drop_item(inventory_index, game, objects);
+            }
+        }
+        (_, Some(Action::Throw)) if playing => {
+            // show the inventory: if an item is selected, throw it
+            let inventory_index = inventory_menu(
+                game,
+                &objects[PLAYER],
+                "Press the key next to a potion to throw it, or any other to cancel.\n",
+                tcod,
+            );
+            if let Some(inventory_index) = inventory_index {
+                return throw_item(inventory_index, tcod, game, objects);
+            }
+        }
+        (_, Some(Action::CharacterInfo)) if playing => {
+            // show character information, with the stats including equipment bonuses
+            let player = &objects[PLAYER];
+            if let Some(fighter) = player.fighter.as_ref() {
+                let mut msg = format!(
+                    "Character information
+
+Level: {}
+Experience: {}
+Experience to level up: {}
+
+Maximum HP: {}
+Attack: {}
+Defense: {}",
+                    player.level,
+                    fighter.xp,
+                    level_up_xp(player.level),
+                    player.max_hp(game),
+                    player.power(game),
+                    player.defense(game),
+                );
+                if game.has_amulet {
+                    msg.push_str("\n\nYou carry the royal amulet.");
+                }
+                msgbox(&msg, CHARACTER_SCREEN_WIDTH, tcod);
+            }
+        }
+        (_, Some(Action::Minimap)) if playing => minimap(tcod, game, &objects[PLAYER]),
+        (_, Some(Action::Look)) if playing => look(tcod, game, objects),
+        // the log stays open to the dead, to see what killed them
+        (_, Some(Action::MessageLog)) => message_log(tcod, game),
+        // the debug keys, which do nothing unless the game was started with --debug
+        (_, Some(Action::DebugGodMode)) if playing && tcod.debug => {
+            game.god_mode = !game.god_mode;
+            let state = if game.god_mode { "on" } else { "off" };
+            game.messages
+                .add_message(format!("God mode is {}.", state), LIGHT_VIOLET);
+        }
+        (_, Some(Action::DebugRevealMap)) if playing && tcod.debug => {
+            for tile in game.map.tiles.iter_mut() {
+                tile.explored = true;
+            }
+            game.messages
+                .add_message("The whole level is revealed.", LIGHT_VIOLET);
+        }
+        (_, Some(Action::DebugSpawnMonster)) if playing && tcod.debug => {
+            debug_spawn_monster(tcod, game, objects);
+        }
+        _ => {}
+    }
+    // menus, fullscreen and unknown keys give the monsters no free move
+    PlayerAction::DidntTakeTurn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{fighter, game, tcod};
+
+    fn press(code: KeyCode, tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<Object>) {
+        tcod.key = Key {
+            code,
+            ..Key::default()
+        };
+        handle_keys(tcod, game, objects);
+    }
+
+    #[test]
+    fn debug_keys_need_the_flag() {
+        let (mut tcod, mut game) = (tcod(), game());
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5)];
+        press(KeyCode::F9, &mut tcod, &mut game, &mut objects);
+        press(KeyCode::F10, &mut tcod, &mut game, &mut objects);
+        assert!(!game.god_mode);
+        assert!(game.map.tiles.iter().all(|tile| !tile.explored));
+    }
+
+    #[test]
+    fn god_mode_and_reveal_map() {
+        let (mut tcod, mut game) = (tcod(), game());
+        tcod.debug = true;
+        let troll = fighter("troll", 2, 1, 50, 5, 20);
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5), troll];
+        press(KeyCode::F9, &mut tcod, &mut game, &mut objects);
+        assert!(game.god_mode);
+        // the player can't be hurt, and kills anything in one hit
+        let (player, troll) = objects.split_at_mut(1);
+        troll[0].attack(&mut player[0], &mut game);
+        assert_eq!(player[0].fighter.unwrap().hp, 30);
+        player[0].attack(&mut troll[0], &mut game);
+        assert!(!troll[0].alive);
+        press(KeyCode::F10, &mut tcod, &mut game, &mut objects);
+        assert!(game.map.tiles.iter().all(|tile| tile.explored));
+    }
+}
@@ -0,0 +1,296 @@
+use std::fs;
+
+use crate::backend::{Key, KeyCode};
+
+// everything a key can be bound to. Escape (quit) and Alt+Enter (fullscreen) stay fixed,
+// so a broken key map can never lock the player in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    MoveNorth,
+    MoveSouth,
+    MoveWest,
+    MoveEast,
+    MoveNorthWest,
+    MoveNorthEast,
+    MoveSouthWest,
+    MoveSouthEast,
+    Wait,
+    PickUp,
+    Inventory,
+    Drop,
+    Throw,
+    Descend,
+    Ascend,
+    CharacterInfo,
+    Minimap,
+    MessageLog,
+    Look,
+    Screenshot,
+    // only with --debug
+    DebugGodMode,
+    DebugRevealMap,
+    DebugSpawnMonster,
+}
+
+impl Action {
+    const ALL: [Action; 23] = [
+        Action::MoveNorth,
+        Action::MoveSouth,
+        Action::MoveWest,
+        Action::MoveEast,
+        Action::MoveNorthWest,
+        Action::MoveNorthEast,
+        Action::MoveSouthWest,
+        Action::MoveSouthEast,
+        Action::Wait,
+        Action::PickUp,
+        Action::Inventory,
+        Action::Drop,
+        Action::Throw,
+        Action::Descend,
+        Action::Ascend,
+        Action::CharacterInfo,
+        Action::Minimap,
+        Action::MessageLog,
+        Action::Look,
+        Action::Screenshot,
+        Action::DebugGodMode,
+        Action::DebugRevealMap,
+        Action::DebugSpawnMonster,
+    ];
+
+    // the name used for the action in keys.toml
+    fn name(self) -> &'static str {
+        match self {
+            Action::MoveNorth => "move_north",
+            Action::MoveSouth => "move_south",
+            Action::MoveWest => "move_west",
+            Action::MoveEast => "move_east",
+            Action::MoveNorthWest => "move_north_west",
+            Action::MoveNorthEast => "move_north_east",
+            Action::MoveSouthWest => "move_south_west",
+            Action::MoveSouthEast => "move_south_east",
+            Action::Wait => "wait",
+            Action::PickUp => "pick_up",
+            Action::Inventory => "inventory",
+            Action::Drop => "drop",
+            Action::Throw => "throw",
+            Action::Descend => "descend",
+            Action::Ascend => "ascend",
+            Action::CharacterInfo => "character_info",
+            Action::Minimap => "minimap",
+            Action::MessageLog => "message_log",
+            Action::Look => "look",
+            Action::Screenshot => "screenshot",
+            Action::DebugGodMode => "debug_god_mode",
+            Action::DebugRevealMap => "debug_reveal_map",
+            Action::DebugSpawnMonster => "debug_spawn_monster",
+        }
+    }
+
+    // the step the player takes for a movement action
+    pub fn direction(self) -> Option<(i32, i32)> {
+        match self {
+            Action::MoveNorth => Some((0, -1)),
+            Action::MoveSouth => Some((0, 1)),
+            Action::MoveWest => Some((-1, 0)),
+            Action::MoveEast => Some((1, 0)),
+            Action::MoveNorthWest => Some((-1, -1)),
+            Action::MoveNorthEast => Some((1, -1)),
+            Action::MoveSouthWest => Some((-1, 1)),
+            Action::MoveSouthEast => Some((1, 1)),
+            _ => None,
+        }
+    }
+}
+
+// a single key: either a special key like an arrow, or a printable character, which
+// comes as text so that shifted characters like '>' work on any layout
+#[derive(Clone, Debug, PartialEq)]
+enum Binding {
+    Code(KeyCode),
+    Text(char),
+}
+
+// the names of the special keys, as used in keys.toml and in replay logs
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Insert", KeyCode::Insert),
+    ("Delete", KeyCode::Delete),
+    ("Backspace", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab),
+    ("Enter", KeyCode::Enter),
+    ("Escape", KeyCode::Escape),
+    ("Space", KeyCode::Spacebar),
+    ("NumPad0", KeyCode::NumPad0),
+    ("NumPad1", KeyCode::NumPad1),
+    ("NumPad2", KeyCode::NumPad2),
+    ("NumPad3", KeyCode::NumPad3),
+    ("NumPad4", KeyCode::NumPad4),
+    ("NumPad5", KeyCode::NumPad5),
+    ("NumPad6", KeyCode::NumPad6),
+    ("NumPad7", KeyCode::NumPad7),
+    ("NumPad8", KeyCode::NumPad8),
+    ("NumPad9", KeyCode::NumPad9),
+    ("F1", KeyCode::F1),
+    ("F2", KeyCode::F2),
+    ("F3", KeyCode::F3),
+    ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5),
+    ("F6", KeyCode::F6),
+    ("F7", KeyCode::F7),
+    ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9),
+    ("F10", KeyCode::F10),
+    ("F11", KeyCode::F11),
+    ("F12", KeyCode::F12),
+];
+
+pub fn key_code(name: &str) -> Option<KeyCode> {
+    KEY_NAMES.iter().find(|&&(known, _)| known == name).map(|&(_, code)| code)
+}
+
+pub fn key_name(code: KeyCode) -> Option<&'static str> {
+    KEY_NAMES.iter().find(|&&(_, known)| known == code).map(|&(name, _)| name)
+}
+
+impl Binding {
+    fn parse(name: &str) -> Result<Self, String> {
+        if let Some(code) = key_code(name) {
+            Ok(Binding::Code(code))
+        } else if name.chars().count() == 1 {
+            Ok(Binding::Text(name.chars().next().unwrap()))
+        } else {
+            Err(format!("unknown key \"{}\"", name))
+        }
+    }
+
+    fn matches(&self, key: Key) -> bool {
+        match self {
+            Binding::Code(code) => key.code == *code,
+            Binding::Text(c) => key.code == KeyCode::Text && key.printable == *c,
+        }
+    }
+}
+
+// which keys trigger which action. several keys can share an action, like the arrows,
+// numpad and vi-keys all moving the player
+#[derive(Clone, Debug)]
+pub struct KeyBindings {
+    bindings: Vec<(Action, Vec<Binding>)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let keys = |action| -> &[&str] {
+            match action {
+                Action::MoveNorth => &["Up", "NumPad8", "k"],
+                Action::MoveSouth => &["Down", "NumPad2", "j"],
+                Action::MoveWest => &["Left", "NumPad4", "h"],
+                Action::MoveEast => &["Right", "NumPad6", "l"],
+                Action::MoveNorthWest => &["NumPad7", "y"],
+                Action::MoveNorthEast => &["NumPad9", "u"],
+                Action::MoveSouthWest => &["NumPad1", "b"],
+                Action::MoveSouthEast => &["NumPad3", "n"],
+                Action::Wait => &["NumPad5", "."],
+                Action::PickUp => &["g"],
+                Action::Inventory => &["i"],
+                Action::Drop => &["d"],
+                Action::Throw => &["t"],
+                Action::Descend => &[">"],
+                Action::Ascend => &["<"],
+                Action::CharacterInfo => &["c"],
+                Action::Minimap => &["m"],
+                Action::MessageLog => &["p"],
+                Action::Look => &["x"],
+                Action::Screenshot => &["F12"],
+                Action::DebugGodMode => &["F9"],
+                Action::DebugRevealMap => &["F10"],
+                Action::DebugSpawnMonster => &["F11"],
+            }
+        };
+        let bindings = Action::ALL
+            .iter()
+            .map(|&action| {
+                let keys = keys(action).iter().map(|name| Binding::parse(name).unwrap());
+                (action, keys.collect())
+            })
+            .collect();
+        KeyBindings { bindings }
+    }
+}
+
+impl KeyBindings {
+    // read the key map from a file. only the actions listed in it are rebound, the
+    // rest keep their default keys. the file is a small subset of TOML, one action per
+    // line with a key or a list of keys:
+    //
+    //     move_north = ["Up", "NumPad8", "w"]
+    //     pick_up = "g"
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        let mut key_bindings = KeyBindings::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            key_bindings
+                .parse_line(line)
+                .map_err(|err| format!("{} line {}: {}", path, number + 1, err))?;
+        }
+        Ok(key_bindings)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| "expected `action = \"key\"`".to_string())?;
+        let name = name.trim();
+        let action = Action::ALL
+            .into_iter()
+            .find(|action| action.name() == name)
+            .ok_or_else(|| format!("unknown action \"{}\"", name))?;
+
+        let value = value.trim();
+        let list = value.strip_prefix('[').map(|rest| rest.strip_suffix(']'));
+        let keys: Vec<&str> = match list {
+            Some(Some(list)) => {
+                list.split(',').map(str::trim).filter(|key| !key.is_empty()).collect()
+            }
+            Some(None) => return Err("missing `]` at the end of the list".to_string()),
+            None => vec![value],
+        };
+        if keys.is_empty() {
+            return Err(format!("no keys for \"{}\"", name));
+        }
+
+        let mut bindings = vec![];
+        for key in keys {
+            let key = key
+                .strip_prefix('"')
+                .and_then(|key| key.strip_suffix('"'))
+                .ok_or_else(|| format!("expected a quoted key name, got {}", key))?;
+            bindings.push(Binding::parse(key)?);
+        }
+        if let Some(entry) = self.bindings.iter_mut().find(|(bound, _)| *bound == action) {
+            entry.1 = bindings;
+        }
+        Ok(())
+    }
+
+    // the action bound to a pressed key, if any
+    pub fn action_for(&self, key: Key) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(_, bindings)| bindings.iter().any(|binding| binding.matches(key)))
+            .map(|&(action, _)| action)
+    }
+}
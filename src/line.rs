@@ -0,0 +1,86 @@
+// the cells of a straight line between two cells, by Bresenham's algorithm the way
+// libtcod draws it: every cell after the start, up to and including the end
+#[derive(Clone, Debug)]
+pub struct Line {
+    x: i32,
+    y: i32,
+    to: (i32, i32),
+    // the doubled distances to go and the direction of each step
+    delta: (i32, i32),
+    step: (i32, i32),
+    error: i32,
+}
+
+impl Line {
+    pub fn new(from: (i32, i32), to: (i32, i32)) -> Self {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let step = (dx.signum(), dy.signum());
+        // the error starts at the distance along the longer axis
+        let error = dx.abs().max(dy.abs());
+        Line {
+            x: from.0,
+            y: from.1,
+            to,
+            delta: (dx * 2, dy * 2),
+            step,
+            error,
+        }
+    }
+}
+
+impl Iterator for Line {
+    type Item = (i32, i32);
+
+    fn next(&mut self) -> Option<(i32, i32)> {
+        let (step_x, step_y) = self.step;
+        let (delta_x, delta_y) = self.delta;
+        if step_x * delta_x > step_y * delta_y {
+            // mostly horizontal: a step along x every time, and along y now and then
+            if self.x == self.to.0 {
+                return None;
+            }
+            self.x += step_x;
+            self.error -= step_y * delta_y;
+            if self.error < 0 {
+                self.y += step_y;
+                self.error += step_x * delta_x;
+            }
+        } else {
+            if self.y == self.to.1 {
+                return None;
+            }
+            self.y += step_y;
+            self.error -= step_x * delta_x;
+            if self.error < 0 {
+                self.x += step_x;
+                self.error += step_y * delta_y;
+            }
+        }
+        Some((self.x, self.y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_match_libtcod_in_every_octant() {
+        // libtcod's cells for a line from the origin into each octant, mirrored
+        let cells = [(1, 0), (2, 1), (3, 1), (4, 2), (5, 2)];
+        for (sign_x, sign_y) in [(1, 1), (-1, 1), (1, -1), (-1, -1)] {
+            let flat: Vec<_> = cells.iter().map(|&(x, y)| (x * sign_x, y * sign_y)).collect();
+            let to = (5 * sign_x, 2 * sign_y);
+            assert_eq!(Line::new((0, 0), to).collect::<Vec<_>>(), flat, "to {:?}", to);
+            let steep: Vec<_> = cells.iter().map(|&(x, y)| (y * sign_x, x * sign_y)).collect();
+            let to = (2 * sign_x, 5 * sign_y);
+            assert_eq!(Line::new((0, 0), to).collect::<Vec<_>>(), steep, "to {:?}", to);
+        }
+    }
+
+    #[test]
+    fn lines_leave_out_the_start() {
+        assert_eq!(Line::new((1, 1), (4, 4)).collect::<Vec<_>>(), [(2, 2), (3, 3), (4, 4)]);
+        assert_eq!(Line::new((3, 3), (3, 3)).count(), 0);
+    }
+}
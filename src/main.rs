@@ -1,286 +1,2745 @@
-use std::collections::HashMap;
-use tcod::colors::*;
-use tcod::console::*;
 use std::cmp;
 use std::cmp::PartialEq;
-use rand::{random_range};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+mod astar;
+mod backend;
+mod colors;
+mod console;
+mod dijkstra;
+mod fov;
+mod input;
+mod keys;
+mod line;
+mod map;
+mod noise;
+mod object;
+mod render;
+mod replay;
+mod scent;
+mod sound;
+mod tileset;
+
+#[cfg(feature = "terminal")]
+use crate::backend::TerminalBackend;
+#[cfg(feature = "tcod")]
+use crate::backend::TcodBackend;
+use crate::backend::{Backend, Input, Key, Mouse};
+use crate::colors::*;
+use crate::console::*;
+use crate::dijkstra::DijkstraMap;
+use crate::fov::{FovAlgorithm, FovMap};
+use crate::input::{PlayerAction, handle_keys};
+use crate::keys::KeyBindings;
+use crate::map::{
+    Map, MapGenConfig, TileKind, Transition, Vault, item_labels, line_of_sight, load_vaults,
+    make_map, new_item, spawn_monster,
+};
+use crate::noise::Noise;
+use crate::object::{
+    Ability, Ai, DeathCallback, Fighter, Item, Object, Slot, Status, StatusKind, continue_ids,
+    index_of, is_blocked, move_astar, move_away, move_by, move_downhill, move_uphill, mut_two,
+    trigger_trap,
+};
+use crate::render::{
+    Camera, PaletteKind, Projectile, Step, animate_projectiles, animate_steps, arrow_glyph, menu,
+    msgbox, render_all,
+};
+use crate::replay::Replay;
+use crate::scent::ScentMap;
+use crate::sound::{Sound, Sounds};
+use crate::tileset::{TILESET_FILE, Tileset};
 
 // actual size of window
 const SCREEN_WIDTH: i32 = 80;
 const SCREEN_HEIGHT: i32 = 50;
-const LIMIT_FPS: i32 = 60; //20 frames per sec maximum
+// frames per second at most, while anything moves on the screen. the default for the option
+const LIMIT_FPS: i32 = 60;
 const MAP_WIDTH: i32 = 80;
-const MAP_HEIGHT: i32 = 45;
-const COLOR_DARK_WALL: Color = Color { r: 0, g: 0, b: 100 };
-const COLOR_DARK_GROUND: Color = Color {
-    r: 50,
-    g: 50,
-    b: 150,
-};
-const ROOM_MAX_SIZE: i32 = 10;
-const ROOM_MIN_SIZE: i32 = 6;
-const MAX_ROOMS: i32 = 30;
+const MAP_HEIGHT: i32 = 43;
+// the part of the screen showing the map; bigger maps scroll to follow the player
+const CAMERA_WIDTH: i32 = 80;
+const CAMERA_HEIGHT: i32 = 43;
+// sizes and coordinates relevant for the GUI
+const BAR_WIDTH: i32 = 20;
+const PANEL_HEIGHT: i32 = 7;
+const PANEL_Y: i32 = SCREEN_HEIGHT - PANEL_HEIGHT;
+const MSG_X: i32 = BAR_WIDTH + 2;
+const MSG_WIDTH: i32 = SCREEN_WIDTH - MSG_X - 1;
+const MSG_HEIGHT: usize = PANEL_HEIGHT as usize - 1;
+const INVENTORY_WIDTH: i32 = 50;
+// one inventory slot per letter, a-z
+const MAX_INVENTORY: usize = 26;
+// how much the player can carry: a base, and more for every point of strength
+const CARRY_BASE: i32 = 30;
+const CARRY_PER_POWER: i32 = 6;
+const HEAL_AMOUNT: i32 = 4;
+const REGEN_POTION_TURNS: i32 = 10;
+const REGEN_POTION_AMOUNT: i32 = 1;
+const LIGHTNING_DAMAGE: i32 = 40;
+const LIGHTNING_RANGE: i32 = 5;
+const FIREBALL_RADIUS: i32 = 3;
+const FIREBALL_DAMAGE: i32 = 12;
+const CONFUSE_RANGE: i32 = 8;
+const CONFUSE_NUM_TURNS: i32 = 10;
+// how far a potion can be thrown, and how far it splashes when it shatters
+const THROW_RANGE: i32 = 6;
+const SPLASH_RADIUS: i32 = 1;
+const FOV_LIGHT_WALLS: bool = true; // light walls or not, the default for the option
+const TORCH_RADIUS: i32 = 10;
+// cosmetic torch flicker: the lit area pulses a little, but what's in view never changes.
+// the default for the option. it keeps redrawing the screen even when nothing happens,
+// but only TORCH_FLICKER_FPS times a second, so an idle game hardly uses the CPU
+const TORCH_FLICKER: bool = true;
+const TORCH_FLICKER_SPEED: f32 = 3.0; // noise steps per second, independent of the fps
+const TORCH_FLICKER_FPS: u64 = 12; // a few frames per noise step look smooth enough
+const TORCH_FLICKER_RADIUS: f32 = 1.5; // how far the edge of the light wanders, in tiles
+const TORCH_FLICKER_BRIGHTNESS: f32 = 0.15; // how much dimmer the torch gets at its lowest
+// rubble further away than this blocks the view
+const RUBBLE_SIGHT_RANGE: f32 = 3.0;
+// fighters regain 1 hp every this many ticks (see Game), 0 turns regeneration off altogether
+const REGEN_INTERVAL: u32 = 10;
+// whether monsters the player can't see regenerate too
+const MONSTER_REGEN: bool = true;
+// the noise the player makes, which monsters out of sight follow
+const NOISE_MOVE: f32 = 4.0;
+const NOISE_FIGHT: f32 = 16.0;
+const NOISE_DECAY: f32 = 0.02; // share of the noise that fades away every turn
+const NOISE_DIFFUSION: f32 = 0.8; // how much of the way to its neighbours' noise a tile goes
+const NOISE_HEARING: f32 = 0.02; // anything quieter goes unnoticed
+// monsters see for themselves: this far, with nothing in between
+const MONSTER_SIGHT_RANGE: f32 = 8.0;
+// how many turns an awake monster keeps hunting once it loses sight of the player
+const MONSTER_ALERT_TURNS: i32 = 20;
+// how long the followers of a dead leader run before they fight on their own
+const SCATTER_TURNS: i32 = 5;
+// damage dealt to anything that ends its turn on lava
+const LAVA_DAMAGE: i32 = 3;
+// traps
+const SPIKE_TRAP_DAMAGE: i32 = 5;
+const TELEPORT_TRAP_TRIES: i32 = 100;
+// paths longer than this make monsters give up and just head straight at the target
+const MAX_ASTAR_PATH: usize = 25;
+// experience and level-ups
+const LEVEL_UP_BASE: i32 = 200;
+const LEVEL_UP_FACTOR: i32 = 150;
+const LEVEL_SCREEN_WIDTH: i32 = 40;
+const CHARACTER_SCREEN_WIDTH: i32 = 30;
+const CONFIRM_WIDTH: i32 = 30;
+// the most screen the minimap may cover; larger maps are shrunk until they fit
+const MINIMAP_MAX_WIDTH: i32 = 60;
+const MINIMAP_MAX_HEIGHT: i32 = 30;
+// player will always be the first object
+const PLAYER: usize = 0;
+// the amulet lies where the stairs would be on this level, so it's as deep as it gets
+const FINAL_DUNGEON_LEVEL: u32 = 10;
+
+// the golden ratio in 64 bits, which turns consecutive levels into unrelated seeds
+const LEVEL_SEED_MIX: u64 = 0x9E37_79B9_7F4A_7C15;
+#[cfg(not(test))]
+const SAVE_FILE: &str = "savegame";
+// the tests change levels and die too, which saves and deletes. that mustn't touch a real
+// game
+#[cfg(test)]
+const SAVE_FILE: &str = "target/test-savegame";
+const OPTIONS_FILE: &str = "options.json";
+// the chance for the player to lose a piece of gear that isn't worn, each turn in deep water
+const SINK_CHANCE: f32 = 0.2;
+// how hard the player hits in god mode, see Game
+const GOD_MODE_POWER: i32 = 1000;
+// the default time a projectile takes to fly, see Options
+const PROJECTILE_MS: u64 = 150;
+// and the time a step from one tile to the next takes to show
+const MOVE_MS: u64 = 80;
+const DEATH_SCREEN_WIDTH: i32 = 30;
+const VICTORY_SCREEN_WIDTH: i32 = 40;
+
+// tcod's Color isn't serializable, so save files store colors as (r, g, b) tuples
+mod color_rgb {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use crate::colors::Color;
+
+    pub fn serialize<S: Serializer>(color: &Color, serializer: S) -> Result<S::Ok, S::Error> {
+        (color.r, color.g, color.b).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+        let (r, g, b) = <(u8, u8, u8)>::deserialize(deserializer)?;
+        Ok(Color { r, g, b })
+    }
+}
+
+// same as `color_rgb`, for the (text, color) lines of the message log
+mod message_colors {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use crate::colors::Color;
+
+    pub fn serialize<S: Serializer>(
+        messages: &[(String, Color)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let rgb: Vec<_> = messages
+            .iter()
+            .map(|(text, color)| (text, (color.r, color.g, color.b)))
+            .collect();
+        rgb.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(String, Color)>, D::Error> {
+        let rgb = Vec::<(String, (u8, u8, u8))>::deserialize(deserializer)?;
+        Ok(rgb
+            .into_iter()
+            .map(|(text, (r, g, b))| (text, Color { r, g, b }))
+            .collect())
+    }
+}
 
 struct Tcod {
-    root: Root,
+    backend: Box<dyn Backend>,
+    // every frame is put together here, then handed to the backend
+    screen: Offscreen,
     con: Offscreen,
+    panel: Offscreen,
+    fov: FovMap,
+    key: Key,
+    mouse: Mouse,
+    camera: Camera,
+    key_bindings: KeyBindings,
+    // drives the torch flicker, along with the time since the game started
+    noise: Noise,
+    started: Instant,
+    sounds: Sounds,
+    // records the input, or plays it back
+    replay: Replay,
+    options: Options,
+    // which glyphs the window draws things with, plain ASCII unless tileset.json says
+    tileset: Tileset,
+    // started with --debug, which turns on the debug keys
+    debug: bool,
+    // the map tile under the cursor of look mode, while it's on
+    look: Option<(i32, i32)>,
+}
+
+// settings that belong to the player rather than to a game, kept in OPTIONS_FILE
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct Options {
+    #[serde(default)]
+    palette: PaletteKind,
+    // how long an arrow or spell takes to reach its target, 0 for no animation at all
+    #[serde(default = "default_projectile_ms")]
+    projectile_ms: u64,
+    // how long a step to the next tile takes to show, 0 for moving there at once
+    #[serde(default = "default_move_ms")]
+    move_ms: u64,
+    // the frame rate cap, 0 for none
+    #[serde(default = "default_fps")]
+    fps: i32,
+    // without the flicker the game sleeps until the next key, instead of drawing frames
+    #[serde(default = "default_flicker")]
+    flicker: bool,
+    // how the field of view is worked out. it decides what monsters see too, so a replay
+    // only plays out the same with the options it was recorded with
+    #[serde(default)]
+    fov: FovKind,
+    #[serde(default = "default_light_walls")]
+    light_walls: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            palette: PaletteKind::default(),
+            projectile_ms: default_projectile_ms(),
+            move_ms: default_move_ms(),
+            fps: default_fps(),
+            flicker: default_flicker(),
+            fov: FovKind::default(),
+            light_walls: default_light_walls(),
+        }
+    }
+}
+
+fn default_projectile_ms() -> u64 {
+    PROJECTILE_MS
 }
 
-#[derive(Clone, Copy, Debug)]
-struct Tile {
-    blocked: bool,
-    block_sight: bool,
+fn default_move_ms() -> u64 {
+    MOVE_MS
 }
 
-impl Tile {
-    pub fn empty() -> Self {
-        Tile {
-            blocked: false,
-            block_sight: false,
+fn default_fps() -> i32 {
+    LIMIT_FPS
+}
+
+fn default_flicker() -> bool {
+    TORCH_FLICKER
+}
+
+fn default_light_walls() -> bool {
+    FOV_LIGHT_WALLS
+}
+
+// libtcod's FOV algorithms, as named in the options. the permissive one comes with how
+// permissive it is, from 0 to 8, like `"fov": {"permissive": 4}`. only permissive 8 is
+// symmetric, with the rest a monster can now and then see the player without being seen
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum FovKind {
+    Basic,
+    Diamond,
+    #[default]
+    Shadow,
+    Permissive(u8),
+    Restrictive,
+}
+
+impl FovKind {
+    fn algorithm(self) -> FovAlgorithm {
+        match self {
+            FovKind::Basic => FovAlgorithm::Basic,
+            FovKind::Diamond => FovAlgorithm::Diamond,
+            FovKind::Shadow => FovAlgorithm::Shadow,
+            // anything past 8 is as permissive as it gets
+            FovKind::Permissive(n) => FovAlgorithm::Permissive(n.min(8)),
+            FovKind::Restrictive => FovAlgorithm::Restrictive,
         }
     }
-    pub fn wall() -> Self {
-        Tile {
-            blocked: true,
-            block_sight: true,
+}
+
+// the player's field of view from the given spot, by the algorithm in the options
+fn compute_fov(tcod: &mut Tcod, x: i32, y: i32) {
+    let Options {
+        fov, light_walls, ..
+    } = tcod.options;
+    tcod.fov.compute_fov(x, y, TORCH_RADIUS, light_walls, fov.algorithm());
+}
+
+// whether the player can still act
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum GameState {
+    Playing,
+    Dead,
+    // the player used the amulet
+    Won,
+}
+
+// the game message log, already wrapped to the panel width
+#[derive(Serialize, Deserialize)]
+struct Messages {
+    #[serde(with = "message_colors")]
+    messages: Vec<(String, Color)>,
+    // every message of the game as it was added, for the message log screen
+    #[serde(default, with = "message_colors")]
+    history: Vec<(String, Color)>,
+}
+
+impl Messages {
+    pub fn new() -> Self {
+        Self {
+            messages: vec![],
+            history: vec![],
+        }
+    }
+    // add the new message as a tuple, with the text and the color
+    pub fn add_message<T: Into<String>>(&mut self, message: T, color: Color) {
+        let message = message.into();
+        // split the message if necessary, among multiple lines
+        for line in wrap_text(&message, MSG_WIDTH as usize) {
+            self.messages.push((line, color));
+        }
+        self.history.push((message, color));
+        // if the buffer is full, remove the oldest lines to make room for the new ones
+        if self.messages.len() > MSG_HEIGHT {
+            let excess = self.messages.len() - MSG_HEIGHT;
+            self.messages.drain(..excess);
         }
     }
+    // iterate the messages from oldest to most recent
+    pub fn iter(&self) -> impl Iterator<Item = &(String, Color)> {
+        self.messages.iter()
+    }
+    // all the messages so far, oldest first and not wrapped
+    pub fn history(&self) -> &[(String, Color)] {
+        &self.history
+    }
 }
 
-type Map = Vec<Vec<Tile>>;
+// break text into lines of at most `width` characters, on word boundaries where possible
+fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word.to_string();
+        // words longer than a whole line get hard-split
+        while word.chars().count() > width {
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            let split_at = word.char_indices().nth(width).map_or(word.len(), |(i, _)| i);
+            let rest = word.split_off(split_at);
+            lines.push(word);
+            word = rest;
+        }
+        let needed = if line.is_empty() { 0 } else { line.chars().count() + 1 };
+        if needed + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
 
+// a kind of monster, as defined in monsters.json
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MonsterDef {
+    glyph: char,
+    #[serde(with = "color_rgb")]
+    color: Color,
+    hp: i32,
+    power: i32,
+    defense: i32,
+    // experience the player gets for killing it
+    xp: i32,
+    // relative chance of this monster being picked when spawning, by dungeon level
+    weight: Vec<Transition>,
+    // a status its hits leave behind, if any
+    #[serde(default)]
+    on_hit: Option<Status>,
+    #[serde(default)]
+    ability: Option<Ability>,
+    // how far it shoots, for monsters with a ranged attack
+    #[serde(default)]
+    range: Option<i32>,
+    // can't swim, so it stays out of deep water
+    #[serde(default)]
+    land_only: bool,
+    // makes it the leader of a pack, which spawns around it
+    #[serde(default)]
+    pack: Option<Pack>,
+}
+
+// the followers a leader spawns with, in the same room
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Pack {
+    // the kinds of monster in it, picked at random for each follower. a kind listed
+    // twice is twice as likely
+    followers: Vec<String>,
+    // how many followers there are, at least and at most
+    min: i32,
+    max: i32,
+}
+
+// monster definitions by name
+type MonsterDefs = HashMap<String, MonsterDef>;
+
+fn load_monster_defs(path: &str) -> Result<MonsterDefs, Box<dyn Error>> {
+    let mut json = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut json))
+        .map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let defs: MonsterDefs =
+        serde_json::from_str(&json).map_err(|e| format!("Malformed {}: {}", path, e))?;
+
+    if defs.is_empty() {
+        return Err(format!("{} does not define any monsters", path).into());
+    }
+    for (name, def) in &defs {
+        if def.hp <= 0 {
+            return Err(format!("{}: monster \"{}\" needs a positive hp", path, name).into());
+        }
+        if def.weight.iter().all(|transition| transition.value == 0) {
+            return Err(format!("{}: monster \"{}\" needs a positive weight", path, name).into());
+        }
+        // shooting from right next to the player would just be a melee attack
+        if def.range.is_some_and(|range| range < 2) {
+            let err = format!("{}: monster \"{}\" needs a range of at least 2", path, name);
+            return Err(err.into());
+        }
+        let bad_ability = match def.ability {
+            Some(Ability::Split { min_damage }) => min_damage < 1,
+            Some(Ability::Drain { percent }) => !(1..=100).contains(&percent),
+            None => false,
+        };
+        if bad_ability {
+            let err = format!("{}: monster \"{}\" has a broken ability", path, name);
+            return Err(err.into());
+        }
+        if let Some(pack) = &def.pack {
+            if pack.followers.is_empty() || pack.min < 0 || pack.min > pack.max {
+                let err = format!("{}: the pack of \"{}\" has no followers or sizes", path, name);
+                return Err(err.into());
+            }
+            if let Some(unknown) = pack.followers.iter().find(|kind| !defs.contains_key(*kind)) {
+                let err = format!("{}: \"{}\" leads unknown monster \"{}\"", path, name, unknown);
+                return Err(err.into());
+            }
+        }
+    }
+    Ok(defs)
+}
+
+// how hard a game is, picked when it starts. everything it changes goes through the
+// multipliers here
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+    // the name used in menus and replay logs
+    fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+
+    // how many monsters a room gets, next to normal
+    fn monster_count(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.5,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.5,
+        }
+    }
+
+    fn monster_hp(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.75,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.25,
+        }
+    }
+
+    fn monster_power(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.8,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.25,
+        }
+    }
+
+    // how many turns the player waits for each hp regained
+    fn regen_interval(self) -> u32 {
+        match self {
+            Difficulty::Easy => REGEN_INTERVAL / 2,
+            Difficulty::Normal => REGEN_INTERVAL,
+            Difficulty::Hard => REGEN_INTERVAL * 2,
+        }
+    }
+}
+
+// a value times one of the difficulty multipliers, never below 1 if it was positive
+fn scale(value: i32, multiplier: f32) -> i32 {
+    let scaled = (value as f32 * multiplier).round() as i32;
+    if value > 0 { scaled.max(1) } else { scaled }
+}
+
+// a level the player has left, kept as it was for when they come back
+#[derive(Serialize, Deserialize)]
+struct Level {
+    map: Map,
+    // everything on the level but the player
+    objects: Vec<Object>,
+}
+
+#[derive(Serialize, Deserialize)]
 struct Game {
     map: Map,
+    // how the next levels get generated
+    map_config: MapGenConfig,
+    messages: Messages,
+    inventory: Vec<Object>,
+    dungeon_level: u32,
+    // the levels visited before, by depth. the one being played is in `map` and the
+    // objects instead, so it's never in here
+    #[serde(default)]
+    levels: HashMap<u32, Level>,
+    // the seed all levels of this game are generated from
+    seed: u64,
+    // picked when the game started
+    #[serde(default)]
+    difficulty: Difficulty,
+    state: GameState,
+    // picked up the amulet, and still carrying it
+    has_amulet: bool,
+    // turns the player has taken since the game started, which replays go by
+    turn: u32,
+    // rounds the monsters have had, the clock regeneration and slowness go by. it runs
+    // ahead of the turns, as a slow step of the player's gives them more than one
+    #[serde(default)]
+    tick: u32,
+    // what the potions and scrolls look like this game, and the kinds used so far
+    #[serde(default)]
+    item_labels: Vec<(Item, String)>,
+    #[serde(default)]
+    identified: Vec<Item>,
+    // the dice rolled during play, like where a confused monster stumbles to. seeded
+    // from the game seed, so that a replay rolls the same
+    #[serde(skip, default = "StdRng::from_os_rng")]
+    rng: StdRng,
+    // loaded from monsters.json at startup, not part of the save
+    #[serde(skip)]
+    monster_defs: MonsterDefs,
+    // loaded from vaults.txt at startup, not part of the save either
+    #[serde(skip)]
+    vaults: Vec<Vault>,
+    // how far each tile is from the player, for monsters to follow. rebuilt when needed
+    #[serde(skip)]
+    player_distances: DijkstraMap,
+    // how loud it is around the level, spread a little every turn
+    #[serde(skip)]
+    scent: ScentMap,
+    // sounds for the main loop to play, like the messages for the log
+    #[serde(skip)]
+    sounds: Vec<Sound>,
+    // and arrows and spells for it to show flying
+    #[serde(skip)]
+    projectiles: Vec<Projectile>,
+    // monsters that split off from one being hit, for the main loop to put on the map
+    #[serde(skip)]
+    offspring: Vec<Object>,
+    // a debug cheat: the player can't be hurt, and kills anything in one hit
+    #[serde(skip)]
+    god_mode: bool,
+}
+
+fn ai_take_turn(monster_id: usize, game: &mut Game, objects: &mut [Object]) {
+    let previous_position = objects[monster_id].pos();
+    if let Some(ai) = objects[monster_id].ai.take() {
+        let new_ai = run_ai(monster_id, game, objects, ai);
+        // a monster that died during its own turn stays a corpse without AI
+        if objects[monster_id].alive {
+            objects[monster_id].ai = Some(new_ai);
+        }
+        // monsters set off traps just like the player
+        if objects[monster_id].alive && objects[monster_id].pos() != previous_position {
+            trigger_trap(monster_id, game, objects);
+        }
+    }
+}
+
+fn run_ai(monster_id: usize, game: &mut Game, objects: &mut [Object], ai: Ai) -> Ai {
+    use Ai::*;
+    match ai {
+        Asleep { awake_ai } => ai_asleep(monster_id, game, objects, awake_ai),
+        Basic { alert } => match hunt(monster_id, game, objects, alert) {
+            Some(alert) => Basic { alert },
+            None => doze_off(Basic {
+                alert: MONSTER_ALERT_TURNS,
+            }),
+        },
+        Ranged { range, alert } => ai_ranged(monster_id, game, objects, range, alert),
+        Follower { leader, awake_ai } => ai_follower(monster_id, game, objects, leader, awake_ai),
+        Fleeing { turns, then } => ai_fleeing(monster_id, game, objects, turns, then),
+        Confused {
+            previous_ai,
+            num_turns,
+        } => ai_confused(monster_id, game, objects, previous_ai, num_turns),
+    }
+}
+
+fn doze_off(awake_ai: Ai) -> Ai {
+    Ai::Asleep {
+        awake_ai: Box::new(awake_ai),
+    }
 }
 
-// rectangle on the map, used to characterize a room.
-#[derive(Clone, Copy, Debug)]
-struct Rect {
-    x1: i32,
-    y1: i32,
-    x2: i32,
-    y2: i32,
+// whether the monster can see the player, by its own eyes rather than the player's FOV
+fn monster_sees_player(monster_id: usize, map: &Map, objects: &[Object]) -> bool {
+    let monster = &objects[monster_id];
+    let player = &objects[PLAYER];
+    monster.distance_to(player) <= MONSTER_SIGHT_RANGE
+        && line_of_sight(map, monster.x, monster.y, player.x, player.y)
 }
-impl PartialEq for Rect {
-    fn eq(&self, other: &Self) -> bool {
-        (self.x1 == other.x2)
-            && (self.x2 == other.x1)
-            && (self.y1 == other.y2)
-            && (self.y2 == other.y1)
+
+fn ai_asleep(monster_id: usize, game: &mut Game, objects: &mut [Object], awake_ai: Box<Ai>) -> Ai {
+    if !monster_sees_player(monster_id, &game.map, objects) {
+        return Ai::Asleep { awake_ai };
     }
+    game.messages
+        .add_message(format!("The {} notices you!", objects[monster_id].name), ORANGE);
+    // it starts hunting right away, rather than giving the player a free turn
+    run_ai(monster_id, game, objects, *awake_ai)
 }
-impl Rect {
-    pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
-        Rect {
-            x1: x,
-            y1: y,
-            x2: x + w,
-            y2: y + h,
+
+// a basic monster takes its turn. If it can see you, it comes for you. returns how much
+// longer it stays alert, or None once it gives up and falls asleep
+fn hunt(monster_id: usize, game: &mut Game, objects: &mut [Object], alert: i32) -> Option<i32> {
+    let (monster_x, monster_y) = objects[monster_id].pos();
+    if monster_sees_player(monster_id, &game.map, objects) {
+        if objects[monster_id].distance_to(&objects[PLAYER]) >= 2.0 {
+            // move towards player if far away, falling back to A* where the distance
+            // map has no route (e.g. behind a closed door)
+            if game.player_distances.get(monster_x, monster_y).is_some() {
+                move_downhill(monster_id, &game.player_distances, &game.map, objects);
+            } else {
+                move_astar(monster_id, PLAYER, &game.map, objects);
+            }
+        } else if player_alive(objects) {
+            // close enough, attack! (if the player is still alive.)
+            let (monster, player) = mut_two(monster_id, PLAYER, objects);
+            monster.attack(player, game);
         }
+        return Some(MONSTER_ALERT_TURNS);
     }
-    pub fn center(&self) -> (i32, i32) {
-        let center_x = (self.x1 + self.x2) / 2;
-        let center_y = (self.y1 + self.y2) / 2;
-        (center_x, center_y)
+    if alert <= 0 {
+        return None;
     }
-    pub fn intersects_with(&self, other: &Rect) -> bool {
-        // returns true if this rectangle intersects with another one
-        (self.x1 <= other.x2)
-        && (self.x2 >= other.x1)
-        && (self.y1 <= other.y2)
-        && (self.y2 >= other.y1)
+    if game.scent.get(monster_x, monster_y) >= NOISE_HEARING {
+        // out of sight, but the player can be heard: go and see what the noise was
+        move_uphill(monster_id, &game.scent, &game.map, objects);
     }
+    Some(alert - 1)
 }
 
-#[derive(Debug)]
-struct Object {
-    x: i32,
-    y: i32,
-    char: char,
-    color: Color,
+// shoot when the player is in sight and range but not adjacent, otherwise close in or
+// fight up close like any other monster
+fn ai_ranged(
+    monster_id: usize,
+    game: &mut Game,
+    objects: &mut [Object],
+    range: i32,
+    alert: i32,
+) -> Ai {
+    let distance = objects[monster_id].distance_to(&objects[PLAYER]);
+    let alert = if distance >= 2.0
+        && distance <= range as f32
+        && monster_sees_player(monster_id, &game.map, objects)
+    {
+        if player_alive(objects) {
+            let (from, to) = (objects[monster_id].pos(), objects[PLAYER].pos());
+            game.projectiles.push(Projectile {
+                from,
+                to,
+                glyph: arrow_glyph(from, to),
+                color: WHITE,
+            });
+            let (monster, player) = mut_two(monster_id, PLAYER, objects);
+            monster.shoot(player, game);
+        }
+        Some(MONSTER_ALERT_TURNS)
+    } else {
+        hunt(monster_id, game, objects, alert)
+    };
+    match alert {
+        Some(alert) => Ai::Ranged { range, alert },
+        None => doze_off(Ai::Ranged {
+            range,
+            alert: MONSTER_ALERT_TURNS,
+        }),
+    }
 }
-impl Object {
-    pub fn new(x: i32, y: i32, char: char, color: Color) -> Self {
-        Object { x, y, char, color }
+
+// a pack follower keeps close to its leader, unless the player is nearer. when the leader
+// dies, the pack scatters
+fn ai_follower(
+    monster_id: usize,
+    game: &mut Game,
+    objects: &mut [Object],
+    leader: u32,
+    awake_ai: Box<Ai>,
+) -> Ai {
+    let Some(leader_id) = index_of(leader, objects).filter(|&id| objects[id].alive) else {
+        if monster_sees_player(monster_id, &game.map, objects) {
+            let name = &objects[monster_id].name;
+            game.messages
+                .add_message(format!("With its leader dead, the {} flees!", name), ORANGE);
+        }
+        // it doesn't need the pack to know how to fight, once it stops running
+        let then = match *awake_ai {
+            Ai::Asleep { awake_ai } => awake_ai,
+            awake_ai => Box::new(awake_ai),
+        };
+        return ai_fleeing(monster_id, game, objects, SCATTER_TURNS, then);
+    };
+    let monster = &objects[monster_id];
+    let to_leader = monster.distance_to(&objects[leader_id]);
+    let player_nearer = monster.distance_to(&objects[PLAYER]) < to_leader;
+    let leader_hunts = !matches!(objects[leader_id].ai, Some(Ai::Asleep { .. }));
+    // with the leader on the hunt the pack rallies, and goes for the player on sight.
+    // otherwise the player has to be the nearer one
+    if (leader_hunts || player_nearer) && monster_sees_player(monster_id, &game.map, objects) {
+        let awake_ai = Box::new(run_ai(monster_id, game, objects, *awake_ai));
+        return Ai::Follower { leader, awake_ai };
     }
-    // move by the given amount
-    pub fn move_by(&mut self, dx: i32, dy: i32, game: &Game) {
-        if !game.map[(self.x + dx) as usize][(self.y + dy) as usize].blocked {
-            self.x += dx;
-            self.y += dy;
+    if to_leader >= 2.0 {
+        move_astar(monster_id, leader_id, &game.map, objects);
+    }
+    Ai::Follower { leader, awake_ai }
+}
+
+// run from the player, then get back to what it was doing
+fn ai_fleeing(
+    monster_id: usize,
+    game: &mut Game,
+    objects: &mut [Object],
+    turns: i32,
+    then: Box<Ai>,
+) -> Ai {
+    if turns <= 0 {
+        return *then;
+    }
+    move_away(monster_id, &game.player_distances, &game.map, objects);
+    Ai::Fleeing {
+        turns: turns - 1,
+        then,
+    }
+}
+
+fn player_alive(objects: &[Object]) -> bool {
+    objects[PLAYER].alive && objects[PLAYER].fighter.is_some_and(|f| f.hp > 0)
+}
+
+fn ai_confused(
+    monster_id: usize,
+    game: &mut Game,
+    objects: &mut [Object],
+    previous_ai: Box<Ai>,
+    num_turns: i32,
+) -> Ai {
+    if num_turns >= 0 {
+        // still confused ...
+        // move in a random direction, and decrease the number of turns confused
+        move_by(
+            monster_id,
+            game.rng.random_range(-1..=1),
+            game.rng.random_range(-1..=1),
+            &game.map,
+            objects,
+        );
+        Ai::Confused {
+            previous_ai,
+            num_turns: num_turns - 1,
         }
+    } else {
+        // restore the previous AI (this one will be deleted)
+        game.messages.add_message(
+            format!("The {} is no longer confused!", objects[monster_id].name),
+            RED,
+        );
+        *previous_ai
     }
-    // set the color and then draw the character that represents this object at its position
-    pub fn draw(&self, con: &mut dyn Console) {
-        con.set_default_foreground(self.color);
-        con.put_char(self.x, self.y, self.char, BackgroundFlag::None);
+}
+
+// find closest enemy, up to a maximum range, in the player's FOV and with nothing in the
+// way of a spell
+fn closest_monster(tcod: &Tcod, map: &Map, objects: &[Object], max_range: i32) -> Option<usize> {
+    let player = &objects[PLAYER];
+    let mut closest_enemy = None;
+    let mut closest_dist = (max_range + 1) as f32; // start with (slightly more than) maximum range
+
+    for (id, object) in objects.iter().enumerate() {
+        if (id != PLAYER)
+            && object.fighter.is_some()
+            && object.ai.is_some()
+            && tcod.fov.is_in_fov(object.x, object.y)
+            && line_of_sight(map, player.x, player.y, object.x, object.y)
+        {
+            // calculate distance between this object and the player
+            let dist = player.distance_to(object);
+            if dist < closest_dist {
+                // it's closer, so remember it
+                closest_enemy = Some(id);
+                closest_dist = dist;
+            }
+        }
     }
+    closest_enemy
+}
+
+// the weight the player can carry. it goes by strength alone, a sword doesn't help
+fn carry_capacity(player: &Object) -> i32 {
+    CARRY_BASE + CARRY_PER_POWER * player.fighter.map_or(0, |fighter| fighter.base_power)
 }
 
-struct Objects {
-    player: Object,
-    npcs: HashMap<String, Object>,
+// the weight of the whole inventory, worn or not
+fn carried_weight(game: &Game) -> i32 {
+    game.inventory.iter().map(Object::weight).sum()
 }
-impl Objects {
-    pub fn new(player: Object, npcs: HashMap<String, Object>) -> Self {
-        Objects { player, npcs }
+
+// add to the player's inventory and remove from the map
+fn pick_item_up(object_id: usize, game: &mut Game, objects: &mut Vec<Object>) -> PlayerAction {
+    if carried_weight(game) + objects[object_id].weight() > carry_capacity(&objects[PLAYER]) {
+        game.messages.add_message("It's too heavy to carry.", RED);
+        return PlayerAction::DidntTakeTurn;
     }
-    pub fn draw_all(&self, con: &mut dyn Console) {
-        for npc in self.npcs.values() {
-            npc.draw(con);
+    // an item that stacks goes onto the stack of its kind, which fits even when full
+    let stack = objects[object_id].item.filter(|item| item.stacks()).and_then(|item| {
+        game.inventory
+            .iter()
+            .position(|carried| carried.item == Some(item))
+    });
+    if let Some(stack) = stack {
+        let item = objects.swap_remove(object_id);
+        game.messages
+            .add_message(format!("You picked up a {}!", item.stack_name(game)), GREEN);
+        game.sounds.push(Sound::Pickup);
+        game.inventory[stack].count += item.count;
+        PlayerAction::TookTurn
+    } else if game.inventory.len() >= MAX_INVENTORY {
+        game.messages.add_message(
+            format!(
+                "Your inventory is full, cannot pick up {}.",
+                objects[object_id].display_name(game)
+            ),
+            RED,
+        );
+        PlayerAction::DidntTakeTurn
+    } else {
+        let item = objects.swap_remove(object_id);
+        game.messages
+            .add_message(format!("You picked up a {}!", item.stack_name(game)), GREEN);
+        game.sounds.push(Sound::Pickup);
+        if item.item == Some(Item::Amulet) {
+            game.has_amulet = true;
+            game.messages
+                .add_message("Use it to leave the tombs, and win!", GOLD);
         }
-        self.player.draw(con);
+        game.inventory.push(item);
+        PlayerAction::TookTurn
     }
 }
 
-fn handle_keys(tcod: &mut Tcod, game: &Game, player: &mut Object) -> bool {
-    use tcod::input::Key;
-    use tcod::input::KeyCode::*;
+// take the loot from the remains under the player and put it on the floor as a normal
+// item, returning its id
+fn take_loot(objects: &mut Vec<Object>) -> Option<usize> {
+    let player_pos = objects[PLAYER].pos();
+    let loot = objects
+        .iter_mut()
+        .find(|object| object.pos() == player_pos && !object.alive && object.loot.is_some())
+        .and_then(|corpse| corpse.loot.take())?;
+    objects.push(new_item(loot, player_pos.0, player_pos.1));
+    Some(objects.len() - 1)
+}
+
+// gold doesn't take up an inventory slot, so it's picked up from anything the player
+// steps on: piles of it, and whatever monster remains had some
+fn collect_gold(game: &mut Game, objects: &mut Vec<Object>) {
+    let player_pos = objects[PLAYER].pos();
+    let mut found = 0;
+    for object in objects.iter_mut().skip(PLAYER + 1) {
+        if object.pos() == player_pos && !object.alive {
+            found += object.gold;
+            object.gold = 0;
+        }
+    }
+    if found == 0 {
+        return;
+    }
+    objects[PLAYER].gold += found;
+    // emptied piles go, but remains stay where they are
+    objects.retain(|object| object.name != "gold" || object.gold > 0);
+    game.messages
+        .add_message(format!("You pick up {} gold.", found), GOLD);
+    game.sounds.push(Sound::Pickup);
+}
 
-    let key = tcod.root.wait_for_keypress(true);
-    match key {
-        Key {
-            code: Enter,
-            alt: true,
-            ..
-        } => {
-            // Alt+Enter: toggle fullscreen
-            let fullscreen = tcod.root.is_fullscreen();
-            tcod.root.set_fullscreen(!fullscreen);
+// a debug cheat: pick a kind of monster, and it appears right next to the player
+fn debug_spawn_monster(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<Object>) {
+    let mut names: Vec<&String> = game.monster_defs.keys().collect();
+    names.sort();
+    names.truncate(MAX_INVENTORY);
+    let header = "Press the key next to a monster to spawn it, or any other to cancel.\n";
+    let Some(choice) = menu(header, &names, INVENTORY_WIDTH, tcod) else {
+        return;
+    };
+    let name = names[choice].clone();
+    let (player_x, player_y) = objects[PLAYER].pos();
+    let spot = (-1..=1)
+        .flat_map(|dy| (-1..=1).map(move |dx| (player_x + dx, player_y + dy)))
+        .find(|&(x, y)| !is_blocked(x, y, &game.map, objects));
+    match spot {
+        Some((x, y)) => {
+            objects.push(spawn_monster(&name, &game.monster_defs[&name], x, y));
+            game.messages
+                .add_message(format!("A {} appears.", name), LIGHT_VIOLET);
         }
-        Key { code: Escape, .. } => return true, // exit game
-        // movement keys
-        Key { code: Up, .. } => player.move_by(0, -1, game),
-        Key { code: Down, .. } => player.move_by(0, 1, game),
-        Key { code: Left, .. } => player.move_by(-1, 0, game),
-        Key { code: Right, .. } => player.move_by(1, 0, game),
-        _ => {}
+        None => game
+            .messages
+            .add_message("There is no room for it here.", LIGHT_VIOLET),
     }
-    false
 }
 
-fn create_room(room: Rect, map: &mut Map) {
-    // go through the tiles in the rectangle and make them passable
-    for x in (room.x1 + 1)..room.x2 {
-        for y in (room.y1 + 1)..room.y2 {
-            map[x as usize][y as usize] = Tile::empty();
+// put the monsters that split off this turn next to the one they split from. with no
+// free tile around it, the other half is lost
+fn place_offspring(game: &mut Game, objects: &mut Vec<Object>) {
+    for mut offspring in std::mem::take(&mut game.offspring) {
+        let (x, y) = offspring.pos();
+        let fits = |x: i32, y: i32| {
+            let kind = game.map.get(x, y).map(|tile| tile.kind);
+            let wet = offspring.land_only && kind == Some(TileKind::DeepWater);
+            !is_blocked(x, y, &game.map, objects) && kind != Some(TileKind::Lava) && !wet
+        };
+        let spot = (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy)))
+            .find(|&(x, y)| fits(x, y));
+        if let Some((x, y)) = spot {
+            game.messages
+                .add_message(format!("The {} splits in two!", offspring.name), LIGHT_GREEN);
+            offspring.set_pos(x, y);
+            objects.push(offspring);
         }
     }
 }
 
-fn render_all(tcod: &mut Tcod, game: &Game, objects: &Objects) {
-    // draw all objects in the list
-    objects.draw_all(&mut tcod.con);
+// take an item out of the inventory and put it on the floor under the player
+fn drop_item(inventory_id: usize, game: &mut Game, objects: &mut Vec<Object>) -> PlayerAction {
+    let mut item = game.inventory.remove(inventory_id);
+    // gear comes off before it's dropped
+    if item.equipment.is_some() {
+        item.dequip(&mut game.messages);
+    }
+    item.set_pos(objects[PLAYER].x, objects[PLAYER].y);
+    game.messages
+        .add_message(format!("You dropped a {}.", item.stack_name(game)), YELLOW);
+    if item.item == Some(Item::Amulet) {
+        game.has_amulet = false;
+    }
+    objects.push(item);
+    clamp_player_hp(game, objects);
+    PlayerAction::TookTurn
+}
+
+// whether using an item spent it
+enum UseResult {
+    UsedUp,
+    UsedAndKept,
+    Cancelled,
+}
 
-    // go through all tiles, and set their background color
-    for y in 0..MAP_HEIGHT {
-        for x in 0..MAP_WIDTH {
-            let wall = game.map[x as usize][y as usize].block_sight;
-            if wall {
-                tcod.con
-                    .set_char_background(x, y, COLOR_DARK_WALL, BackgroundFlag::Set);
-            } else {
-                tcod.con
-                    .set_char_background(x, y, COLOR_DARK_GROUND, BackgroundFlag::Set);
+fn use_item(
+    inventory_id: usize,
+    tcod: &mut Tcod,
+    game: &mut Game,
+    objects: &mut [Object],
+) -> PlayerAction {
+    use Item::*;
+    // just call the "use_function" if it is defined
+    if let Some(item) = game.inventory[inventory_id].item {
+        let on_use = match item {
+            Heal => cast_heal,
+            Regen => cast_regen,
+            Lightning => cast_lightning,
+            Confuse => cast_confuse,
+            Fireball => cast_fireball,
+            Sword => toggle_equipment,
+            Shield => toggle_equipment,
+            Greataxe => toggle_equipment,
+            Amulet => use_amulet,
+        };
+        match on_use(inventory_id, tcod, game, objects) {
+            UseResult::UsedUp => {
+                // using it gives away what it is, backing out of it doesn't
+                identify(inventory_id, game);
+                // destroy after use, unless it was cancelled for some reason
+                use_up(inventory_id, game);
+                PlayerAction::TookTurn
+            }
+            UseResult::UsedAndKept => {
+                identify(inventory_id, game);
+                PlayerAction::TookTurn
+            }
+            UseResult::Cancelled => {
+                game.messages.add_message("Cancelled", WHITE);
+                PlayerAction::DidntTakeTurn
             }
         }
+    } else {
+        game.messages.add_message(
+            format!("The {} cannot be used.", game.inventory[inventory_id].name),
+            WHITE,
+        );
+        PlayerAction::DidntTakeTurn
     }
+}
 
-    blit(
-        &tcod.con,
-        (0, 0),
-        (SCREEN_WIDTH, SCREEN_HEIGHT),
-        &mut tcod.root,
-        (0, 0),
-        1.0,
-        1.0,
+// the kind of a potion or scroll is known for good once one has been used
+fn identify(inventory_id: usize, game: &mut Game) {
+    let object = &game.inventory[inventory_id];
+    let Some(item) = object.item else {
+        return;
+    };
+    let looks = object.display_name(game);
+    if looks != object.name && !game.identified.contains(&item) {
+        let message = format!("The {} was a {}.", looks, object.name);
+        game.identified.push(item);
+        game.messages.add_message(message, LIGHT_CYAN);
+    }
+}
+
+// of a stack, only the one used goes
+fn use_up(inventory_id: usize, game: &mut Game) {
+    if game.inventory[inventory_id].count > 1 {
+        game.inventory[inventory_id].count -= 1;
+    } else {
+        game.inventory.remove(inventory_id);
+    }
+}
+
+// throw a potion at a tile, where it shatters and splashes everything next to it
+fn throw_item(
+    inventory_id: usize,
+    tcod: &mut Tcod,
+    game: &mut Game,
+    objects: &mut [Object],
+) -> PlayerAction {
+    let thrown = &game.inventory[inventory_id];
+    let Some(item) = thrown.item.filter(|item| item.is_potion()) else {
+        let message = format!("You can't throw the {}.", thrown.display_name(game));
+        game.messages.add_message(message, WHITE);
+        return PlayerAction::DidntTakeTurn;
+    };
+    let (glyph, color) = (thrown.char, thrown.color);
+    game.messages.add_message(
+        format!(
+            "Left-click where to throw the {}, or right-click to cancel.",
+            thrown.display_name(game)
+        ),
+        LIGHT_CYAN,
     );
+    let Some((x, y)) = target_tile(tcod, game, objects, Some(THROW_RANGE as f32)) else {
+        game.messages.add_message("Cancelled", WHITE);
+        return PlayerAction::DidntTakeTurn;
+    };
+    game.projectiles.push(Projectile {
+        from: objects[PLAYER].pos(),
+        to: (x, y),
+        glyph,
+        color,
+    });
+    fly_projectiles(tcod, game, objects);
+    game.messages.add_message(
+        format!("The {} shatters!", game.inventory[inventory_id].display_name(game)),
+        color,
+    );
+    for object in objects.iter_mut() {
+        let splashed = object.distance(x, y) <= SPLASH_RADIUS as f32;
+        if !splashed || !object.alive || object.fighter.is_none() {
+            continue;
+        }
+        match item {
+            Item::Heal => {
+                game.messages
+                    .add_message(format!("The {} looks healthier.", object.name), LIGHT_VIOLET);
+                object.heal(HEAL_AMOUNT, game);
+            }
+            Item::Regen => {
+                let status = Status {
+                    kind: StatusKind::Regen,
+                    turns_left: REGEN_POTION_TURNS,
+                    magnitude: REGEN_POTION_AMOUNT,
+                };
+                object.add_status(status, game);
+            }
+            _ => {}
+        }
+    }
+    identify(inventory_id, game);
+    use_up(inventory_id, game);
+    PlayerAction::TookTurn
 }
 
-fn create_tunnel(x1: i32, x2: i32, y: i32, y2: i32, map: &mut Map) {
-    for x in cmp::min(x1, x2)..(cmp::max(x1, x2) +1) {
-        for y in cmp::min(y, y2)..(cmp::max(y, y2) +1) {
-            map[x as usize][y as usize] = Tile::empty();
+fn cast_heal(
+    _inventory_id: usize,
+    _tcod: &mut Tcod,
+    game: &mut Game,
+    objects: &mut [Object],
+) -> UseResult {
+    // heal the player
+    if let Some(fighter) = objects[PLAYER].fighter {
+        if fighter.hp == objects[PLAYER].max_hp(game) {
+            game.messages.add_message("You are already at full health.", RED);
+            return UseResult::Cancelled;
         }
+        game.messages
+            .add_message("Your wounds start to feel better!", LIGHT_VIOLET);
+        objects[PLAYER].heal(HEAL_AMOUNT, game);
+        return UseResult::UsedUp;
     }
+    UseResult::Cancelled
 }
 
-fn make_map(player: &mut Object) -> Map {
-    // fill map with "unblocked" tiles
-    let mut map = vec![vec![Tile::wall(); MAP_HEIGHT as usize]; MAP_WIDTH as usize];
-    let mut rooms = vec![];
+fn cast_regen(
+    _inventory_id: usize,
+    _tcod: &mut Tcod,
+    game: &mut Game,
+    objects: &mut [Object],
+) -> UseResult {
+    // heal over time instead of all at once
+    let status = Status {
+        kind: StatusKind::Regen,
+        turns_left: REGEN_POTION_TURNS,
+        magnitude: REGEN_POTION_AMOUNT,
+    };
+    objects[PLAYER].add_status(status, game);
+    UseResult::UsedUp
+}
 
-    for _ in 0..MAX_ROOMS {
-        let w = random_range(ROOM_MIN_SIZE..ROOM_MAX_SIZE +1);
-        let h = random_range(ROOM_MIN_SIZE..ROOM_MAX_SIZE +1);
-        let room = Rect::new(random_range(0..MAP_WIDTH - w),
-                              random_range(0..MAP_HEIGHT - h), w, h);
-        let failed = rooms.iter().any(|other| room.intersects_with(other));
-        if !failed{
-            create_room(room, &mut map);
-            let (cen_x, cen_y) = room.center();
-            if rooms.is_empty() {
-                player.x = cen_x;
-                player.y = cen_y;
-            } else {
-                let (prev_x, prev_y) = rooms[rooms.len() - 1].center();
+fn use_amulet(
+    _inventory_id: usize,
+    _tcod: &mut Tcod,
+    game: &mut Game,
+    _objects: &mut [Object],
+) -> UseResult {
+    game.messages.add_message(
+        "You raise the amulet, and the way out of the tombs opens before you.",
+        GOLD,
+    );
+    game.state = GameState::Won;
+    UseResult::UsedAndKept
+}
 
-                if rand::random() {
-                    create_tunnel(prev_x, cen_x, prev_y, prev_y, &mut map);
-                    create_tunnel(cen_x, cen_x, prev_y, cen_y, &mut map);
-                } else {
-                    create_tunnel(prev_x, prev_x, prev_y, cen_y, &mut map);
-                    create_tunnel(prev_x, cen_x, cen_y, cen_y, &mut map);
-                }
+fn toggle_equipment(
+    inventory_id: usize,
+    _tcod: &mut Tcod,
+    game: &mut Game,
+    objects: &mut [Object],
+) -> UseResult {
+    let equipment = match game.inventory[inventory_id].equipment {
+        Some(equipment) => equipment,
+        None => return UseResult::Cancelled,
+    };
+    if equipment.equipped {
+        game.inventory[inventory_id].dequip(&mut game.messages);
+    } else {
+        // if the slot is already being used, dequip whatever is there first. a two-handed
+        // weapon needs both hands free, and a hand can't be used while holding one
+        while let Some(current) = get_equipped_in_slot(equipment.slot, &game.inventory) {
+            let in_the_way = &game.inventory[current];
+            if in_the_way.equipment.is_some_and(|e| e.slot != equipment.slot) {
+                let message = format!(
+                    "You can't use the {} together with the {}.",
+                    game.inventory[inventory_id].name, in_the_way.name
+                );
+                game.messages.add_message(message, LIGHT_YELLOW);
             }
-            rooms.push(room);
+            game.inventory[current].dequip(&mut game.messages);
         }
+        game.inventory[inventory_id].equip(&mut game.messages);
     }
+    clamp_player_hp(game, objects);
+    UseResult::UsedAndKept
+}
 
-    map
+// losing a max hp bonus can leave the player above the new maximum
+fn clamp_player_hp(game: &Game, objects: &mut [Object]) {
+    let max_hp = objects[PLAYER].max_hp(game);
+    if let Some(fighter) = objects[PLAYER].fighter.as_mut() {
+        fighter.hp = cmp::min(fighter.hp, max_hp);
+    }
 }
 
-fn main() {
-    let mut tcod = Tcod {
-        root: Root::initializer()
-            .font("arial10x10.png", FontLayout::Tcod)
-            .font_type(FontType::Greyscale)
-            .size(SCREEN_WIDTH, SCREEN_HEIGHT)
-            .title("Rust/libtcod tutorial")
-            .init(),
-        con: Offscreen::new(MAP_WIDTH, MAP_HEIGHT),
+// the inventory index of an item equipped in the given slot, or in one that overlaps it
+// like both hands do with either hand
+fn get_equipped_in_slot(slot: Slot, inventory: &[Object]) -> Option<usize> {
+    inventory.iter().position(|item| {
+        item.equipment.is_some_and(|e| e.equipped && e.slot.overlaps(slot))
+    })
+}
+
+fn gain_xp(object: &mut Object, xp: i32) {
+    if let Some(fighter) = object.fighter.as_mut() {
+        fighter.xp += xp;
+    }
+}
+
+fn cast_lightning(
+    _inventory_id: usize,
+    tcod: &mut Tcod,
+    game: &mut Game,
+    objects: &mut [Object],
+) -> UseResult {
+    // find closest enemy (inside a maximum range) and damage it
+    let monster_id = closest_monster(tcod, &game.map, objects, LIGHTNING_RANGE);
+    if let Some(monster_id) = monster_id {
+        // zap it!
+        game.projectiles.push(Projectile {
+            from: objects[PLAYER].pos(),
+            to: objects[monster_id].pos(),
+            glyph: '*',
+            color: LIGHT_BLUE,
+        });
+        fly_projectiles(tcod, game, objects);
+        game.messages.add_message(
+            format!(
+                "A lightning bolt strikes the {} with a loud thunder! \
+                 The damage is {} hit points.",
+                objects[monster_id].name, LIGHTNING_DAMAGE
+            ),
+            LIGHT_BLUE,
+        );
+        if let Some(xp) = objects[monster_id].take_damage(LIGHTNING_DAMAGE, game) {
+            gain_xp(&mut objects[PLAYER], xp);
+        }
+        UseResult::UsedUp
+    } else {
+        // no enemy found within maximum range
+        game.messages
+            .add_message("No enemy is close enough to strike.", RED);
+        UseResult::Cancelled
+    }
+}
+
+// show the queued projectiles flying, over the map as it is before they hit
+fn fly_projectiles(tcod: &mut Tcod, game: &mut Game, objects: &[Object]) {
+    tcod.con.clear();
+    render_all(tcod, game, objects, false);
+    animate_projectiles(tcod, &mut game.projectiles);
+}
+
+fn cast_fireball(
+    _inventory_id: usize,
+    tcod: &mut Tcod,
+    game: &mut Game,
+    objects: &mut [Object],
+) -> UseResult {
+    // ask the player for a target tile to throw a fireball at
+    game.messages.add_message(
+        "Left-click a target tile for the fireball, or right-click to cancel.",
+        LIGHT_CYAN,
+    );
+    let (x, y) = match target_tile(tcod, game, objects, None) {
+        Some(tile_pos) => tile_pos,
+        None => return UseResult::Cancelled,
+    };
+    game.projectiles.push(Projectile {
+        from: objects[PLAYER].pos(),
+        to: (x, y),
+        glyph: '*',
+        color: ORANGE,
+    });
+    fly_projectiles(tcod, game, objects);
+    game.messages.add_message(
+        format!(
+            "The fireball explodes, burning everything within {} tiles!",
+            FIREBALL_RADIUS
+        ),
+        ORANGE,
+    );
+
+    // damage every fighter in range, including the player
+    let mut xp_to_gain = 0;
+    for (id, obj) in objects.iter_mut().enumerate() {
+        if obj.distance(x, y) <= FIREBALL_RADIUS as f32 && obj.fighter.is_some() {
+            game.messages.add_message(
+                format!(
+                    "The {} gets burned for {} hit points.",
+                    obj.name, FIREBALL_DAMAGE
+                ),
+                ORANGE,
+            );
+            if let Some(xp) = obj.take_damage(FIREBALL_DAMAGE, game)
+                && id != PLAYER
+            {
+                // don't reward the player for burning themself!
+                xp_to_gain += xp;
+            }
+        }
+    }
+    gain_xp(&mut objects[PLAYER], xp_to_gain);
+
+    UseResult::UsedUp
+}
+
+// return the position of a tile left-clicked in player's FOV (optionally in a
+// range), or None if right-clicked.
+fn target_tile(
+    tcod: &mut Tcod,
+    game: &mut Game,
+    objects: &[Object],
+    max_range: Option<f32>,
+) -> Option<(i32, i32)> {
+    use crate::backend::KeyCode::Escape;
+    loop {
+        // render the screen. this erases the inventory and shows the names of
+        // objects under the mouse.
+        tcod.backend.present(&tcod.screen);
+        poll_input(tcod, game.turn);
+        render_all(tcod, game, objects, false);
+
+        // accept the target if the player clicked in FOV over the map window, and
+        // in case a range is specified, if it's in that range
+        let mouse = (tcod.mouse.cx as i32, tcod.mouse.cy as i32);
+        if let Some((x, y)) = tcod.camera.to_map_coordinates(mouse.0, mouse.1) {
+            let (player_x, player_y) = objects[PLAYER].pos();
+            let in_fov = game.map.in_bounds(x, y)
+                && tcod.fov.is_in_fov(x, y)
+                && line_of_sight(&game.map, player_x, player_y, x, y);
+            let in_range = max_range.is_none_or(|range| objects[PLAYER].distance(x, y) <= range);
+            if tcod.mouse.lbutton_pressed && in_fov && in_range {
+                return Some((x, y));
+            }
+        }
+
+        if tcod.mouse.rbutton_pressed || tcod.key.code == Escape {
+            return None; // cancel if the player right-clicked or pressed Escape
+        }
+
+        if tcod.backend.window_closed() {
+            return None;
+        }
+    }
+}
+
+fn cast_confuse(
+    _inventory_id: usize,
+    tcod: &mut Tcod,
+    game: &mut Game,
+    objects: &mut [Object],
+) -> UseResult {
+    // find closest enemy in-range and confuse it
+    let monster_id = closest_monster(tcod, &game.map, objects, CONFUSE_RANGE);
+    if let Some(monster_id) = monster_id {
+        // whatever it was doing, it's awake now
+        let old_ai = match objects[monster_id].ai.take() {
+            Some(Ai::Asleep { awake_ai }) => *awake_ai,
+            Some(ai) => ai,
+            None => Ai::Basic {
+                alert: MONSTER_ALERT_TURNS,
+            },
+        };
+        // replace the monster's AI with a "confused" one; after
+        // some turns it will restore the old AI
+        objects[monster_id].ai = Some(Ai::Confused {
+            previous_ai: Box::new(old_ai),
+            num_turns: CONFUSE_NUM_TURNS,
+        });
+        game.messages.add_message(
+            format!(
+                "The eyes of {} look vacant, as it starts to stumble around!",
+                objects[monster_id].name
+            ),
+            LIGHT_GREEN,
+        );
+        UseResult::UsedUp
+    } else {
+        // no enemy found within maximum range
+        game.messages
+            .add_message("No enemy is close enough to confuse.", RED);
+        UseResult::Cancelled
+    }
+}
+
+// every level gets its own generator derived from the game seed, so a level can be
+// reproduced from the seed alone no matter what happened on the levels before it. the
+// level is spread over all the bits first, or seed s on level 2 would be seed s + 1 on
+// level 1
+fn level_rng(seed: u64, level: u32) -> StdRng {
+    StdRng::seed_from_u64(seed ^ (level as u64).wrapping_mul(LEVEL_SEED_MIX))
+}
+
+// the dice for the play itself, kept apart from the levels so that playing differently
+// never changes how a level looks. a loaded game continues from its turn
+fn play_rng(seed: u64, turn: u32) -> StdRng {
+    StdRng::seed_from_u64(seed.rotate_left(32) ^ turn as u64)
+}
+
+fn initialise_fov(tcod: &mut Tcod, map: &Map) {
+    // populate the FOV map, according to the generated map
+    tcod.fov = FovMap::new(map.width, map.height);
+    for y in 0..map.height {
+        for x in 0..map.width {
+            let Some(&tile) = map.get(x, y) else {
+                continue;
+            };
+            tcod.fov.set_transparent(x, y, !tile.block_sight);
+        }
+    }
+    // unexplored areas start black (which is the default background color)
+    tcod.con.clear();
+}
+
+// the final level has the amulet in place of the stairs
+fn place_amulet(level: u32, objects: &mut [Object]) {
+    if level != FINAL_DUNGEON_LEVEL {
+        return;
+    }
+    if let Some(stairs) = objects.iter_mut().find(|object| object.name == "stairs") {
+        *stairs = new_item(Item::Amulet, stairs.x, stairs.y);
+    }
+}
+
+// the map generator had to dig extra corridors to reach parts of the level
+fn log_merged_regions(game: &mut Game, merged: usize) {
+    if merged > 0 {
+        game.messages.add_message(
+            format!("Joined {} unreachable parts of the level to the rest.", merged),
+            LIGHT_GREY,
+        );
+    }
+}
+
+// advance to the next level, generating it the first time
+fn next_level(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<Object>) -> Result<(), String> {
+    let level = game.dungeon_level + 1;
+    // a new level is made apart from the current one, which stays as it was if that fails.
+    // the player goes along as a copy, put wherever the new level starts
+    let mut new_level = None;
+    if !game.levels.contains_key(&level) {
+        let mut new_objects = vec![objects[PLAYER].clone()];
+        let mut rng = level_rng(game.seed, level);
+        let (map, merged) = make_map(
+            &game.map_config,
+            &mut new_objects,
+            level,
+            game.difficulty,
+            &game.monster_defs,
+            &game.vaults,
+            &mut rng,
+        )?;
+        // only a new level gives this, or going up and down would heal for free
+        game.messages.add_message(
+            "You take a moment to rest, and recover your strength.",
+            VIOLET,
+        );
+        let heal_hp = new_objects[PLAYER].max_hp(game) / 2;
+        new_objects[PLAYER].heal(heal_hp, game);
+        new_level = Some((map, merged, new_objects));
+    }
+
+    game.messages
+        .add_message("You descend deeper into the heart of the dungeon...", RED);
+    game.sounds.push(Sound::Descend);
+    if let Some((map, merged, new_objects)) = new_level {
+        leave_level(game, objects);
+        *objects = new_objects;
+        game.dungeon_level = level;
+        game.map = map;
+        log_merged_regions(game, merged);
+        place_amulet(game.dungeon_level, objects);
+        // the way back up is right where the player arrives
+        let (player_x, player_y) = objects[PLAYER].pos();
+        let mut up_stairs = Object::new(player_x, player_y, '<', "up stairs", WHITE, false);
+        up_stairs.always_visible = true;
+        objects.push(up_stairs);
+    } else {
+        return_to_level(level, "up stairs", game, objects);
+    }
+    arrive_on_level(tcod, game, objects);
+    Ok(())
+}
+
+// climb back to the level above, which is always one the player has been on
+fn previous_level(
+    tcod: &mut Tcod,
+    game: &mut Game,
+    objects: &mut Vec<Object>,
+) -> Result<(), String> {
+    let level = game.dungeon_level - 1;
+    if !game.levels.contains_key(&level) {
+        return Err(format!("level {} is gone", level));
+    }
+    game.messages
+        .add_message("You climb back up the stairs.", LIGHT_VIOLET);
+    return_to_level(level, "stairs", game, objects);
+    arrive_on_level(tcod, game, objects);
+    Ok(())
+}
+
+// put the current level away, leaving only the player. the inventory is kept in `game`
+fn leave_level(game: &mut Game, objects: &mut Vec<Object>) {
+    let left = Level {
+        map: game.map.clone(),
+        objects: objects.split_off(PLAYER + 1),
+    };
+    game.levels.insert(game.dungeon_level, left);
+}
+
+// swap in a level visited before, with the player on the stairs of the given name
+fn return_to_level(level: u32, stairs: &str, game: &mut Game, objects: &mut Vec<Object>) {
+    leave_level(game, objects);
+    let Some(Level {
+        map,
+        objects: others,
+    }) = game.levels.remove(&level)
+    else {
+        return;
+    };
+    game.dungeon_level = level;
+    game.map = map;
+    objects.extend(others);
+    if let Some(pos) = objects.iter().find(|object| object.name == stairs).map(Object::pos) {
+        objects[PLAYER].set_pos(pos.0, pos.1);
+    }
+}
+
+// everything that has to be redone for the level the player just got to
+fn arrive_on_level(tcod: &mut Tcod, game: &mut Game, objects: &mut [Object]) {
+    initialise_fov(tcod, &game.map);
+    // the old distances belong to the previous level
+    game.player_distances = DijkstraMap::default();
+    game.scent = ScentMap::default();
+    let (player_x, player_y) = objects[PLAYER].pos();
+    compute_fov(tcod, player_x, player_y);
+
+    // save at the top of the new level, so a crash loses at most this level
+    auto_save(game, objects);
+}
+
+// experience needed to advance past the given level
+fn level_up_xp(level: i32) -> i32 {
+    LEVEL_UP_BASE + level * LEVEL_UP_FACTOR
+}
+
+fn level_up(tcod: &mut Tcod, game: &mut Game, objects: &mut [Object]) {
+    let player = &mut objects[PLAYER];
+    let level_up_xp = level_up_xp(player.level);
+    // see if the player's experience is enough to level-up
+    let Some(xp) = player.fighter.map(|f| f.xp) else {
+        return;
+    };
+    if xp < level_up_xp {
+        return;
+    }
+
+    // it is! level up
+    let Some(fighter) = player.fighter.as_mut() else {
+        return;
+    };
+    let mut choice = None;
+    while choice.is_none() {
+        // a closed window can't answer: the xp stays unspent, to level up after a load
+        if tcod.backend.window_closed() {
+            return;
+        }
+        // keep asking until a choice is made
+        choice = menu(
+            "Level up! Choose a stat to raise:\n",
+            &[
+                format!("Constitution (+20 HP, from {})", fighter.base_max_hp),
+                format!("Strength (+1 attack, from {})", fighter.base_power),
+                format!("Agility (+1 defense, from {})", fighter.base_defense),
+            ],
+            LEVEL_SCREEN_WIDTH,
+            tcod,
+        );
+    }
+    player.level += 1;
+    game.messages.add_message(
+        format!(
+            "Your battle skills grow stronger! You reached level {}!",
+            player.level
+        ),
+        YELLOW,
+    );
+    fighter.xp -= level_up_xp;
+    match choice.unwrap() {
+        0 => {
+            fighter.base_max_hp += 20;
+            fighter.hp += 20;
+        }
+        1 => {
+            fighter.base_power += 1;
+        }
+        2 => {
+            fighter.base_defense += 1;
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn new_game(
+    tcod: &mut Tcod,
+    monster_defs: &MonsterDefs,
+    vaults: &[Vault],
+    seed: Option<u64>,
+    difficulty: Difficulty,
+) -> Result<(Game, Vec<Object>), String> {
+    // a random seed unless one was asked for
+    let seed = seed.unwrap_or_else(rand::random);
+
+    // create object representing the player
+    let mut player = Object::new(0, 0, '@', "player", WHITE, true);
+    player.alive = true;
+    player.fighter = Some(Fighter {
+        base_max_hp: 30,
+        hp: 30,
+        base_defense: 2,
+        base_power: 5,
+        xp: 0,
+        on_death: DeathCallback::Player,
+        on_hit: None,
+        ability: None,
+    });
+    // the player is always the first object
+    let mut objects = vec![player];
+
+    let map_config = MapGenConfig::default();
+    let mut rng = level_rng(seed, 1);
+    // generate map (at this point it's not drawn to the screen)
+    let (map, merged) =
+        make_map(&map_config, &mut objects, 1, difficulty, monster_defs, vaults, &mut rng)?;
+    let mut game = Game {
+        map,
+        map_config,
+        messages: Messages::new(),
+        inventory: vec![],
+        dungeon_level: 1,
+        levels: HashMap::new(),
+        seed,
+        difficulty,
+        state: GameState::Playing,
+        has_amulet: false,
+        turn: 0,
+        tick: 0,
+        item_labels: vec![],
+        identified: vec![],
+        rng: play_rng(seed, 0),
+        monster_defs: monster_defs.clone(),
+        vaults: vaults.to_vec(),
+        player_distances: DijkstraMap::default(),
+        scent: ScentMap::default(),
+        sounds: vec![],
+        projectiles: vec![],
+        offspring: vec![],
+        god_mode: false,
     };
+    place_amulet(game.dungeon_level, &mut objects);
+    game.item_labels = item_labels(&mut game.rng);
 
-    tcod::system::set_fps(LIMIT_FPS);
+    initialise_fov(tcod, &game.map);
 
-    let player = Object::new(0, 0, '@', WHITE);
-    let mut npcs = HashMap::new();
-    npcs.insert(
-        "bob".to_string(),
-        Object::new(SCREEN_WIDTH / 2 - 5, SCREEN_HEIGHT / 2, '@', YELLOW),
+    // a warm welcoming message!
+    game.messages.add_message(
+        "Welcome stranger! Prepare to perish in the Tombs of the Ancient Kings.",
+        RED,
     );
-    let mut objects = Objects::new(
-        player,
-        npcs,
+    game.messages.add_message(
+        format!("Dungeon seed: {} ({})", seed, difficulty.name()),
+        LIGHT_GREY,
     );
-    objects.player.draw(&mut tcod.con);
+    log_merged_regions(&mut game, merged);
+    if let Err(err) = tcod.replay.start(seed, difficulty) {
+        game.messages
+            .add_message(format!("Could not record this game: {}", err), RED);
+    }
 
-    let game = Game {
-        // generate map (at this point it's not drawn to the screen)
-        map: make_map(&mut objects.player),
+    Ok((game, objects))
+}
+
+// pick up the latest key press and mouse position. a frame only ever acts on the key that
+// came in during it. a flickering torch needs a new frame now and then, so then this only
+// waits until the next one is due; otherwise the screen only changes on input, and it
+// sleeps until there is some
+fn poll_input(tcod: &mut Tcod, turn: u32) {
+    let input = if tcod.options.flicker {
+        let frame = Duration::from_millis(1000 / TORCH_FLICKER_FPS);
+        tcod.replay.wait_event_timeout(&mut *tcod.backend, turn, frame)
+    } else {
+        tcod.replay.wait_event(&mut *tcod.backend, turn)
     };
+    take_input(tcod, input);
+}
 
-    while !tcod.root.window_closed() {
+// make the input the one this frame acts on
+fn take_input(tcod: &mut Tcod, input: Option<Input>) {
+    match input {
+        Some(Input::Mouse(m)) => {
+            tcod.mouse = m;
+            tcod.key = Default::default();
+        }
+        Some(Input::Key(k)) => tcod.key = k,
+        None => tcod.key = Default::default(),
+    }
+}
+
+// the objects that went to a neighbouring tile since they were at `positions`. the ones
+// that went further, like by teleporting, just show up in their new place
+fn steps_taken(
+    positions: &[(u32, (i32, i32))],
+    tcod: &Tcod,
+    game: &Game,
+    objects: &[Object],
+) -> Vec<Step> {
+    positions
+        .iter()
+        .filter_map(|&(id, from)| {
+            let object = &objects[index_of(id, objects)?];
+            let to = object.pos();
+            let distance = cmp::max((to.0 - from.0).abs(), (to.1 - from.1).abs());
+            // drawn the way it is on the map, see draw_all
+            let glyph = tcod.tileset.glyph(&object.display_name(game)).unwrap_or(object.char);
+            let step = Step {
+                from,
+                to,
+                glyph,
+                color: object.color,
+            };
+            (distance == 1).then_some(step)
+        })
+        .collect()
+}
+
+fn play_game(tcod: &mut Tcod, game: &mut Game, objects: &mut Vec<Object>) {
+    // force FOV "recompute" first time through the game loop
+    let mut previous_player_position = (-1, -1);
+    // nothing from a previous run may carry over, like the key that ended it
+    tcod.key = Default::default();
+    tcod.mouse = Default::default();
+    // what the player did while the last turn was still being shown
+    let mut held_input = None;
+
+    while !tcod.backend.window_closed() {
         // clear the screen of the previous frame
         tcod.con.clear();
-        render_all(&mut tcod, &game, &objects);
-        tcod.root.flush();
+        let player_position = (objects[PLAYER].x, objects[PLAYER].y);
+        let fov_recompute = previous_player_position != player_position;
+        render_all(tcod, game, objects, fov_recompute);
+        tcod.backend.present(&tcod.screen);
+
+        match held_input.take() {
+            Some(input) => take_input(tcod, Some(input)),
+            None => poll_input(tcod, game.turn),
+        }
+        if let Some(reason) = tcod.replay.take_ended() {
+            game.messages.add_message(
+                format!("The replay stopped ({}), you're in control now.", reason),
+                LIGHT_GREEN,
+            );
+        }
+
+        // level up if needed
+        level_up(tcod, game, objects);
+
+        // where everything stood before the turn, to show what moved during it
+        let level = game.dungeon_level;
+        let positions: Vec<_> = objects.iter().map(|object| (object.id, object.pos())).collect();
 
-        tcod.root.wait_for_keypress(true);
         // handle keys and exit game if needed
-        let exit = handle_keys(&mut tcod, &game, &mut objects.player);
-        if exit {
+        previous_player_position = player_position;
+        let action = handle_keys(tcod, game, objects);
+        // a menu or target prompt can also see the window closed, after the key was
+        // handled; the monsters don't get a turn against a game nobody is playing
+        if action == PlayerAction::Exit || tcod.backend.window_closed() {
+            // a dead player's save is already gone, and must stay gone
+            if game.state == GameState::Playing && !auto_save(game, objects) {
+                if tcod.backend.window_closed() {
+                    eprintln!("The game could not be saved.");
+                } else {
+                    // let the player see why before leaving the game
+                    render_all(tcod, game, objects, false);
+                    tcod.backend.present(&tcod.screen);
+                    msgbox("\nThe game could not be saved.\n", 50, tcod);
+                }
+            }
             break;
         }
+
+        place_offspring(game, objects);
+
+        // let monsters take their turn
+        if game.state == GameState::Playing && action == PlayerAction::TookTurn {
+            // wading into slow terrain gives the monsters extra turns
+            let player = &objects[PLAYER];
+            let mut turns = if player.pos() != player_position {
+                game.map.get(player.x, player.y).map_or(1, |tile| tile.kind.move_cost())
+            } else {
+                1
+            };
+            // and so does being slowed
+            if player.has_status(StatusKind::Slow) {
+                turns *= 2;
+            }
+            // arrows shot this turn fly over the map as the player left it, not over
+            // the menu they may have used
+            tcod.con.clear();
+            render_all(tcod, game, objects, true);
+            for _ in 0..turns {
+                // one distance map for all the monsters, only rebuilt if the player moved
+                game.player_distances.update(objects[PLAYER].pos(), &game.map);
+                game.scent.spread(&game.map, NOISE_DECAY, NOISE_DIFFUSION);
+                // by id, as the list can change under the loop; one that is gone is skipped
+                let ids: Vec<u32> = objects.iter().map(|object| object.id).collect();
+                for id in ids {
+                    // once the player is dead, the remaining monsters stop too
+                    if game.state != GameState::Playing {
+                        break;
+                    }
+                    let Some(id) = index_of(id, objects) else {
+                        continue;
+                    };
+                    let monster = &objects[id];
+                    if monster.alive && monster.ai.is_some() && !monster.loses_turn(game.tick) {
+                        ai_take_turn(id, game, objects);
+                    }
+                }
+                burn_on_lava(game, objects);
+                sink_gear(game, objects);
+                for object in objects.iter_mut() {
+                    object.tick_statuses(game);
+                }
+                place_offspring(game, objects);
+                game.tick += 1;
+                regenerate(game.tick, &tcod.fov, game, objects);
+            }
+            game.turn += 1;
+        }
+
+        // what flew this turn, shown before the frame with what it hit
+        animate_projectiles(tcod, &mut game.projectiles);
+        tcod.sounds.play_all(&mut game.sounds);
+        // then everyone who moved steps to their new tile together, unless the player went
+        // to another level and nothing is where it was
+        if action == PlayerAction::TookTurn && game.dungeon_level == level {
+            let steps = steps_taken(&positions, tcod, game, objects);
+            held_input = animate_steps(tcod, game, objects, &steps);
+        }
+
+        // the player died this turn: show the corpse, then go back to the main menu
+        if game.state == GameState::Dead {
+            render_all(tcod, game, objects, false);
+            tcod.backend.present(&tcod.screen);
+            let text = "\nYou died!\n\nPress any key to continue.\n";
+            msgbox(text, DEATH_SCREEN_WIDTH, tcod);
+            break;
+        }
+        // a won game is over just like a lost one, so its save goes too
+        if game.state == GameState::Won {
+            if let Err(err) = delete_save_game() {
+                eprintln!("Could not delete the saved game: {}", err);
+            }
+            render_all(tcod, game, objects, false);
+            tcod.backend.present(&tcod.screen);
+            let text = format!(
+                "\nYou escaped the Tombs of the Ancient Kings with their amulet, after going \
+                 {} levels deep. You won!\n\nPress any key to continue.\n",
+                game.dungeon_level
+            );
+            msgbox(&text, VICTORY_SCREEN_WIDTH, tcod);
+            break;
+        }
+    }
+}
+
+// every REGEN_INTERVAL ticks, the living fighters below their maximum hp get one back,
+// the player sooner or later depending on the difficulty. monsters only heal while the
+// player can't see them, to keep fights fair
+fn regenerate(turn: u32, fov: &FovMap, game: &Game, objects: &mut [Object]) {
+    let due = |interval: u32| interval > 0 && turn.is_multiple_of(interval);
+    for (id, object) in objects.iter_mut().enumerate() {
+        let heals = if id == PLAYER {
+            due(game.difficulty.regen_interval())
+        } else {
+            MONSTER_REGEN && due(REGEN_INTERVAL) && !fov.is_in_fov(object.x, object.y)
+        };
+        if object.alive && heals {
+            object.heal(1, game);
+        }
+    }
+}
+
+// swimming with gear that isn't worn is risky: every turn in deep water, the player might
+// lose hold of a piece of it
+fn sink_gear(game: &mut Game, objects: &[Object]) {
+    let player = &objects[PLAYER];
+    let swimming = game
+        .map
+        .get(player.x, player.y)
+        .is_some_and(|tile| tile.kind == TileKind::DeepWater);
+    if !player.alive || !swimming || game.rng.random::<f32>() >= SINK_CHANCE {
+        return;
+    }
+    let carried: Vec<usize> = (0..game.inventory.len())
+        .filter(|&id| game.inventory[id].equipment.is_some_and(|gear| !gear.equipped))
+        .collect();
+    if carried.is_empty() {
+        return;
+    }
+    let lost = game.inventory.remove(carried[game.rng.random_range(0..carried.len())]);
+    game.messages.add_message(
+        format!("Your {} slips away and sinks out of reach!", lost.display_name(game)),
+        LIGHT_BLUE,
+    );
+}
+
+// lava burns every fighter that ends the turn standing in it
+fn burn_on_lava(game: &mut Game, objects: &mut [Object]) {
+    for object in objects.iter_mut() {
+        let on_lava = game
+            .map
+            .get(object.x, object.y)
+            .is_some_and(|tile| tile.kind == TileKind::Lava);
+        if on_lava && object.alive && object.fighter.is_some() {
+            game.messages.add_message(
+                format!("The lava burns {} for {} hit points.", object.name, LAVA_DAMAGE),
+                ORANGE,
+            );
+            object.take_damage(LAVA_DAMAGE, game);
+        }
+    }
+}
+
+// the options from last time. without a file it's the defaults, and a broken one is
+// reported but not fatal
+fn load_options() -> Options {
+    match fs::read_to_string(OPTIONS_FILE) {
+        Ok(text) => serde_json::from_str(&text).unwrap_or_else(|err| {
+            eprintln!("Warning: ignoring {}: {}", OPTIONS_FILE, err);
+            Options::default()
+        }),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Options::default(),
+        Err(err) => {
+            eprintln!("Warning: could not read {}: {}", OPTIONS_FILE, err);
+            Options::default()
+        }
+    }
+}
+
+fn save_options(options: &Options) -> Result<(), io::Error> {
+    fs::write(OPTIONS_FILE, serde_json::to_string(options)?)
+}
+
+fn save_game(game: &Game, objects: &[Object], path: &str) -> Result<(), io::Error> {
+    let save_data = serde_json::to_string(&(game, objects))?;
+    let mut file = File::create(path)?;
+    file.write_all(save_data.as_bytes())?;
+    Ok(())
+}
+
+// save without interrupting the game: a failure is reported in the message log, and
+// the return value says whether the save worked
+fn auto_save(game: &mut Game, objects: &[Object]) -> bool {
+    match save_game(game, objects, SAVE_FILE) {
+        Ok(()) => true,
+        Err(err) => {
+            game.messages
+                .add_message(format!("Could not save the game: {}", err), RED);
+            false
+        }
+    }
+}
+
+// permadeath: once the player dies, the run can't be continued
+fn delete_save_game() -> Result<(), io::Error> {
+    match fs::remove_file(SAVE_FILE) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+fn load_game(path: &str) -> Result<(Game, Vec<Object>), Box<dyn Error>> {
+    let mut json_save_state = String::new();
+    let mut file = File::open(path)?;
+    file.read_to_string(&mut json_save_state)?;
+    let (mut game, mut objects) = serde_json::from_str::<(Game, Vec<Object>)>(&json_save_state)?;
+    continue_ids(&mut objects, &mut game.inventory);
+    Ok((game, objects))
+}
+
+fn main_menu(tcod: &mut Tcod, monster_defs: &MonsterDefs, vaults: &[Vault], seed: Option<u64>) {
+    while !tcod.backend.window_closed() {
+        // show the game's title
+        tcod.screen.set_default_background(BLACK);
+        tcod.screen.clear();
+        tcod.screen.set_default_foreground(LIGHT_YELLOW);
+        tcod.screen.print_ex(
+            SCREEN_WIDTH / 2,
+            SCREEN_HEIGHT / 2 - 4,
+            BackgroundFlag::None,
+            TextAlignment::Center,
+            "TOMBS OF THE ANCIENT KINGS",
+        );
+
+        // show options and wait for the player's choice
+        let colors = format!("Colors: {}", tcod.options.palette.name());
+        let movement = if tcod.options.move_ms > 0 {
+            "Movement: Smooth"
+        } else {
+            "Movement: Instant"
+        };
+        let choices = &["Play a new game", "Continue last game", &colors, movement, "Quit"];
+        let choice = menu("", choices, 24, tcod);
+
+        match choice {
+            Some(0) => {
+                // new game, once the player picked how hard it is
+                let names = Difficulty::ALL.map(Difficulty::name);
+                let Some(choice) = menu("Difficulty:", &names, 24, tcod) else {
+                    continue;
+                };
+                let difficulty = Difficulty::ALL[choice];
+                match new_game(tcod, monster_defs, vaults, seed, difficulty) {
+                    Ok((mut game, mut objects)) => play_game(tcod, &mut game, &mut objects),
+                    Err(err) => {
+                        let text = format!("\nCould not create the dungeon:\n{}\n", err);
+                        msgbox(&text, 50, tcod);
+                    }
+                }
+            }
+            Some(1) => {
+                // load game
+                match load_game(SAVE_FILE) {
+                    Ok((mut game, mut objects)) => {
+                        game.monster_defs = monster_defs.clone();
+                        game.vaults = vaults.to_vec();
+                        game.rng = play_rng(game.seed, game.turn);
+                        tcod.replay.pause();
+                        initialise_fov(tcod, &game.map);
+                        play_game(tcod, &mut game, &mut objects);
+                    }
+                    Err(_e) => {
+                        msgbox("\nNo saved game to load.\n", 24, tcod);
+                        continue;
+                    }
+                }
+            }
+            Some(choice @ (2 | 3)) => {
+                if choice == 2 {
+                    // switch to the next palette
+                    tcod.options.palette = tcod.options.palette.next();
+                } else {
+                    // animate steps, or not
+                    tcod.options.move_ms = if tcod.options.move_ms > 0 { 0 } else { MOVE_MS };
+                }
+                // and keep it for next time
+                if let Err(err) = save_options(&tcod.options) {
+                    let text = format!("\nCould not save the options:\n{}\n", err);
+                    msgbox(&text, 50, tcod);
+                }
+            }
+            Some(4) => {
+                // quit
+                break;
+            }
+            _ => {}
+        }
+    }
+}
+
+// read `--seed <n>` from the command line, if given
+fn parse_seed(args: &[String]) -> Result<Option<u64>, String> {
+    match args.iter().position(|arg| arg == "--seed") {
+        None => Ok(None),
+        Some(i) => {
+            let value = args.get(i + 1).ok_or("--seed needs a value")?;
+            value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid seed: {}", value))
+        }
+    }
+}
+
+// --record <file> logs the input of every new game, and --replay <file> plays such a
+// log back. a replay brings its own seed
+fn parse_replay(args: &[String], seed: Option<u64>) -> Result<(Replay, Option<u64>), String> {
+    let value = |flag: &str| match args.iter().position(|arg| arg == flag) {
+        None => Ok(None),
+        Some(i) => args.get(i + 1).map(Some).ok_or_else(|| format!("{} needs a file", flag)),
+    };
+    match (value("--record")?, value("--replay")?) {
+        (Some(_), Some(_)) => Err("--record and --replay can't be used together".to_string()),
+        (Some(path), None) => Ok((Replay::record(path), seed)),
+        (None, Some(path)) => {
+            let (replay, logged_seed) = Replay::play(path)?;
+            if seed.is_some_and(|seed| seed != logged_seed) {
+                return Err(format!("{} was recorded with seed {}", path, logged_seed));
+            }
+            Ok((replay, Some(logged_seed)))
+        }
+        (None, None) => Ok((Replay::off(), seed)),
+    }
+}
+
+// the tcod window, or the terminal when started with --terminal or built without tcod
+fn open_backend(
+    args: &[String],
+    fps: i32,
+    tileset: &Tileset,
+) -> Result<Box<dyn Backend>, String> {
+    let terminal = !cfg!(feature = "tcod") || args.iter().any(|arg| arg == "--terminal");
+    #[cfg(feature = "tcod")]
+    if !terminal {
+        let backend = TcodBackend::new(SCREEN_WIDTH, SCREEN_HEIGHT, fps, tileset);
+        return Ok(Box::new(backend));
+    }
+    #[cfg(feature = "terminal")]
+    if terminal {
+        let backend = TerminalBackend::new(SCREEN_WIDTH, SCREEN_HEIGHT, fps)?;
+        return Ok(Box::new(backend));
+    }
+    let _ = (fps, tileset, terminal);
+    Err("--terminal needs a build with the \"terminal\" feature".to_string())
+}
+
+// the map window and the panel below it have to fit on the screen, or part of them is
+// silently cut off. the map itself can be any size, the camera scrolls over it
+fn check_layout() -> Result<(), String> {
+    if CAMERA_WIDTH > SCREEN_WIDTH {
+        return Err(format!(
+            "The map window is {} wide, but the screen only {}",
+            CAMERA_WIDTH, SCREEN_WIDTH
+        ));
+    }
+    if CAMERA_HEIGHT + PANEL_HEIGHT > SCREEN_HEIGHT {
+        return Err(format!(
+            "The map window ({}) and the panel ({}) are taller than the screen ({})",
+            CAMERA_HEIGHT, PANEL_HEIGHT, SCREEN_HEIGHT
+        ));
+    }
+    if MSG_WIDTH <= 0 {
+        return Err(format!(
+            "No room left for messages next to the {} wide health bar",
+            BAR_WIDTH
+        ));
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let seed = match parse_seed(&args) {
+        Ok(seed) => seed,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let (replay, seed) = match parse_replay(&args, seed) {
+        Ok(replay) => replay,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(err) = check_layout() {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+
+    // load the game data first, so a broken file is reported before anything starts
+    let monster_defs = match load_monster_defs("monsters.json") {
+        Ok(defs) => defs,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let vaults = match load_vaults("vaults.txt") {
+        Ok(vaults) => vaults,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // a missing or broken key map isn't fatal, the default keys still work
+    let key_bindings = KeyBindings::load("keys.toml").unwrap_or_else(|err| {
+        eprintln!("Warning: using the default keys, could not load {}", err);
+        KeyBindings::default()
+    });
+
+    // the options come first, the backend needs the frame rate
+    let options = load_options();
+    // the terminal has characters of its own, a font sheet means nothing there
+    let tileset = if args.iter().any(|arg| arg == "--terminal") {
+        Tileset::default()
+    } else {
+        Tileset::load(TILESET_FILE)
+    };
+    let backend = match open_backend(&args, options.fps, &tileset) {
+        Ok(backend) => backend,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut tcod = Tcod {
+        backend,
+        screen: Offscreen::new(SCREEN_WIDTH, SCREEN_HEIGHT),
+        con: Offscreen::new(CAMERA_WIDTH, CAMERA_HEIGHT),
+        panel: Offscreen::new(SCREEN_WIDTH, PANEL_HEIGHT),
+        fov: FovMap::new(MAP_WIDTH, MAP_HEIGHT),
+        key: Default::default(),
+        mouse: Default::default(),
+        camera: Default::default(),
+        key_bindings,
+        noise: Noise::new(&mut rand::rng()),
+        started: Instant::now(),
+        sounds: Sounds::load("assets/sfx"),
+        replay,
+        options,
+        tileset,
+        debug: args.iter().any(|arg| arg == "--debug"),
+        look: None,
+    };
+
+    // a replay goes straight into its game, the main menu only comes after
+    if tcod.replay.is_playing() {
+        let difficulty = tcod.replay.difficulty();
+        match new_game(&mut tcod, &monster_defs, &vaults, seed, difficulty) {
+            Ok((mut game, mut objects)) => play_game(&mut tcod, &mut game, &mut objects),
+            Err(err) => {
+                let text = format!("\nCould not create the dungeon:\n{}\n", err);
+                msgbox(&text, 50, &mut tcod);
+            }
+        }
+    }
+
+    main_menu(&mut tcod, &monster_defs, &vaults, seed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use crate::backend::KeyCode;
+    use crate::map::{Generator, Tile, new_gold};
+    use crate::object::player_move_or_attack;
+
+    // a backend without a window, which presses the given keys one after the other and
+    // then nothing
+    struct NoWindow {
+        keys: VecDeque<char>,
+        closed: bool,
+    }
+
+    impl NoWindow {
+        fn next_key(&mut self) -> Key {
+            self.keys.pop_front().map_or_else(Key::default, |printable| Key {
+                code: KeyCode::Text,
+                printable,
+                alt: false,
+            })
+        }
+    }
+
+    impl Backend for NoWindow {
+        fn dimensions(&self) -> (i32, i32) {
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        }
+        fn put_char(&mut self, _x: i32, _y: i32, _c: char, _fg: Color) {}
+        fn set_bg(&mut self, _x: i32, _y: i32, _bg: Color) {}
+        fn flush(&mut self) {}
+        fn poll_event(&mut self) -> Option<Input> {
+            if self.keys.is_empty() {
+                None
+            } else {
+                Some(Input::Key(self.next_key()))
+            }
+        }
+        fn wait_key(&mut self) -> Key {
+            self.next_key()
+        }
+        fn wait_event(&mut self) -> Input {
+            Input::Key(self.next_key())
+        }
+        fn wait_event_timeout(&mut self, _timeout: Duration) -> Option<Input> {
+            self.poll_event()
+        }
+        fn window_closed(&self) -> bool {
+            self.closed
+        }
+    }
+
+    pub(crate) fn tcod() -> Tcod {
+        tcod_pressing("")
+    }
+
+    // a Tcod whose backend presses the given keys, in order
+    pub(crate) fn tcod_pressing(keys: &str) -> Tcod {
+        Tcod {
+            backend: Box::new(NoWindow {
+                keys: keys.chars().collect(),
+                closed: false,
+            }),
+            screen: Offscreen::new(SCREEN_WIDTH, SCREEN_HEIGHT),
+            con: Offscreen::new(CAMERA_WIDTH, CAMERA_HEIGHT),
+            panel: Offscreen::new(SCREEN_WIDTH, PANEL_HEIGHT),
+            fov: FovMap::new(MAP_WIDTH, MAP_HEIGHT),
+            key: Default::default(),
+            mouse: Default::default(),
+            camera: Default::default(),
+            key_bindings: KeyBindings::default(),
+            noise: Noise::new(&mut rand::rng()),
+            started: Instant::now(),
+            sounds: Sounds::load("assets/sfx"),
+            replay: Replay::off(),
+            options: Options::default(),
+            debug: false,
+            look: None,
+            tileset: Tileset::default(),
+        }
+    }
+
+    // a game on an empty level, for the tests to put things in
+    pub(crate) fn game() -> Game {
+        Game {
+            map: Map::new(MAP_WIDTH, MAP_HEIGHT, Tile::empty()),
+            map_config: MapGenConfig::default(),
+            state: GameState::Playing,
+            messages: Messages::new(),
+            inventory: vec![],
+            dungeon_level: 1,
+            monster_defs: MonsterDefs::new(),
+            seed: 1,
+            player_distances: DijkstraMap::default(),
+            vaults: vec![],
+            has_amulet: false,
+            turn: 0,
+            sounds: vec![],
+            scent: ScentMap::default(),
+            rng: play_rng(1, 0),
+            identified: vec![],
+            item_labels: vec![],
+            projectiles: vec![],
+            god_mode: false,
+            tick: 0,
+            levels: HashMap::new(),
+            difficulty: Difficulty::Normal,
+            offspring: vec![],
+        }
+    }
+
+    // a fighter with the given stats, standing at (x, y)
+    pub(crate) fn fighter(name: &str, x: i32, y: i32, hp: i32, defense: i32, power: i32) -> Object {
+        let mut object = Object::new(x, y, 'o', name, WHITE, true);
+        object.alive = true;
+        object.fighter = Some(Fighter {
+            base_max_hp: hp,
+            hp,
+            base_defense: defense,
+            base_power: power,
+            xp: 10,
+            on_death: if name == "player" {
+                DeathCallback::Player
+            } else {
+                DeathCallback::Monster
+            },
+            on_hit: None,
+            ability: None,
+        });
+        object
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut game = game();
+        let mut potion = Object::new(0, 0, '!', "healing potion", VIOLET, false);
+        potion.item = Some(Item::Heal);
+        game.inventory.push(potion);
+        let objects = vec![fighter("player", 12, 7, 30, 2, 5), fighter("orc", 3, 4, 10, 0, 3)];
+        let path = std::env::temp_dir().join(format!("savegame-test-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        save_game(&game, &objects, path).unwrap();
+        let (loaded, loaded_objects) = load_game(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded_objects[PLAYER].pos(), (12, 7));
+        assert_eq!(loaded_objects.len(), 2);
+        assert_eq!(loaded.inventory.len(), 1);
+        assert_eq!(loaded.inventory[0].item, Some(Item::Heal));
+    }
+
+    #[test]
+    fn level_up_thresholds() {
+        assert_eq!(level_up_xp(1), 350);
+        assert_eq!(level_up_xp(2), 500);
+        assert_eq!(level_up_xp(3), 650);
+    }
+
+    #[test]
+    fn regenerates_at_the_interval() {
+        let fov = FovMap::new(MAP_WIDTH, MAP_HEIGHT);
+        let game = game();
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5)];
+        objects[PLAYER].fighter.as_mut().unwrap().hp = 20;
+        let interval = game.difficulty.regen_interval();
+        regenerate(interval - 1, &fov, &game, &mut objects);
+        assert_eq!(objects[PLAYER].fighter.unwrap().hp, 20);
+        regenerate(interval, &fov, &game, &mut objects);
+        assert_eq!(objects[PLAYER].fighter.unwrap().hp, 21);
+        regenerate(interval + 1, &fov, &game, &mut objects);
+        assert_eq!(objects[PLAYER].fighter.unwrap().hp, 21);
+    }
+
+    #[test]
+    fn regeneration_stops_at_max_hp_and_death() {
+        let fov = FovMap::new(MAP_WIDTH, MAP_HEIGHT);
+        let game = game();
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5)];
+        let interval = game.difficulty.regen_interval();
+        regenerate(interval, &fov, &game, &mut objects);
+        assert_eq!(objects[PLAYER].fighter.unwrap().hp, 30);
+        objects[PLAYER].fighter.as_mut().unwrap().hp = 0;
+        objects[PLAYER].alive = false;
+        regenerate(interval, &fov, &game, &mut objects);
+        assert_eq!(objects[PLAYER].fighter.unwrap().hp, 0);
+    }
+
+    #[test]
+    fn levels_up_once_at_the_threshold() {
+        // the level up menu gets answered with constitution, every time it comes up
+        let mut tcod = tcod_pressing("aaa");
+        let mut game = game();
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5)];
+        objects[PLAYER].fighter.as_mut().unwrap().xp = level_up_xp(1) - 1;
+        level_up(&mut tcod, &mut game, &mut objects);
+        assert_eq!(objects[PLAYER].level, 1);
+        objects[PLAYER].fighter.as_mut().unwrap().xp += 1;
+        level_up(&mut tcod, &mut game, &mut objects);
+        assert_eq!(objects[PLAYER].level, 2);
+        assert_eq!(objects[PLAYER].fighter.unwrap().xp, 0);
+        assert_eq!(objects[PLAYER].fighter.unwrap().base_max_hp, 50);
+        // with no xp left over, there isn't another one
+        level_up(&mut tcod, &mut game, &mut objects);
+        assert_eq!(objects[PLAYER].level, 2);
+    }
+
+    #[test]
+    fn using_a_potion_identifies_the_others() {
+        let mut tcod = tcod();
+        let mut game = game();
+        game.item_labels = vec![(Item::Heal, "murky potion".to_string())];
+        game.inventory.push(new_item(Item::Heal, 0, 0));
+        game.inventory.push(new_item(Item::Heal, 0, 0));
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5)];
+        objects[PLAYER].fighter.as_mut().unwrap().hp = 10;
+        assert_eq!(game.inventory[1].display_name(&game), "murky potion");
+        use_item(0, &mut tcod, &mut game, &mut objects);
+        // the one left is known by its name now
+        assert_eq!(game.inventory.len(), 1);
+        assert_eq!(game.inventory[0].display_name(&game), game.inventory[0].name);
+        assert!(game.identified.contains(&Item::Heal));
+    }
+
+    #[test]
+    fn closing_the_window_leaves_the_level_up_for_later() {
+        let mut tcod = tcod();
+        tcod.backend = Box::new(NoWindow {
+            keys: VecDeque::new(),
+            closed: true,
+        });
+        let mut game = game();
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5)];
+        objects[PLAYER].fighter.as_mut().unwrap().xp = level_up_xp(1);
+        level_up(&mut tcod, &mut game, &mut objects);
+        assert_eq!(objects[PLAYER].level, 1);
+        assert_eq!(objects[PLAYER].fighter.unwrap().xp, level_up_xp(1));
+        // and the game gets left, which saves it
+        assert_eq!(handle_keys(&mut tcod, &mut game, &mut objects), PlayerAction::Exit);
+    }
+
+    #[test]
+    fn monster_behind_a_wall_stays_asleep() {
+        let mut game = game();
+        for y in 0..MAP_HEIGHT {
+            *game.map.get_mut(3, y).unwrap() = Tile::wall();
+        }
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5), fighter("orc", 5, 1, 10, 0, 3)];
+        objects[1].ai = Some(doze_off(Ai::Basic { alert: 0 }));
+        ai_take_turn(1, &mut game, &mut objects);
+        assert!(matches!(objects[1].ai, Some(Ai::Asleep { .. })));
+        assert_eq!(objects[1].pos(), (5, 1));
+        // with the wall gone, it sees the player and wakes up
+        *game.map.get_mut(3, 1).unwrap() = Tile::empty();
+        ai_take_turn(1, &mut game, &mut objects);
+        assert!(matches!(objects[1].ai, Some(Ai::Basic { .. })));
+    }
+
+    #[test]
+    fn archer_shoots_from_two_tiles() {
+        let mut game = game();
+        let archer = fighter("archer", 3, 1, 10, 0, 4);
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5), archer];
+        objects[1].ai = Some(Ai::Ranged { range: 5, alert: 0 });
+        ai_take_turn(1, &mut game, &mut objects);
+        assert_eq!(objects[PLAYER].fighter.unwrap().hp, 28);
+        // it stays where it is, and an arrow flies
+        assert_eq!(objects[1].pos(), (3, 1));
+        assert_eq!(game.projectiles.len(), 1);
+    }
+
+    #[test]
+    fn walking_onto_gold_picks_it_up() {
+        let mut game = game();
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5), new_gold(25, 2, 1)];
+        player_move_or_attack(1, 0, &mut game, &mut objects);
+        collect_gold(&mut game, &mut objects);
+        assert_eq!(objects[PLAYER].pos(), (2, 1));
+        assert_eq!(objects[PLAYER].gold, 25);
+        assert_eq!(objects.len(), 1);
+        assert!(game.inventory.is_empty());
+    }
+
+    #[test]
+    fn potions_stack_when_picked_up() {
+        let mut game = game();
+        let mut objects = vec![
+            fighter("player", 1, 1, 30, 2, 5),
+            new_item(Item::Heal, 1, 1),
+            new_item(Item::Heal, 1, 1),
+        ];
+        pick_item_up(1, &mut game, &mut objects);
+        pick_item_up(1, &mut game, &mut objects);
+        assert_eq!(objects.len(), 1);
+        assert_eq!(game.inventory.len(), 1);
+        assert_eq!(game.inventory[0].count, 2);
+        assert_eq!(game.inventory[0].stack_name(&game), "healing potion (x2)");
+        // using one leaves the rest of the stack
+        objects[PLAYER].fighter.as_mut().unwrap().hp = 10;
+        use_item(0, &mut tcod(), &mut game, &mut objects);
+        assert_eq!(game.inventory[0].count, 1);
+    }
+
+    #[test]
+    fn equipment_never_stacks() {
+        let mut game = game();
+        let mut objects = vec![
+            fighter("player", 1, 1, 30, 2, 5),
+            new_item(Item::Sword, 1, 1),
+            new_item(Item::Sword, 1, 1),
+        ];
+        pick_item_up(1, &mut game, &mut objects);
+        pick_item_up(1, &mut game, &mut objects);
+        assert_eq!(game.inventory.len(), 2);
+    }
+
+    #[test]
+    fn greataxe_and_shield_exclude_each_other() {
+        let (mut tcod, mut game) = (tcod(), game());
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5)];
+        let equipped = |game: &Game| -> Vec<bool> {
+            game.inventory
+                .iter()
+                .map(|item| item.equipment.unwrap().equipped)
+                .collect()
+        };
+        for item in [Item::Sword, Item::Shield, Item::Greataxe] {
+            game.inventory.push(new_item(item, 0, 0));
+        }
+        use_item(0, &mut tcod, &mut game, &mut objects);
+        use_item(1, &mut tcod, &mut game, &mut objects);
+        assert_eq!(equipped(&game), [true, true, false]);
+        // the greataxe takes both hands
+        use_item(2, &mut tcod, &mut game, &mut objects);
+        assert_eq!(equipped(&game), [false, false, true]);
+        // and the shield needs a hand free of it again
+        use_item(1, &mut tcod, &mut game, &mut objects);
+        assert_eq!(equipped(&game), [false, true, false]);
+    }
+
+    #[test]
+    fn going_back_up_restores_the_level() {
+        let (mut tcod, mut game) = (tcod(), game());
+        game.monster_defs = load_monster_defs("monsters.json").unwrap();
+        *game.map.get_mut(5, 5).unwrap() = Tile::wall();
+        game.map.get_mut(1, 1).unwrap().explored = true;
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5), fighter("orc", 8, 8, 10, 0, 3)];
+        let mut stairs = Object::new(1, 1, '>', "stairs", WHITE, false);
+        stairs.always_visible = true;
+        objects.push(stairs);
+        let first_map = game.map.clone();
+
+        next_level(&mut tcod, &mut game, &mut objects).unwrap();
+        assert_eq!(game.dungeon_level, 2);
+        assert_ne!(game.map, first_map);
+        previous_level(&mut tcod, &mut game, &mut objects).unwrap();
+        assert_eq!(game.dungeon_level, 1);
+        // the explored flags are part of the map too
+        assert_eq!(game.map, first_map);
+        let names: Vec<_> = objects.iter().map(|object| object.name.as_str()).collect();
+        assert_eq!(names, ["player", "orc", "stairs"]);
+        assert_eq!(objects[PLAYER].pos(), (1, 1));
+    }
+
+    #[test]
+    fn too_heavy_items_stay_on_the_floor() {
+        let mut game = game();
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5)];
+        // fill up to just under the capacity with potions
+        let mut potions = new_item(Item::Heal, 0, 0);
+        potions.count = carry_capacity(&objects[PLAYER]) / Item::Heal.weight();
+        game.inventory.push(potions);
+        objects.push(new_item(Item::Greataxe, 1, 1));
+        let action = pick_item_up(1, &mut game, &mut objects);
+        assert_eq!(action, PlayerAction::DidntTakeTurn);
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[1].item, Some(Item::Greataxe));
+        assert_eq!(game.inventory.len(), 1);
+    }
+
+    #[test]
+    fn a_level_that_cant_be_made_changes_nothing() {
+        let (mut tcod, mut game) = (tcod(), game());
+        // a cave this small never has enough open space
+        game.map_config = MapGenConfig {
+            width: 4,
+            height: 4,
+            room_min_size: 1,
+            room_max_size: 1,
+            max_rooms: 1,
+            generator: Generator::Caves,
+        };
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5), fighter("orc", 8, 8, 10, 0, 3)];
+        objects[PLAYER].fighter.as_mut().unwrap().hp = 10;
+        let first_map = game.map.clone();
+
+        assert!(next_level(&mut tcod, &mut game, &mut objects).is_err());
+        assert_eq!(game.dungeon_level, 1);
+        assert_eq!(game.map, first_map);
+        assert!(game.levels.is_empty());
+        let names: Vec<_> = objects.iter().map(|object| object.name.as_str()).collect();
+        assert_eq!(names, ["player", "orc"]);
+        // and the rest that comes with a new level didn't happen either
+        assert_eq!(objects[PLAYER].fighter.unwrap().hp, 10);
+        assert_eq!(objects[PLAYER].pos(), (1, 1));
+    }
+
+    #[test]
+    fn only_steps_to_a_neighbouring_tile_are_animated() {
+        let (tcod, game) = (tcod(), game());
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5), fighter("orc", 5, 5, 10, 0, 3)];
+        let positions: Vec<_> = objects.iter().map(|object| (object.id, object.pos())).collect();
+        // the player steps aside, the orc teleports away
+        objects[PLAYER].set_pos(2, 2);
+        objects[1].set_pos(20, 5);
+        let steps = steps_taken(&positions, &tcod, &game, &objects);
+        let moves: Vec<_> = steps.iter().map(|step| (step.from, step.to)).collect();
+        assert_eq!(moves, [((1, 1), (2, 2))]);
+    }
+
+    #[test]
+    fn a_key_cuts_the_step_animation_short() {
+        let (mut tcod, mut game) = (tcod_pressing("x"), game());
+        let objects = vec![fighter("player", 2, 1, 30, 2, 5)];
+        let step = Step {
+            from: (1, 1),
+            to: (2, 1),
+            glyph: '@',
+            color: WHITE,
+        };
+        // the key is handed back for the next turn, instead of being lost
+        let input = animate_steps(&mut tcod, &mut game, &objects, &[step]);
+        assert!(matches!(input, Some(Input::Key(key)) if key.printable == 'x'));
+        // and with the animation turned off, nothing waits for a key at all
+        tcod.options.move_ms = 0;
+        assert!(animate_steps(&mut tcod, &mut game, &objects, &[step]).is_none());
     }
 }
@@ -0,0 +1,1828 @@
+use std::cmp;
+use std::collections::HashSet;
+use std::fs;
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Difficulty, MAP_HEIGHT, MAP_WIDTH, MONSTER_ALERT_TURNS, MonsterDef, MonsterDefs, PLAYER, scale,
+};
+use crate::colors::*;
+use crate::line::Line;
+use crate::object::{Ai, Chest, DeathCallback, Equipment, Fighter, Item, Object, Slot, is_blocked};
+
+const ROOM_MAX_SIZE: i32 = 10;
+const ROOM_MIN_SIZE: i32 = 6;
+const MAX_ROOMS: i32 = 30;
+const MAX_ROOM_MONSTERS: i32 = 3;
+const MAX_ROOM_ITEMS: i32 = 2;
+// chance for a monster to carry an item
+const MONSTER_LOOT_CHANCE: f32 = 0.2;
+// chances for a room to have a pile of gold lying around, and for a monster to carry some.
+// the amount is up to GOLD_PER_LEVEL for every dungeon level
+const ROOM_GOLD_CHANCE: f32 = 0.3;
+const MONSTER_GOLD_CHANCE: f32 = 0.3;
+const GOLD_PER_LEVEL: i32 = 10;
+// chance for a room to have a chest, for a chest to be empty, and how much a full one holds
+const ROOM_CHEST_CHANCE: f32 = 0.1;
+const CHEST_EMPTY_CHANCE: f32 = 0.25;
+const CHEST_MAX_ITEMS: i32 = 3;
+// chances for a room to get a pool of water, lava or a pile of rubble
+const ROOM_WATER_CHANCE: f32 = 0.1;
+const ROOM_LAVA_CHANCE: f32 = 0.05;
+const ROOM_RUBBLE_CHANCE: f32 = 0.1;
+// chance for a room to be replaced by one of the hand-made vaults
+const VAULT_CHANCE: f32 = 0.1;
+// chances for a room to be round or cross-shaped instead of a rectangle
+const ROOM_CIRCLE_CHANCE: f32 = 0.2;
+const ROOM_CROSS_CHANCE: f32 = 0.2;
+// chance for a room to hide a trap
+const ROOM_TRAP_CHANCE: f32 = 0.15;
+// caves start out with this share of wall tiles, then get smoothed a few times
+const CAVE_WALL_DENSITY: f32 = 0.45;
+const CAVE_SMOOTHING_PASSES: i32 = 4;
+// caves whose largest open region is smaller than this share of the map are rerolled
+const CAVE_MIN_OPEN_SHARE: f32 = 0.3;
+const CAVE_MAX_ATTEMPTS: i32 = 10;
+// how many times a level is made over when its stairs can't be reached, before giving up
+const MAP_MAX_ATTEMPTS: i32 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Tile {
+    pub kind: TileKind,
+    pub blocked: bool,
+    pub block_sight: bool,
+    pub explored: bool,
+    pub door: Option<Door>,
+    pub trap: Option<Trap>,
+}
+
+// a hidden trap, set off by anything that steps on its tile
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Trap {
+    pub kind: TrapKind,
+    // hidden traps aren't drawn until something sets them off
+    pub revealed: bool,
+    // set off already and harmless now, see trigger_trap
+    #[serde(default)]
+    pub spent: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TrapKind {
+    // damages whatever steps on it
+    Spike,
+    // moves whatever steps on it to a random spot of the level
+    Teleport,
+}
+
+// what a tile is made of, which decides how it can be crossed and seen through
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TileKind {
+    Floor,
+    Wall,
+    // shallow, slows down movement
+    Water,
+    // has to be swum, which is slow too. only some monsters go in, and heavy gear the
+    // player isn't wearing can be lost in it
+    DeepWater,
+    // burns whatever ends its turn on it
+    Lava,
+    // only blocks sight from afar, see RUBBLE_SIGHT_RANGE
+    Rubble,
+}
+
+impl TileKind {
+    pub fn blocks(self) -> bool {
+        self == TileKind::Wall
+    }
+    pub fn blocks_sight(self) -> bool {
+        self == TileKind::Wall
+    }
+    // the number of turns it takes to step onto a tile of this kind
+    pub fn move_cost(self) -> i32 {
+        match self {
+            TileKind::Water | TileKind::DeepWater => 2,
+            _ => 1,
+        }
+    }
+}
+
+// a door between a room and a corridor. closed doors block movement and sight
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Door {
+    pub open: bool,
+}
+
+impl Tile {
+    pub fn new(kind: TileKind) -> Self {
+        Tile {
+            kind,
+            blocked: kind.blocks(),
+            block_sight: kind.blocks_sight(),
+            explored: false,
+            door: None,
+            trap: None,
+        }
+    }
+    pub fn empty() -> Self {
+        Tile::new(TileKind::Floor)
+    }
+    pub fn wall() -> Self {
+        Tile::new(TileKind::Wall)
+    }
+    pub fn closed_door() -> Self {
+        Tile {
+            blocked: true,
+            block_sight: true,
+            door: Some(Door { open: false }),
+            ..Tile::new(TileKind::Floor)
+        }
+    }
+    pub fn is_closed_door(&self) -> bool {
+        self.door.is_some_and(|door| !door.open)
+    }
+    // open the door, if this is one, so it no longer blocks anything
+    pub fn open_door(&mut self) {
+        if let Some(ref mut door) = self.door {
+            door.open = true;
+            self.blocked = false;
+            self.block_sight = false;
+        }
+    }
+}
+
+// the tiles of a level, stored row by row in a single flat vec
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Map {
+    pub width: i32,
+    pub height: i32,
+    pub tiles: Vec<Tile>,
+}
+
+impl Map {
+    pub fn new(width: i32, height: i32, fill: Tile) -> Self {
+        Map {
+            width,
+            height,
+            tiles: vec![fill; (width * height) as usize],
+        }
+    }
+    pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+        (0..self.width).contains(&x) && (0..self.height).contains(&y)
+    }
+    // position of (x, y) in the flat tile vec, callers must check the bounds first
+    pub fn idx(&self, x: i32, y: i32) -> usize {
+        (y * self.width + x) as usize
+    }
+    // tile at (x, y), or None outside the map
+    pub fn get(&self, x: i32, y: i32) -> Option<&Tile> {
+        if self.in_bounds(x, y) {
+            self.tiles.get(self.idx(x, y))
+        } else {
+            None
+        }
+    }
+    pub fn get_mut(&mut self, x: i32, y: i32) -> Option<&mut Tile> {
+        if self.in_bounds(x, y) {
+            let idx = self.idx(x, y);
+            self.tiles.get_mut(idx)
+        } else {
+            None
+        }
+    }
+}
+
+// the algorithm used to lay out a level
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Generator {
+    // rooms placed at random where they fit, each joined to the previous one
+    #[default]
+    Random,
+    // a binary space partition with one room per leaf, see make_map_bsp
+    Bsp,
+    // open caves grown with a cellular automaton, see make_map_caves
+    Caves,
+}
+
+// parameters for generating a level
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapGenConfig {
+    pub width: i32,
+    pub height: i32,
+    pub room_min_size: i32,
+    pub room_max_size: i32,
+    // only used by the random generator, the others fill the whole map
+    pub max_rooms: i32,
+    pub generator: Generator,
+}
+
+impl Default for MapGenConfig {
+    fn default() -> Self {
+        MapGenConfig {
+            width: MAP_WIDTH,
+            height: MAP_HEIGHT,
+            room_min_size: ROOM_MIN_SIZE,
+            room_max_size: ROOM_MAX_SIZE,
+            max_rooms: MAX_ROOMS,
+            generator: Generator::default(),
+        }
+    }
+}
+
+impl MapGenConfig {
+    // check the parameters make sense before generating anything with them
+    pub fn validate(&self) -> Result<(), String> {
+        if self.room_min_size < 1 {
+            return Err(format!("room_min_size must be at least 1, got {}", self.room_min_size));
+        }
+        if self.room_min_size > self.room_max_size {
+            return Err(format!(
+                "room_min_size ({}) is greater than room_max_size ({})",
+                self.room_min_size, self.room_max_size
+            ));
+        }
+        // rooms keep a one-tile border of wall around them, see make_map
+        if self.room_max_size + 2 >= self.width || self.room_max_size + 2 >= self.height {
+            return Err(format!(
+                "room_max_size ({}) does not fit in a {}x{} map",
+                self.room_max_size, self.width, self.height
+            ));
+        }
+        if self.max_rooms < 1 {
+            return Err(format!("max_rooms must be at least 1, got {}", self.max_rooms));
+        }
+        Ok(())
+    }
+}
+
+// rectangle on the map, used to characterize a room.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Rect {
+    x1: i32,
+    y1: i32,
+    x2: i32,
+    y2: i32,
+}
+impl Rect {
+    pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Rect {
+            x1: x,
+            y1: y,
+            x2: x + w,
+            y2: y + h,
+        }
+    }
+    pub fn center(&self) -> (i32, i32) {
+        let center_x = (self.x1 + self.x2) / 2;
+        let center_y = (self.y1 + self.y2) / 2;
+        (center_x, center_y)
+    }
+    pub fn intersects_with(&self, other: &Rect) -> bool {
+        // returns true if this rectangle intersects with another one.
+        // the edges are the room's walls (create_room only carves the inside),
+        // so rooms that merely share an edge count as intersecting too: that way
+        // two accepted rooms always keep at least one wall tile between them
+        (self.x1 <= other.x2)
+            && (self.x2 >= other.x1)
+            && (self.y1 <= other.y2)
+            && (self.y2 >= other.y1)
+    }
+}
+
+// the outline carved out of a room's rectangle. every shape includes the center, which
+// is where corridors, the player and the stairs go
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RoomShape {
+    Rectangle,
+    Circle,
+    Cross,
+}
+
+impl RoomShape {
+    fn random(rng: &mut StdRng) -> Self {
+        let dice = rng.random::<f32>();
+        if dice < ROOM_CIRCLE_CHANCE {
+            RoomShape::Circle
+        } else if dice < ROOM_CIRCLE_CHANCE + ROOM_CROSS_CHANCE {
+            RoomShape::Cross
+        } else {
+            RoomShape::Rectangle
+        }
+    }
+
+    // whether the shape covers the given tile of the room
+    fn contains(self, room: Rect, x: i32, y: i32) -> bool {
+        let (center_x, center_y) = room.center();
+        let (w, h) = (room.x2 - room.x1, room.y2 - room.y1);
+        match self {
+            RoomShape::Rectangle => true,
+            RoomShape::Circle => {
+                // the widest circle that fits, slightly rounded out so it's not so spiky
+                let radius = (cmp::min(w, h) - 1) / 2;
+                (x - center_x).pow(2) + (y - center_y).pow(2) <= radius * radius + radius
+            }
+            RoomShape::Cross => {
+                // two bars through the center, each about a third as thick as the room
+                let (arm_w, arm_h) = (cmp::max(w / 6, 1), cmp::max(h / 6, 1));
+                (x - center_x).abs() <= arm_w || (y - center_y).abs() <= arm_h
+            }
+        }
+    }
+}
+
+fn create_room(room: Rect, shape: RoomShape, map: &mut Map) {
+    // go through the tiles in the rectangle and make the ones inside the shape passable
+    for x in (room.x1 + 1)..room.x2 {
+        for y in (room.y1 + 1)..room.y2 {
+            if shape.contains(room, x, y)
+                && let Some(tile) = map.get_mut(x, y)
+            {
+                *tile = Tile::empty();
+            }
+        }
+    }
+}
+
+// a hand-made room, stamped into the map as it was drawn. its rectangle is one tile
+// bigger on each side, so the drawing is exactly the inside of the room
+#[derive(Clone, Debug)]
+pub struct Vault {
+    rows: Vec<Vec<VaultCell>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum VaultCell {
+    Wall,
+    Floor,
+    // floor with a monster or an item on it, picked from the spawn tables
+    Monster,
+    Item,
+}
+
+impl Vault {
+    fn width(&self) -> i32 {
+        self.rows[0].len() as i32
+    }
+
+    fn height(&self) -> i32 {
+        self.rows.len() as i32
+    }
+
+    // the cell at the room's center, where the corridors come in
+    fn center(&self) -> VaultCell {
+        self.rows[(self.rows.len() - 1) / 2][(self.rows[0].len() - 1) / 2]
+    }
+
+    // carve the vault into the room's rectangle, which has to be of the vault's size
+    fn stamp(&self, room: Rect, map: &mut Map) {
+        for (dy, row) in self.rows.iter().enumerate() {
+            for (dx, &cell) in row.iter().enumerate() {
+                let (x, y) = (room.x1 + 1 + dx as i32, room.y1 + 1 + dy as i32);
+                if cell != VaultCell::Wall
+                    && let Some(tile) = map.get_mut(x, y)
+                {
+                    *tile = Tile::empty();
+                }
+            }
+        }
+    }
+
+    // put a monster or an item on each of the vault's spawn markers
+    fn place_objects(
+        &self,
+        room: Rect,
+        map: &Map,
+        objects: &mut Vec<Object>,
+        level: u32,
+        monster_defs: &MonsterDefs,
+        rng: &mut StdRng,
+    ) {
+        let item_chances = item_chances(level);
+        for (dy, row) in self.rows.iter().enumerate() {
+            for (dx, &cell) in row.iter().enumerate() {
+                let (x, y) = (room.x1 + 1 + dx as i32, room.y1 + 1 + dy as i32);
+                // the player might start on a marker
+                if is_blocked(x, y, map, objects) {
+                    continue;
+                }
+                match cell {
+                    VaultCell::Monster => {
+                        if let Some(monster) =
+                            new_monster(x, y, level, monster_defs, &item_chances, rng)
+                        {
+                            add_monster(monster, room, map, objects, level, monster_defs, rng);
+                        }
+                    }
+                    VaultCell::Item => {
+                        let item = random_choice_weighted(&item_chances, rng);
+                        objects.extend(item.map(|&item| new_item(item, x, y)));
+                    }
+                    VaultCell::Wall | VaultCell::Floor => {}
+                }
+            }
+        }
+    }
+}
+
+// read the vaults from a text file. each vault is drawn with '#' for walls, '.' for
+// floor, 'o' for a monster and '!' for an item, and vaults are separated by blank lines.
+// lines starting with ';' are comments
+pub fn load_vaults(path: &str) -> Result<Vec<Vault>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let mut vaults = vec![];
+    // the vault being read, and the line it starts at
+    let mut rows: Vec<Vec<VaultCell>> = vec![];
+    let mut start = 0;
+    let lines = text.lines().chain([""]).enumerate();
+    for (number, line) in lines.filter(|(_, line)| !line.starts_with(';')) {
+        let line = line.trim_end();
+        if line.is_empty() {
+            if !rows.is_empty() {
+                let vault = Vault {
+                    rows: std::mem::take(&mut rows),
+                };
+                if vault.center() == VaultCell::Wall {
+                    return Err(format!(
+                        "{}: the vault at line {} needs floor at its center",
+                        path, start
+                    ));
+                }
+                vaults.push(vault);
+            }
+            continue;
+        }
+        if rows.is_empty() {
+            start = number + 1;
+        }
+        let row = line
+            .chars()
+            .map(|c| match c {
+                '#' => Ok(VaultCell::Wall),
+                '.' => Ok(VaultCell::Floor),
+                'o' => Ok(VaultCell::Monster),
+                '!' => Ok(VaultCell::Item),
+                _ => Err(format!("{} line {}: unknown vault tile '{}'", path, number + 1, c)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if rows.first().is_some_and(|first| first.len() != row.len()) {
+            return Err(format!(
+                "{} line {}: all rows of a vault need the same width",
+                path,
+                number + 1
+            ));
+        }
+        rows.push(row);
+    }
+    Ok(vaults)
+}
+
+fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
+    // horizontal tunnel, one tile wide
+    for x in cmp::min(x1, x2)..(cmp::max(x1, x2) + 1) {
+        if let Some(tile) = map.get_mut(x, y) {
+            *tile = Tile::empty();
+        }
+    }
+}
+
+fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
+    // vertical tunnel, one tile wide
+    for y in cmp::min(y1, y2)..(cmp::max(y1, y2) + 1) {
+        if let Some(tile) = map.get_mut(x, y) {
+            *tile = Tile::empty();
+        }
+    }
+}
+
+fn create_tunnel(from: (i32, i32), to: (i32, i32), map: &mut Map, rng: &mut StdRng) {
+    // L-shaped corridor between two points, randomly horizontal-first or vertical-first
+    let (from_x, from_y) = from;
+    let (to_x, to_y) = to;
+    if rng.random() {
+        create_h_tunnel(from_x, to_x, from_y, map);
+        create_v_tunnel(from_y, to_y, to_x, map);
+    } else {
+        create_v_tunnel(from_y, to_y, from_x, map);
+        create_h_tunnel(from_x, to_x, to_y, map);
+    }
+}
+
+// turn the openings that corridors cut into the room walls into closed doors. an opening
+// only counts if it has walls on both sides, like a door frame, so a corridor that runs
+// along a wall doesn't become a row of doors
+fn place_doors(rooms: &[Rect], map: &mut Map) {
+    let blocked = |map: &Map, x, y| map.get(x, y).is_none_or(|tile: &Tile| tile.blocked);
+    let mut doors = vec![];
+    for room in rooms {
+        // the walls around the room, without the corners
+        let horizontal = (room.x1 + 1..room.x2).flat_map(|x| [(x, room.y1), (x, room.y2)]);
+        let vertical = (room.y1 + 1..room.y2).flat_map(|y| [(room.x1, y), (room.x2, y)]);
+        for (x, y) in horizontal.chain(vertical) {
+            let framed = (blocked(map, x - 1, y) && blocked(map, x + 1, y))
+                || (blocked(map, x, y - 1) && blocked(map, x, y + 1));
+            if !blocked(map, x, y) && framed {
+                doors.push((x, y));
+            }
+        }
+    }
+    // only change the map once all the openings are found, a new door would otherwise
+    // count as a frame for the openings next to it. where two room walls are back to
+    // back, the corridor only gets one door instead of two in a row
+    let mut placed: Vec<(i32, i32)> = vec![];
+    for (x, y) in doors {
+        let next_to_door = placed
+            .iter()
+            .any(|&(door_x, door_y)| (door_x - x).abs() + (door_y - y).abs() <= 1);
+        if !next_to_door && let Some(tile) = map.get_mut(x, y) {
+            *tile = Tile::closed_door();
+            placed.push((x, y));
+        }
+    }
+}
+
+// maybe fill a round patch of the room with water, lava or rubble. the center stays
+// floor, since that's where the player, the stairs and the corridors start from
+fn decorate_room(room: Rect, map: &mut Map, rng: &mut StdRng) {
+    let dice = rng.random::<f32>();
+    let kind = if dice < ROOM_WATER_CHANCE {
+        TileKind::Water
+    } else if dice < ROOM_WATER_CHANCE + ROOM_LAVA_CHANCE {
+        TileKind::Lava
+    } else if dice < ROOM_WATER_CHANCE + ROOM_LAVA_CHANCE + ROOM_RUBBLE_CHANCE {
+        TileKind::Rubble
+    } else {
+        return;
+    };
+    let center_x = rng.random_range(room.x1 + 1..room.x2);
+    let center_y = rng.random_range(room.y1 + 1..room.y2);
+    let radius = rng.random_range(1..=2);
+    for x in (room.x1 + 1)..room.x2 {
+        for y in (room.y1 + 1)..room.y2 {
+            let in_patch = (x - center_x).pow(2) + (y - center_y).pow(2) <= radius * radius;
+            // only on the room's floor, a round or cross-shaped room has walls inside
+            // its rectangle too
+            if in_patch
+                && (x, y) != room.center()
+                && let Some(tile) = map.get_mut(x, y)
+                && tile.kind == TileKind::Floor
+                && tile.door.is_none()
+            {
+                // pools are deep in the middle
+                let deep = (x - center_x).pow(2) + (y - center_y).pow(2) < radius;
+                *tile = match kind {
+                    TileKind::Water if deep => Tile::new(TileKind::DeepWater),
+                    _ => Tile::new(kind),
+                };
+            }
+        }
+    }
+}
+
+// maybe hide a trap on a plain floor tile of the room, away from its center
+fn place_trap(room: Rect, map: &mut Map, rng: &mut StdRng) {
+    if rng.random::<f32>() >= ROOM_TRAP_CHANCE {
+        return;
+    }
+    let x = rng.random_range(room.x1 + 1..room.x2);
+    let y = rng.random_range(room.y1 + 1..room.y2);
+    let kind = if rng.random() {
+        TrapKind::Spike
+    } else {
+        TrapKind::Teleport
+    };
+    if (x, y) != room.center()
+        && let Some(tile) = map.get_mut(x, y)
+        && tile.kind == TileKind::Floor
+    {
+        tile.trap = Some(Trap {
+            kind,
+            revealed: false,
+            spent: false,
+        });
+    }
+}
+
+// one step of a spawn table: from this dungeon level on, the value applies
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct Transition {
+    pub level: u32,
+    pub value: u32,
+}
+
+// the value of a spawn table at the given depth: the one from the deepest step that has
+// been reached, or 0 before the first step
+fn from_dungeon_level(table: &[Transition], level: u32) -> u32 {
+    table
+        .iter()
+        .filter(|transition| transition.level <= level)
+        .max_by_key(|transition| transition.level)
+        .map_or(0, |transition| transition.value)
+}
+
+// pick one of the choices, each as likely as its weight. None if all the weights are 0
+fn random_choice_weighted<'a, T>(choices: &'a [(T, u32)], rng: &mut StdRng) -> Option<&'a T> {
+    let total_weight: u32 = choices.iter().map(|(_, weight)| weight).sum();
+    if total_weight == 0 {
+        return None;
+    }
+    let mut roll = rng.random_range(0..total_weight);
+    for (choice, weight) in choices {
+        if roll < *weight {
+            return Some(choice);
+        }
+        roll -= weight;
+    }
+    None
+}
+
+// how likely each item is to be picked, depending on the depth
+const ITEM_CHANCES: &[(Item, &[Transition])] = &[
+    (Item::Heal, &[Transition { level: 1, value: 35 }]),
+    (Item::Regen, &[Transition { level: 3, value: 10 }]),
+    (
+        Item::Lightning,
+        &[Transition { level: 1, value: 5 }, Transition { level: 4, value: 25 }],
+    ),
+    (
+        Item::Fireball,
+        &[Transition { level: 1, value: 5 }, Transition { level: 6, value: 25 }],
+    ),
+    (
+        Item::Confuse,
+        &[Transition { level: 1, value: 5 }, Transition { level: 2, value: 10 }],
+    ),
+    (
+        Item::Sword,
+        &[Transition { level: 1, value: 3 }, Transition { level: 4, value: 5 }],
+    ),
+    (
+        Item::Shield,
+        &[Transition { level: 1, value: 3 }, Transition { level: 8, value: 15 }],
+    ),
+    (
+        Item::Greataxe,
+        &[Transition { level: 3, value: 3 }, Transition { level: 6, value: 8 }],
+    ),
+];
+
+pub fn new_item(item: Item, x: i32, y: i32) -> Object {
+    let mut object = match item {
+        Item::Heal => Object::new(x, y, '!', "healing potion", VIOLET, false),
+        Item::Regen => Object::new(x, y, '!', "potion of regeneration", LIGHT_CRIMSON, false),
+        Item::Lightning => Object::new(x, y, '#', "scroll of lightning bolt", LIGHT_YELLOW, false),
+        Item::Fireball => Object::new(x, y, '#', "scroll of fireball", LIGHT_YELLOW, false),
+        Item::Confuse => Object::new(x, y, '#', "scroll of confusion", LIGHT_YELLOW, false),
+        Item::Sword => Object::new(x, y, '/', "sword", SKY, false),
+        Item::Shield => Object::new(x, y, '[', "shield", DARKER_ORANGE, false),
+        Item::Greataxe => Object::new(x, y, '/', "greataxe", LIGHT_SKY, false),
+        Item::Amulet => Object::new(x, y, '"', "royal amulet", GOLD, false),
+    };
+    object.item = Some(item);
+    object.equipment = match item {
+        Item::Sword => Some(Equipment {
+            slot: Slot::RightHand,
+            equipped: false,
+            power_bonus: 3,
+            defense_bonus: 0,
+            max_hp_bonus: 0,
+        }),
+        Item::Shield => Some(Equipment {
+            slot: Slot::LeftHand,
+            equipped: false,
+            power_bonus: 0,
+            defense_bonus: 1,
+            max_hp_bonus: 0,
+        }),
+        // hits harder than a sword and shield together, but leaves nothing to block with
+        Item::Greataxe => Some(Equipment {
+            slot: Slot::BothHands,
+            equipped: false,
+            power_bonus: 5,
+            defense_bonus: 0,
+            max_hp_bonus: 0,
+        }),
+        _ => None,
+    };
+    object
+}
+
+const POTION_LOOKS: &[&str] = &["murky", "fizzy", "bubbling", "cloudy", "smoky", "oily"];
+const SCROLL_SYLLABLES: &[&str] = &[
+    "xy", "zzy", "fo", "ba", "mur", "kel", "an", "tor", "qua", "ze",
+];
+
+// what the potions and scrolls look like in this game, since they can't be told apart
+// before one of their kind is used
+pub fn item_labels(rng: &mut StdRng) -> Vec<(Item, String)> {
+    let mut looks = POTION_LOOKS.to_vec();
+    looks.shuffle(rng);
+    let potions = ITEM_CHANCES.iter().map(|(item, _)| *item).filter(|item| item.is_potion());
+    let mut labels: Vec<_> = potions
+        .zip(looks)
+        .map(|(item, look)| (item, format!("{} potion", look)))
+        .collect();
+    for item in [Item::Lightning, Item::Fireball, Item::Confuse] {
+        // a nonsense word, different for every kind of scroll
+        loop {
+            let syllables = rng.random_range(2..=3);
+            let word: String = (0..syllables)
+                .map(|_| SCROLL_SYLLABLES[rng.random_range(0..SCROLL_SYLLABLES.len())])
+                .collect();
+            let label = format!("scroll labeled {}", word.to_uppercase());
+            if labels.iter().all(|(_, taken)| *taken != label) {
+                labels.push((item, label));
+                break;
+            }
+        }
+    }
+    labels
+}
+
+// items get rarer or more common with depth: potions early on, scrolls and equipment
+// further down
+fn item_chances(level: u32) -> Vec<(Item, u32)> {
+    ITEM_CHANCES
+        .iter()
+        .map(|(item, table)| (*item, from_dungeon_level(table, level)))
+        .collect()
+}
+
+// a monster of the given kind, carrying nothing
+pub fn spawn_monster(name: &str, def: &MonsterDef, x: i32, y: i32) -> Object {
+    let mut monster = Object::new(x, y, def.glyph, name, def.color, true);
+    monster.fighter = Some(Fighter {
+        base_max_hp: def.hp,
+        hp: def.hp,
+        base_defense: def.defense,
+        base_power: def.power,
+        xp: def.xp,
+        on_death: DeathCallback::Monster,
+        on_hit: def.on_hit,
+        ability: def.ability,
+    });
+    monster.alive = true;
+    monster.land_only = def.land_only;
+    // monsters start out asleep, and know what to do once they wake up
+    let awake_ai = match def.range {
+        Some(range) => Ai::Ranged {
+            range,
+            alert: MONSTER_ALERT_TURNS,
+        },
+        None => Ai::Basic {
+            alert: MONSTER_ALERT_TURNS,
+        },
+    };
+    monster.ai = Some(Ai::Asleep {
+        awake_ai: Box::new(awake_ai),
+    });
+    monster
+}
+
+// a monster of a kind picked for this depth, None if no kind can appear this deep
+fn new_monster(
+    x: i32,
+    y: i32,
+    level: u32,
+    monster_defs: &MonsterDefs,
+    item_chances: &[(Item, u32)],
+    rng: &mut StdRng,
+) -> Option<Object> {
+    // pick a monster kind, weighted for this depth (sorted so the roll is stable)
+    let mut kinds: Vec<_> = monster_defs
+        .iter()
+        .map(|(name, def)| ((name, def), from_dungeon_level(&def.weight, level)))
+        .collect();
+    kinds.sort_by(|a, b| (a.0).0.cmp((b.0).0));
+    let &(name, def) = random_choice_weighted(&kinds, rng)?;
+    Some(stock_monster(spawn_monster(name, def, x, y), level, item_chances, rng))
+}
+
+// some monsters carry an item, which can be taken from their remains, and some gold
+fn stock_monster(
+    mut monster: Object,
+    level: u32,
+    item_chances: &[(Item, u32)],
+    rng: &mut StdRng,
+) -> Object {
+    if rng.random::<f32>() < MONSTER_LOOT_CHANCE {
+        monster.loot = random_choice_weighted(item_chances, rng).copied();
+    }
+    if rng.random::<f32>() < MONSTER_GOLD_CHANCE {
+        monster.gold = gold_amount(level, rng);
+    }
+    monster
+}
+
+// put the monster in the world, along with its pack if it leads one. the followers go
+// on the free tiles of the room nearest to the leader, so the pack stays together
+fn add_monster(
+    leader: Object,
+    room: Rect,
+    map: &Map,
+    objects: &mut Vec<Object>,
+    level: u32,
+    monster_defs: &MonsterDefs,
+    rng: &mut StdRng,
+) {
+    let pack = monster_defs.get(&leader.name).and_then(|def| def.pack.clone());
+    let (leader_id, (leader_x, leader_y)) = (leader.id, leader.pos());
+    objects.push(leader);
+    let Some(pack) = pack else {
+        return;
+    };
+    let mut spots: Vec<(i32, i32)> = (room.y1 + 1..room.y2)
+        .flat_map(|y| (room.x1 + 1..room.x2).map(move |x| (x, y)))
+        .collect();
+    spots.sort_by_key(|&(x, y)| cmp::max((x - leader_x).abs(), (y - leader_y).abs()));
+    let item_chances = item_chances(level);
+    let size = rng.random_range(pack.min..=pack.max);
+    for _ in 0..size {
+        let kind = &pack.followers[rng.random_range(0..pack.followers.len())];
+        let Some(def) = monster_defs.get(kind) else {
+            continue;
+        };
+        let free = spots.iter().find(|&&(x, y)| {
+            let on_lava = map.get(x, y).is_some_and(|tile| tile.kind == TileKind::Lava);
+            !is_blocked(x, y, map, objects) && !on_lava
+        });
+        let Some(&(x, y)) = free else {
+            break;
+        };
+        let follower = spawn_monster(kind, def, x, y);
+        let mut follower = stock_monster(follower, level, &item_chances, rng);
+        follower.ai = follower.ai.map(|awake_ai| Ai::Follower {
+            leader: leader_id,
+            awake_ai: Box::new(awake_ai),
+        });
+        objects.push(follower);
+    }
+}
+
+fn place_objects(
+    room: Rect,
+    map: &Map,
+    objects: &mut Vec<Object>,
+    level: u32,
+    difficulty: Difficulty,
+    monster_defs: &MonsterDefs,
+    rng: &mut StdRng,
+) {
+    let item_chances = item_chances(level);
+
+    // choose random number of monsters, deeper levels and harder games have more of them
+    let max_monsters = MAX_ROOM_MONSTERS + (level as i32 - 1) / 2;
+    let num_monsters = scale(rng.random_range(0..max_monsters + 1), difficulty.monster_count());
+
+    for _ in 0..num_monsters {
+        // choose random spot for this monster
+        let x = rng.random_range(room.x1 + 1..room.x2);
+        let y = rng.random_range(room.y1 + 1..room.y2);
+
+        // skip the spot if it's a wall or already taken (this includes the player's start),
+        // and don't put monsters straight into lava either
+        let on_lava = map.get(x, y).is_some_and(|tile| tile.kind == TileKind::Lava);
+        if is_blocked(x, y, map, objects) || on_lava {
+            continue;
+        }
+        if let Some(monster) = new_monster(x, y, level, monster_defs, &item_chances, rng) {
+            add_monster(monster, room, map, objects, level, monster_defs, rng);
+        }
+    }
+
+    // choose random number of items
+    let num_items = rng.random_range(0..MAX_ROOM_ITEMS + 1);
+
+    for _ in 0..num_items {
+        // choose random spot for this item
+        let x = rng.random_range(room.x1 + 1..room.x2);
+        let y = rng.random_range(room.y1 + 1..room.y2);
+
+        // only place it if the tile is not blocked
+        if !is_blocked(x, y, map, objects)
+            && let Some(&item) = random_choice_weighted(&item_chances, rng)
+        {
+            objects.push(new_item(item, x, y));
+        }
+    }
+
+    if rng.random::<f32>() < ROOM_GOLD_CHANCE {
+        let x = rng.random_range(room.x1 + 1..room.x2);
+        let y = rng.random_range(room.y1 + 1..room.y2);
+        if !is_blocked(x, y, map, objects) {
+            objects.push(new_gold(gold_amount(level, rng), x, y));
+        }
+    }
+
+    if rng.random::<f32>() < ROOM_CHEST_CHANCE {
+        let x = rng.random_range(room.x1 + 1..room.x2);
+        let y = rng.random_range(room.y1 + 1..room.y2);
+        // not in water or lava, where the loot would be hard to get at
+        let on_floor = map.get(x, y).is_some_and(|tile| tile.kind == TileKind::Floor);
+        if on_floor && !is_blocked(x, y, map, objects) {
+            objects.push(new_chest(chest_contents(&item_chances, rng), x, y));
+        }
+    }
+}
+
+// what a chest holds: nothing, or one to CHEST_MAX_ITEMS items from the spawn table
+fn chest_contents(item_chances: &[(Item, u32)], rng: &mut StdRng) -> Vec<Item> {
+    if rng.random::<f32>() < CHEST_EMPTY_CHANCE {
+        return vec![];
+    }
+    let count = rng.random_range(1..=CHEST_MAX_ITEMS);
+    (0..count)
+        .filter_map(|_| random_choice_weighted(item_chances, rng).copied())
+        .collect()
+}
+
+fn gold_amount(level: u32, rng: &mut StdRng) -> i32 {
+    rng.random_range(1..=GOLD_PER_LEVEL * level as i32)
+}
+
+// a closed chest, in the way until the player opens it
+pub fn new_chest(contents: Vec<Item>, x: i32, y: i32) -> Object {
+    let mut chest = Object::new(x, y, '=', "chest", AMBER, true);
+    chest.chest = Some(Chest {
+        contents,
+        opened: false,
+    });
+    chest
+}
+
+// a pile of gold, picked up just by walking over it
+pub fn new_gold(amount: i32, x: i32, y: i32) -> Object {
+    let mut gold = Object::new(x, y, '$', "gold", GOLD, false);
+    gold.gold = amount;
+    gold
+}
+
+// returns the map, along with how many stranded regions had to be joined to the rest
+pub fn make_map(
+    config: &MapGenConfig,
+    objects: &mut Vec<Object>,
+    level: u32,
+    difficulty: Difficulty,
+    monster_defs: &MonsterDefs,
+    vaults: &[Vault],
+    rng: &mut StdRng,
+) -> Result<(Map, usize), String> {
+    config.validate()?;
+    let first_new = objects.len();
+
+    let (map, merged) = first_valid_map(objects, rng, |objects, rng| {
+        // caves have no rooms, so they never get vaults
+        Ok(match config.generator {
+            Generator::Random => {
+                make_map_random(config, objects, level, difficulty, monster_defs, vaults, rng)
+            }
+            Generator::Bsp => {
+                make_map_bsp(config, objects, level, difficulty, monster_defs, vaults, rng)
+            }
+            Generator::Caves => {
+                make_map_caves(config, objects, level, difficulty, monster_defs, rng)?
+            }
+        })
+    })?;
+    // every monster of the level, from vaults and packs too, gets the hp of the
+    // difficulty
+    for fighter in objects[first_new..]
+        .iter_mut()
+        .filter_map(|object| object.fighter.as_mut())
+    {
+        fighter.base_max_hp = scale(fighter.base_max_hp, difficulty.monster_hp());
+        fighter.hp = fighter.base_max_hp;
+    }
+    Ok((map, merged))
+}
+
+// generate levels until one has stairs the player can reach, up to MAP_MAX_ATTEMPTS times.
+// the objects of a level that is thrown away go with it
+fn first_valid_map(
+    objects: &mut Vec<Object>,
+    rng: &mut StdRng,
+    mut generate: impl FnMut(&mut Vec<Object>, &mut StdRng) -> Result<Map, String>,
+) -> Result<(Map, usize), String> {
+    let first_new = objects.len();
+    for _ in 0..MAP_MAX_ATTEMPTS {
+        let mut map = generate(objects, rng)?;
+        // the generators should never leave anything cut off, but if one does, the player
+        // could be stuck without a way to the stairs
+        let merged = connect_regions(&mut map, objects[PLAYER].pos(), rng);
+        widen_pinches(&mut map);
+        // and if something still got past that, the level is thrown away and made again
+        if !stairs_reachable(&map, objects) {
+            objects.truncate(first_new);
+            continue;
+        }
+        return Ok((map, merged));
+    }
+    Err(format!("no level with stairs the player can reach after {} attempts", MAP_MAX_ATTEMPTS))
+}
+
+// whether the player can walk from where they start to the stairs down. a level without
+// stairs can't be finished either
+fn stairs_reachable(map: &Map, objects: &[Object]) -> bool {
+    let Some(stairs) = objects.iter().find(|object| object.name == "stairs") else {
+        return false;
+    };
+    let (x, y) = stairs.pos();
+    passable(map, x, y) && !assert_connected(map, objects[PLAYER].pos()).contains(&(x, y))
+}
+
+// the two walls of a pinch in the square with its top left corner at the given tile:
+// walls that only meet at their corners, with open tiles on the other two. the player
+// could squeeze between them diagonally, and see through the gap too
+fn pinch(map: &Map, x: i32, y: i32) -> Option<[(i32, i32); 2]> {
+    let wall = |x, y| {
+        map.get(x, y)
+            .is_some_and(|tile| tile.kind == TileKind::Wall && tile.door.is_none())
+    };
+    let open = |x, y| map.get(x, y).is_some_and(|tile| !tile.blocked);
+    if wall(x, y) && wall(x + 1, y + 1) && open(x + 1, y) && open(x, y + 1) {
+        Some([(x, y), (x + 1, y + 1)])
+    } else if wall(x + 1, y) && wall(x, y + 1) && open(x, y) && open(x + 1, y + 1) {
+        Some([(x + 1, y), (x, y + 1)])
+    } else {
+        None
+    }
+}
+
+// knock down one wall of every pinch, so the gap becomes a proper opening. that can
+// leave a new pinch right next to it, so it goes on until there are none. the outer
+// walls stay, or the map would have a hole in it
+fn widen_pinches(map: &mut Map) {
+    let inside = |map: &Map, (x, y): (i32, i32)| {
+        x > 0 && y > 0 && x < map.width - 1 && y < map.height - 1
+    };
+    let mut widened = true;
+    while widened {
+        widened = false;
+        for y in 0..map.height - 1 {
+            for x in 0..map.width - 1 {
+                let Some(walls) = pinch(map, x, y) else {
+                    continue;
+                };
+                if let Some(&(wall_x, wall_y)) = walls.iter().find(|&&wall| inside(map, wall))
+                    && let Some(tile) = map.get_mut(wall_x, wall_y)
+                {
+                    *tile = Tile::empty();
+                    widened = true;
+                }
+            }
+        }
+    }
+}
+
+// whether nothing that blocks sight stands between the two points, along a Bresenham
+// line. the two ends themselves don't count, so a wall can still be seen
+pub fn line_of_sight(map: &Map, x1: i32, y1: i32, x2: i32, y2: i32) -> bool {
+    Line::new((x1, y1), (x2, y2))
+        .take_while(|&point| point != (x2, y2))
+        .all(|(x, y)| map.get(x, y).is_some_and(|tile| !tile.block_sight))
+}
+
+// a tile the player can get onto. closed doors count, since they open when bumped into
+fn passable(map: &Map, x: i32, y: i32) -> bool {
+    map.get(x, y).is_some_and(|tile| !tile.blocked || tile.door.is_some())
+}
+
+// flood fill from the start, and return all the passable tiles it didn't get to
+pub fn assert_connected(map: &Map, start: (i32, i32)) -> HashSet<(i32, i32)> {
+    let mut reached = vec![false; map.tiles.len()];
+    let mut stack = vec![];
+    if passable(map, start.0, start.1) {
+        reached[map.idx(start.0, start.1)] = true;
+        stack.push(start);
+    }
+    // diagonal steps are allowed, like for the player
+    while let Some((x, y)) = stack.pop() {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (nx, ny) = (x + dx, y + dy);
+                if passable(map, nx, ny) && !reached[map.idx(nx, ny)] {
+                    reached[map.idx(nx, ny)] = true;
+                    stack.push((nx, ny));
+                }
+            }
+        }
+    }
+
+    let mut unreachable = HashSet::new();
+    for y in 0..map.height {
+        for x in 0..map.width {
+            if passable(map, x, y) && !reached[map.idx(x, y)] {
+                unreachable.insert((x, y));
+            }
+        }
+    }
+    unreachable
+}
+
+// dig a corridor from every region the start can't get to, over to the closest tile it
+// can. returns the number of regions that were joined up
+fn connect_regions(map: &mut Map, start: (i32, i32), rng: &mut StdRng) -> usize {
+    let mut merged = 0;
+    loop {
+        let unreachable = assert_connected(map, start);
+        // pick the stranded tile nearest to the start, so the result doesn't depend on
+        // the order of the set
+        let stranded = unreachable
+            .iter()
+            .min_by_key(|&&(x, y)| ((x - start.0).pow(2) + (y - start.1).pow(2), x, y));
+        let Some(&(from_x, from_y)) = stranded else {
+            return merged;
+        };
+        let mut closest = None;
+        for y in 0..map.height {
+            for x in 0..map.width {
+                if !passable(map, x, y) || unreachable.contains(&(x, y)) {
+                    continue;
+                }
+                let distance = (x - from_x).pow(2) + (y - from_y).pow(2);
+                if closest.is_none_or(|(best, _)| distance < best) {
+                    closest = Some((distance, (x, y)));
+                }
+            }
+        }
+        // nothing is reachable when the start itself is in a wall, so there is
+        // nothing to connect to either
+        let Some((_, to)) = closest else {
+            return merged;
+        };
+        create_tunnel((from_x, from_y), to, map, rng);
+        merged += 1;
+    }
+}
+
+// once in a while a vault takes the place of a random room, if its rectangle fits in the
+// given space
+fn pick_vault<'a>(
+    vaults: &'a [Vault],
+    max_w: i32,
+    max_h: i32,
+    rng: &mut StdRng,
+) -> Option<&'a Vault> {
+    if vaults.is_empty() || rng.random::<f32>() >= VAULT_CHANCE {
+        return None;
+    }
+    let vault = &vaults[rng.random_range(0..vaults.len())];
+    let (w, h) = (vault.width() + 1, vault.height() + 1);
+    if w <= max_w && h <= max_h {
+        Some(vault)
+    } else {
+        None
+    }
+}
+
+fn make_map_random(
+    config: &MapGenConfig,
+    objects: &mut Vec<Object>,
+    level: u32,
+    difficulty: Difficulty,
+    monster_defs: &MonsterDefs,
+    vaults: &[Vault],
+    rng: &mut StdRng,
+) -> Map {
+    // fill map with wall tiles
+    let mut map = Map::new(config.width, config.height, Tile::wall());
+    let mut rooms = vec![];
+    let mut prev_center: Option<(i32, i32)> = None;
+
+    for _ in 0..config.max_rooms {
+        let vault = pick_vault(vaults, config.width - 3, config.height - 3, rng);
+        let (w, h) = match vault {
+            Some(vault) => (vault.width() + 1, vault.height() + 1),
+            None => (
+                rng.random_range(config.room_min_size..config.room_max_size + 1),
+                rng.random_range(config.room_min_size..config.room_max_size + 1),
+            ),
+        };
+        // random position without going out of the boundaries of the map, keeping a
+        // border of wall between the room's own walls (x1 and x2) and the map edge
+        let room = Rect::new(rng.random_range(1..config.width - w - 1),
+                              rng.random_range(1..config.height - h - 1), w, h);
+        let failed = rooms.iter().any(|other| room.intersects_with(other));
+        if !failed{
+            match vault {
+                Some(vault) => vault.stamp(room, &mut map),
+                None => create_room(room, RoomShape::random(rng), &mut map),
+            }
+            let (cen_x, cen_y) = room.center();
+            if let Some(prev_center) = prev_center {
+                // leave the starting room plain, the others can get some terrain and traps.
+                // vaults are kept the way they were drawn
+                if vault.is_none() {
+                    decorate_room(room, &mut map, rng);
+                    place_trap(room, &mut map, rng);
+                }
+                // connect to the previous room so every room is reachable from the start
+                create_tunnel(prev_center, (cen_x, cen_y), &mut map, rng);
+            } else {
+                // this is the first room, where the player starts at
+                objects[PLAYER].x = cen_x;
+                objects[PLAYER].y = cen_y;
+            }
+            prev_center = Some((cen_x, cen_y));
+            // add some content to this room, such as monsters
+            match vault {
+                Some(vault) => vault.place_objects(room, &map, objects, level, monster_defs, rng),
+                None => place_objects(room, &map, objects, level, difficulty, monster_defs, rng),
+            }
+            rooms.push(room);
+        }
+    }
+
+    // now that all the corridors are dug, put doors where they enter the rooms
+    place_doors(&rooms, &mut map);
+
+    // create stairs at the center of the last room
+    if let Some(last_room) = rooms.last() {
+        let (last_room_x, last_room_y) = last_room.center();
+        let mut stairs = Object::new(last_room_x, last_room_y, '>', "stairs", WHITE, false);
+        stairs.always_visible = true;
+        objects.push(stairs);
+    }
+
+    map
+}
+
+fn make_map_bsp(
+    config: &MapGenConfig,
+    objects: &mut Vec<Object>,
+    level: u32,
+    difficulty: Difficulty,
+    monster_defs: &MonsterDefs,
+    vaults: &[Vault],
+    rng: &mut StdRng,
+) -> Map {
+    // fill map with wall tiles
+    let mut map = Map::new(config.width, config.height, Tile::wall());
+    let mut rooms = vec![];
+    let mut vault_rooms = vec![];
+
+    // the map edges are the outermost partition lines, so rooms keep a wall border
+    let whole_map = Rect::new(0, 0, config.width - 1, config.height - 1);
+    bsp_split(whole_map, config, vaults, &mut map, &mut rooms, &mut vault_rooms, rng);
+    place_doors(&rooms, &mut map);
+    let vault_in = |room: Rect| {
+        vault_rooms
+            .iter()
+            .find(|(vault_room, _)| *vault_room == room)
+            .map(|&(_, vault)| vault)
+    };
+    // leave the starting room plain, the others can get some terrain and traps. vaults
+    // are kept the way they were drawn
+    for &room in rooms.iter().skip(1) {
+        if vault_in(room).is_none() {
+            decorate_room(room, &mut map, rng);
+            place_trap(room, &mut map, rng);
+        }
+    }
+
+    // the player starts in the first room, the stairs are in the last one
+    let (start_x, start_y) = rooms[0].center();
+    objects[PLAYER].x = start_x;
+    objects[PLAYER].y = start_y;
+    for &room in &rooms {
+        match vault_in(room) {
+            Some(vault) => vault.place_objects(room, &map, objects, level, monster_defs, rng),
+            None => place_objects(room, &map, objects, level, difficulty, monster_defs, rng),
+        }
+    }
+    if let Some(last_room) = rooms.last() {
+        let (last_room_x, last_room_y) = last_room.center();
+        let mut stairs = Object::new(last_room_x, last_room_y, '>', "stairs", WHITE, false);
+        stairs.always_visible = true;
+        objects.push(stairs);
+    }
+
+    map
+}
+
+// split the area in two and recurse, or carve a room if it's small enough. the two halves
+// are joined by a corridor, so every leaf of the tree ends up connected to every other.
+// returns the center of a room inside the area, for the caller to connect to
+fn bsp_split<'a>(
+    area: Rect,
+    config: &MapGenConfig,
+    vaults: &'a [Vault],
+    map: &mut Map,
+    rooms: &mut Vec<Rect>,
+    vault_rooms: &mut Vec<(Rect, &'a Vault)>,
+    rng: &mut StdRng,
+) -> (i32, i32) {
+    // each half needs space for the smallest room plus a wall tile on both sides of it,
+    // so that rooms in neighbouring areas never touch
+    let min_span = config.room_min_size + 2;
+    let (width, height) = (area.x2 - area.x1, area.y2 - area.y1);
+    let fits_max_room = width <= config.room_max_size + 2 && height <= config.room_max_size + 2;
+    let can_split_x = width >= 2 * min_span;
+    let can_split_y = height >= 2 * min_span;
+    // a vault can be bigger than a random room, so it may use up an area that could
+    // still be split, as long as that's not a big chunk of the map
+    let max_span = 2 * (config.room_max_size + 2);
+    let near_leaf = width <= max_span && height <= max_span;
+    let vault = if near_leaf {
+        pick_vault(vaults, width - 2, height - 2, rng)
+    } else {
+        None
+    };
+
+    if vault.is_some() || fits_max_room || !(can_split_x || can_split_y) {
+        // a leaf: one room somewhere inside the area
+        let (w, h) = match vault {
+            Some(vault) => (vault.width() + 1, vault.height() + 1),
+            None => (
+                rng.random_range(config.room_min_size..=cmp::min(config.room_max_size, width - 2)),
+                rng.random_range(config.room_min_size..=cmp::min(config.room_max_size, height - 2)),
+            ),
+        };
+        let room = Rect::new(
+            rng.random_range(area.x1 + 1..=area.x2 - 1 - w),
+            rng.random_range(area.y1 + 1..=area.y2 - 1 - h),
+            w,
+            h,
+        );
+        match vault {
+            Some(vault) => {
+                vault.stamp(room, map);
+                vault_rooms.push((room, vault));
+            }
+            None => create_room(room, RoomShape::random(rng), map),
+        }
+        rooms.push(room);
+        return room.center();
+    }
+
+    // cut across the longer side, unless only the other one is big enough
+    let split_x = if can_split_x && can_split_y {
+        width >= height
+    } else {
+        can_split_x
+    };
+    let (first, second) = if split_x {
+        let x = rng.random_range(area.x1 + min_span..=area.x2 - min_span);
+        (Rect { x2: x, ..area }, Rect { x1: x, ..area })
+    } else {
+        let y = rng.random_range(area.y1 + min_span..=area.y2 - min_span);
+        (Rect { y2: y, ..area }, Rect { y1: y, ..area })
+    };
+
+    let first_center = bsp_split(first, config, vaults, map, rooms, vault_rooms, rng);
+    let second_center = bsp_split(second, config, vaults, map, rooms, vault_rooms, rng);
+    create_tunnel(first_center, second_center, map, rng);
+    first_center
+}
+
+fn make_map_caves(
+    config: &MapGenConfig,
+    objects: &mut Vec<Object>,
+    level: u32,
+    difficulty: Difficulty,
+    monster_defs: &MonsterDefs,
+    rng: &mut StdRng,
+) -> Result<Map, String> {
+    let min_open = (config.width * config.height) as f32 * CAVE_MIN_OPEN_SHARE;
+    for _ in 0..CAVE_MAX_ATTEMPTS {
+        let mut map = random_cave(config, rng);
+        for _ in 0..CAVE_SMOOTHING_PASSES {
+            map = smooth_cave(&map);
+        }
+        let region = largest_open_region(&map);
+        if (region.len() as f32) < min_open {
+            continue;
+        }
+
+        // wall off everything that isn't part of the largest region, so all of the
+        // cave is reachable from wherever the player starts
+        let mut connected = vec![false; map.tiles.len()];
+        for &(x, y) in &region {
+            connected[map.idx(x, y)] = true;
+        }
+        for (tile, connected) in map.tiles.iter_mut().zip(connected) {
+            if !connected {
+                *tile = Tile::wall();
+            }
+        }
+
+        // the player starts anywhere in the cave
+        let (start_x, start_y) = region[rng.random_range(0..region.len())];
+        objects[PLAYER].x = start_x;
+        objects[PLAYER].y = start_y;
+
+        // populate the cave in room-sized chunks, so it gets about as much content as a
+        // level made of rooms. place_objects skips the spots that turn out to be walls
+        let chunk = config.room_max_size;
+        for y in (0..config.height).step_by(chunk as usize) {
+            for x in (0..config.width).step_by(chunk as usize) {
+                let area = Rect::new(x, y, chunk, chunk);
+                place_objects(area, &map, objects, level, difficulty, monster_defs, rng);
+            }
+        }
+
+        // and the stairs go to the open spot furthest away from the start
+        let furthest = region
+            .iter()
+            .max_by_key(|&&(x, y)| (x - start_x).pow(2) + (y - start_y).pow(2));
+        if let Some(&(stairs_x, stairs_y)) = furthest {
+            let mut stairs = Object::new(stairs_x, stairs_y, '>', "stairs", WHITE, false);
+            stairs.always_visible = true;
+            objects.push(stairs);
+        }
+
+        return Ok(map);
+    }
+    Err(format!("no cave with enough open space after {} attempts", CAVE_MAX_ATTEMPTS))
+}
+
+// random noise of wall and floor tiles, with a solid wall along the map edges
+fn random_cave(config: &MapGenConfig, rng: &mut StdRng) -> Map {
+    let mut map = Map::new(config.width, config.height, Tile::wall());
+    for y in 1..config.height - 1 {
+        for x in 1..config.width - 1 {
+            if rng.random::<f32>() >= CAVE_WALL_DENSITY
+                && let Some(tile) = map.get_mut(x, y)
+            {
+                *tile = Tile::empty();
+            }
+        }
+    }
+    map
+}
+
+// one cellular automaton pass: a tile becomes wall if 5 or more tiles of its 3x3
+// neighbourhood (itself included) are walls, and floor otherwise. the map edges stay walls
+fn smooth_cave(map: &Map) -> Map {
+    let mut smoothed = Map::new(map.width, map.height, Tile::wall());
+    for y in 1..map.height - 1 {
+        for x in 1..map.width - 1 {
+            let mut walls = 0;
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    // anything outside the map counts as wall
+                    let neighbour = map.get(x + dx, y + dy);
+                    if neighbour.is_none_or(|tile| tile.blocked) {
+                        walls += 1;
+                    }
+                }
+            }
+            if walls < 5
+                && let Some(tile) = smoothed.get_mut(x, y)
+            {
+                *tile = Tile::empty();
+            }
+        }
+    }
+    smoothed
+}
+
+// the positions of the biggest group of floor tiles connected by orthogonal steps
+fn largest_open_region(map: &Map) -> Vec<(i32, i32)> {
+    let mut seen = vec![false; map.tiles.len()];
+    let mut largest = vec![];
+    for y in 0..map.height {
+        for x in 0..map.width {
+            if seen[map.idx(x, y)] || map.get(x, y).is_none_or(|tile| tile.blocked) {
+                continue;
+            }
+            // flood fill from here
+            let mut region = vec![];
+            let mut stack = vec![(x, y)];
+            seen[map.idx(x, y)] = true;
+            while let Some((x, y)) = stack.pop() {
+                region.push((x, y));
+                for (nx, ny) in [(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)] {
+                    if map.get(nx, ny).is_some_and(|tile| !tile.blocked) && !seen[map.idx(nx, ny)] {
+                        seen[map.idx(nx, ny)] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+            if region.len() > largest.len() {
+                largest = region;
+            }
+        }
+    }
+    largest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use crate::load_monster_defs;
+
+    // a level made by the given generator, along with the objects placed on it
+    fn generate(generator: Generator, seed: u64) -> (Map, Vec<Object>) {
+        let config = MapGenConfig {
+            generator,
+            ..MapGenConfig::default()
+        };
+        let monster_defs = load_monster_defs("monsters.json").unwrap();
+        let mut objects = vec![Object::new(0, 0, '@', "player", WHITE, true)];
+        let mut rng = StdRng::seed_from_u64(seed);
+        let map = match generator {
+            Generator::Random => make_map_random(
+                &config, &mut objects, 1, Difficulty::Normal, &monster_defs, &[], &mut rng,
+            ),
+            Generator::Bsp => make_map_bsp(
+                &config, &mut objects, 1, Difficulty::Normal, &monster_defs, &[], &mut rng,
+            ),
+            Generator::Caves => make_map_caves(
+                &config, &mut objects, 1, Difficulty::Normal, &monster_defs, &mut rng,
+            )
+            .unwrap(),
+        };
+        (map, objects)
+    }
+
+    // a whole level the way the game makes it, connected and cleaned up
+    fn level(generator: Generator, difficulty: Difficulty, seed: u64) -> (Map, Vec<Object>) {
+        let config = MapGenConfig {
+            generator,
+            ..MapGenConfig::default()
+        };
+        let monster_defs = load_monster_defs("monsters.json").unwrap();
+        let mut objects = vec![Object::new(0, 0, '@', "player", WHITE, true)];
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (map, _) =
+            make_map(&config, &mut objects, 1, difficulty, &monster_defs, &[], &mut rng).unwrap();
+        (map, objects)
+    }
+
+    #[test]
+    fn random_rooms_are_all_connected() {
+        for seed in 0..10 {
+            let (map, objects) = generate(Generator::Random, seed);
+            // the player starts at the center of the first room
+            let unreachable = assert_connected(&map, objects[PLAYER].pos());
+            assert!(unreachable.is_empty(), "seed {}: {:?} can't be reached", seed, unreachable);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_map() {
+        let monster_defs = load_monster_defs("monsters.json").unwrap();
+        let make = || {
+            let mut objects = vec![Object::new(0, 0, '@', "player", WHITE, true)];
+            let mut rng = StdRng::seed_from_u64(42);
+            let config = MapGenConfig::default();
+            let (map, _) =
+                make_map(&config, &mut objects, 1, Difficulty::Normal, &monster_defs, &[], &mut rng)
+                    .unwrap();
+            let positions: Vec<_> = objects.iter().map(|object| object.pos()).collect();
+            (map, positions)
+        };
+        assert_eq!(make(), make());
+    }
+
+    #[test]
+    fn rooms_sharing_an_edge_intersect() {
+        let room = Rect::new(5, 5, 6, 6);
+        // the second room's left wall is the first one's right wall
+        let neighbour = Rect::new(11, 5, 6, 6);
+        assert!(room.intersects_with(&neighbour));
+        assert!(neighbour.intersects_with(&room));
+        // with a tile of wall between them they don't
+        assert!(!room.intersects_with(&Rect::new(12, 5, 6, 6)));
+    }
+
+    #[test]
+    fn rect_equality() {
+        let room = Rect::new(3, 4, 7, 5);
+        assert_eq!(room, room);
+        assert_ne!(room, Rect::new(3, 4, 8, 5));
+        assert_ne!(room, Rect::new(2, 4, 7, 5));
+    }
+
+    #[test]
+    fn random_map_border_is_wall() {
+        let (map, _) = generate(Generator::Random, 7);
+        for x in 0..map.width {
+            for y in [0, map.height - 1] {
+                assert!(map.get(x, y).unwrap().blocked, "({}, {})", x, y);
+            }
+        }
+        for y in 0..map.height {
+            for x in [0, map.width - 1] {
+                assert!(map.get(x, y).unwrap().blocked, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn bsp_rooms_are_all_connected() {
+        for seed in 0..10 {
+            let (map, objects) = generate(Generator::Bsp, seed);
+            let unreachable = assert_connected(&map, objects[PLAYER].pos());
+            assert!(unreachable.is_empty(), "seed {}: {:?} can't be reached", seed, unreachable);
+        }
+    }
+
+    #[test]
+    fn caves_are_one_region() {
+        for seed in 0..10 {
+            let (map, objects) = generate(Generator::Caves, seed);
+            let unreachable = assert_connected(&map, objects[PLAYER].pos());
+            assert!(unreachable.is_empty(), "seed {}: {:?} can't be reached", seed, unreachable);
+            // only the largest region is left open
+            let open = map.tiles.iter().filter(|tile| !tile.blocked).count();
+            assert_eq!(largest_open_region(&map).len(), open, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn opening_a_door_clears_its_block_sight() {
+        let mut door = Tile::closed_door();
+        assert!(door.blocked && door.block_sight);
+        door.open_door();
+        assert!(!door.blocked && !door.block_sight);
+        assert!(!door.is_closed_door());
+        // walls aren't doors, and stay as they are
+        let mut wall = Tile::wall();
+        wall.open_door();
+        assert!(wall.blocked && wall.block_sight);
+    }
+
+    #[test]
+    fn spawn_table_by_depth() {
+        let table = [Transition { level: 3, value: 15 }, Transition { level: 5, value: 30 }];
+        let weights: Vec<_> = (1..=6).map(|level| from_dungeon_level(&table, level)).collect();
+        assert_eq!(weights, [0, 0, 15, 15, 30, 30]);
+        // item weights on the first level: 35 for healing, 5 for each of the three
+        // scrolls, 3 each for the sword and shield
+        let total: u32 = item_chances(1).iter().map(|(_, weight)| weight).sum();
+        assert_eq!(total, 56);
+    }
+
+    #[test]
+    fn deep_monsters_stay_deep() {
+        let monster_defs = load_monster_defs("monsters.json").unwrap();
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..500 {
+            let monster = new_monster(1, 1, 1, &monster_defs, &[], &mut rng).unwrap();
+            assert_ne!(monster.name, "troll");
+            assert_ne!(monster.name, "vampire");
+        }
+    }
+
+    #[test]
+    fn circle_room_keeps_its_corners() {
+        let mut map = Map::new(20, 20, Tile::wall());
+        let room = Rect::new(2, 2, 10, 10);
+        create_room(room, RoomShape::Circle, &mut map);
+        let (center_x, center_y) = room.center();
+        assert!(!map.get(center_x, center_y).unwrap().blocked);
+        // the inside corners of the rectangle, which a plain room would have carved
+        for (x, y) in [(3, 3), (11, 3), (3, 11), (11, 11)] {
+            assert_eq!(map.get(x, y).unwrap().kind, TileKind::Wall, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn stranded_rooms_are_found_and_joined() {
+        let mut map = Map::new(30, 12, Tile::wall());
+        let (start, stranded) = (Rect::new(1, 1, 6, 6), Rect::new(18, 3, 6, 6));
+        create_room(start, RoomShape::Rectangle, &mut map);
+        create_room(stranded, RoomShape::Rectangle, &mut map);
+        let unreachable = assert_connected(&map, start.center());
+        assert_eq!(unreachable.len(), 25);
+        assert!(unreachable.contains(&stranded.center()));
+        assert!(!unreachable.contains(&start.center()));
+
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(connect_regions(&mut map, start.center(), &mut rng), 1);
+        assert!(assert_connected(&map, start.center()).is_empty());
+    }
+
+    #[test]
+    fn line_of_sight_down_a_corridor() {
+        let mut map = Map::new(12, 5, Tile::wall());
+        create_h_tunnel(1, 10, 2, &mut map);
+        assert!(line_of_sight(&map, 1, 2, 10, 2));
+        // a wall at the end can still be seen, but not past it
+        assert!(line_of_sight(&map, 1, 2, 11, 2));
+        *map.get_mut(5, 2).unwrap() = Tile::wall();
+        assert!(!line_of_sight(&map, 1, 2, 10, 2));
+        assert!(!line_of_sight(&map, 10, 2, 1, 2));
+    }
+
+    #[test]
+    fn no_pinches_are_left() {
+        for generator in [Generator::Random, Generator::Bsp, Generator::Caves] {
+            for seed in 0..5 {
+                let (map, _) = level(generator, Difficulty::Normal, seed);
+                for y in 0..map.height - 1 {
+                    for x in 0..map.width - 1 {
+                        assert_eq!(pinch(&map, x, y), None, "{:?} seed {}", generator, seed);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pinches_get_widened() {
+        let mut map = Map::new(6, 6, Tile::empty());
+        *map.get_mut(2, 2).unwrap() = Tile::wall();
+        *map.get_mut(3, 3).unwrap() = Tile::wall();
+        assert!(pinch(&map, 2, 2).is_some());
+        widen_pinches(&mut map);
+        assert_eq!(pinch(&map, 2, 2), None);
+    }
+
+    #[test]
+    fn packs_spawn_around_their_leader() {
+        let monster_defs = load_monster_defs("monsters.json").unwrap();
+        let mut map = Map::new(20, 20, Tile::wall());
+        let room = Rect::new(2, 2, 8, 8);
+        create_room(room, RoomShape::Rectangle, &mut map);
+        let (x, y) = room.center();
+        let leader = spawn_monster("orc captain", &monster_defs["orc captain"], x, y);
+        let leader_id = leader.id;
+        let mut objects = vec![Object::new(0, 0, '@', "player", WHITE, true)];
+        let mut rng = StdRng::seed_from_u64(5);
+        add_monster(leader, room, &map, &mut objects, 3, &monster_defs, &mut rng);
+
+        let followers = &objects[2..];
+        assert!((2..=4).contains(&followers.len()));
+        for follower in followers {
+            let Some(Ai::Follower { leader, .. }) = follower.ai else {
+                panic!("{} isn't following anyone", follower.name);
+            };
+            assert_eq!(leader, leader_id);
+            // inside the room, and right next to the leader as there is space
+            let (fx, fy) = follower.pos();
+            assert!(fx > room.x1 && fx < room.x2 && fy > room.y1 && fy < room.y2);
+            assert!((fx - x).abs() <= 1 && (fy - y).abs() <= 1, "{:?}", follower.pos());
+        }
+    }
+
+    #[test]
+    fn hard_levels_have_more_monster_hp() {
+        // all the hp of all the monsters of a level
+        let monster_hp = |difficulty, seed| -> i32 {
+            let (_, objects) = level(Generator::Random, difficulty, seed);
+            objects[PLAYER + 1..]
+                .iter()
+                .filter_map(|object| object.fighter)
+                .map(|fighter| fighter.hp)
+                .sum()
+        };
+        for seed in 0..5 {
+            let easy = monster_hp(Difficulty::Easy, seed);
+            let hard = monster_hp(Difficulty::Hard, seed);
+            assert!(hard > easy, "seed {}: {} on hard, {} on easy", seed, hard, easy);
+        }
+    }
+
+    #[test]
+    fn stairs_are_always_reachable() {
+        for generator in [Generator::Random, Generator::Bsp, Generator::Caves] {
+            for seed in 0..20 {
+                let (map, objects) = level(generator, Difficulty::Normal, seed);
+                assert!(stairs_reachable(&map, &objects), "{:?} seed {}", generator, seed);
+            }
+        }
+    }
+
+    // a room with the stairs in it, or walled in next to it
+    fn room_with_stairs(walled_in: bool, objects: &mut Vec<Object>) -> Map {
+        let mut map = Map::new(10, 10, Tile::wall());
+        create_room(Rect::new(0, 0, 6, 6), RoomShape::Rectangle, &mut map);
+        objects[PLAYER].set_pos(2, 2);
+        let stairs_x = if walled_in { 8 } else { 4 };
+        objects.push(Object::new(stairs_x, 2, '>', "stairs", WHITE, false));
+        map
+    }
+
+    #[test]
+    fn unreachable_stairs_make_another_level() {
+        let mut objects = vec![Object::new(0, 0, '@', "player", WHITE, true)];
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut attempts = 0;
+        let (map, _) = first_valid_map(&mut objects, &mut rng, |objects, _| {
+            attempts += 1;
+            Ok(room_with_stairs(attempts == 1, objects))
+        })
+        .unwrap();
+        assert_eq!(attempts, 2);
+        // the stairs of the level thrown away went with it
+        assert_eq!(objects.len(), 2);
+        assert!(stairs_reachable(&map, &objects));
+    }
+
+    #[test]
+    fn gives_up_after_the_last_attempt() {
+        let mut objects = vec![Object::new(0, 0, '@', "player", WHITE, true)];
+        let mut rng = StdRng::seed_from_u64(1);
+        let walled_in = |objects: &mut Vec<Object>, _: &mut StdRng| {
+            Ok(room_with_stairs(true, objects))
+        };
+        assert!(first_valid_map(&mut objects, &mut rng, walled_in).is_err());
+        assert_eq!(objects.len(), 1);
+    }
+}
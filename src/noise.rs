@@ -0,0 +1,33 @@
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+// smooth one-dimensional Perlin noise: close points get close values, far ones unrelated
+// values, all between -1 and 1. the torches flicker by it
+pub struct Noise {
+    // a shuffle of 0..256, to pick a gradient for each whole number
+    permutation: Vec<u8>,
+}
+
+impl Noise {
+    pub fn new(rng: &mut impl Rng) -> Self {
+        let mut permutation: Vec<u8> = (0..=255).collect();
+        permutation.shuffle(rng);
+        Noise { permutation }
+    }
+
+    // the slope of the noise at a whole number, from -1 to 1
+    fn gradient(&self, i: i64) -> f32 {
+        self.permutation[i.rem_euclid(256) as usize] as f32 / 127.5 - 1.0
+    }
+
+    pub fn get(&self, x: f32) -> f32 {
+        let left = x.floor();
+        let t = x - left;
+        // each end's slope gives a value at x, and a smooth curve blends the two
+        let from_left = self.gradient(left as i64) * t;
+        let from_right = self.gradient(left as i64 + 1) * (t - 1.0);
+        let fade = t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        // a value can be at most a half away from 0, so double it to reach -1 and 1
+        ((from_left + (from_right - from_left) * fade) * 2.0).clamp(-1.0, 1.0)
+    }
+}
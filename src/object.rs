@@ -0,0 +1,1217 @@
+use std::cmp;
+use std::sync::atomic::{AtomicU32, Ordering};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    GOD_MODE_POWER, Game, GameState, MAX_ASTAR_PATH, Messages, NOISE_FIGHT, NOISE_MOVE, PLAYER,
+    SPIKE_TRAP_DAMAGE, TELEPORT_TRAP_TRIES, delete_save_game, scale,
+};
+use crate::astar::AStar;
+use crate::colors::*;
+use crate::console::*;
+use crate::dijkstra::DijkstraMap;
+use crate::map::{Map, TileKind, TrapKind, new_item};
+use crate::render::Camera;
+use crate::scent::ScentMap;
+use crate::sound::Sound;
+
+// combat-related properties and methods (monster, player, NPC)
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fighter {
+    // stats without equipment bonuses, see Object::max_hp/power/defense
+    pub base_max_hp: i32,
+    pub hp: i32,
+    pub base_defense: i32,
+    pub base_power: i32,
+    // for monsters, the xp they're worth when killed. for the player, the xp gathered so far
+    pub xp: i32,
+    pub on_death: DeathCallback,
+    // a status the fighter's hits leave on their target, like a spider's poison
+    pub on_hit: Option<Status>,
+    #[serde(default)]
+    pub ability: Option<Ability>,
+}
+
+// something special a monster does besides hitting things, set in monsters.json
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Ability {
+    // a hit of at least `min_damage` that doesn't kill it splits it in two, each half with
+    // half of the hp it has left. see take_damage
+    Split { min_damage: i32 },
+    // heals by `percent` of the damage its hits deal. see hit
+    Drain { percent: i32 },
+}
+
+// a temporary effect on a creature, ticked once per turn until it runs out
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub kind: StatusKind,
+    pub turns_left: i32,
+    // damage or healing per turn, unused for slow
+    pub magnitude: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StatusKind {
+    // loses hp every turn
+    Poison,
+    // only acts every other turn
+    Slow,
+    // gains hp every turn
+    Regen,
+}
+
+impl StatusKind {
+    fn name(self) -> &'static str {
+        match self {
+            StatusKind::Poison => "poisoned",
+            StatusKind::Slow => "slowed",
+            StatusKind::Regen => "regenerating",
+        }
+    }
+}
+
+// what happens when a fighter's hp reaches zero
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DeathCallback {
+    Player,
+    Monster,
+}
+
+impl DeathCallback {
+    fn callback(self, object: &mut Object, game: &mut Game) {
+        use DeathCallback::*;
+        let callback: fn(&mut Object, &mut Game) = match self {
+            Player => player_death,
+            Monster => monster_death,
+        };
+        callback(object, game);
+    }
+}
+
+fn player_death(player: &mut Object, game: &mut Game) {
+    // the game ended!
+    game.messages.add_message("You died!", RED);
+    game.sounds.push(Sound::Death);
+
+    // for added effect, transform the player into a corpse!
+    player.char = '%';
+    player.color = DARK_RED;
+    game.state = GameState::Dead;
+
+    if let Err(err) = delete_save_game() {
+        eprintln!("Could not delete the saved game: {}", err);
+    }
+}
+
+fn monster_death(monster: &mut Object, game: &mut Game) {
+    // transform it into a nasty corpse! it doesn't block, can't be
+    // attacked and doesn't move
+    game.messages
+        .add_message(format!("{} is dead!", monster.name), ORANGE);
+    monster.char = '%';
+    monster.color = DARK_RED;
+    monster.blocks = false;
+    monster.fighter = None;
+    monster.ai = None;
+    monster.name = format!("remains of {}", monster.name);
+    if monster.loot.is_some() {
+        game.messages
+            .add_message(format!("Something glints among the {}.", monster.name), LIGHT_GREY);
+    }
+    if monster.gold > 0 {
+        game.messages
+            .add_message(format!("Coins spill from the {}.", monster.name), GOLD);
+    }
+}
+
+// how a monster decides what to do on its turn
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Ai {
+    // hasn't noticed the player yet, and stays put until it sees them. then it goes on
+    // as `awake_ai`
+    Asleep {
+        awake_ai: Box<Ai>,
+    },
+    // hunts the player. `alert` is how many more turns it keeps at it without seeing them,
+    // following their noise, before it gives up and dozes off again
+    Basic {
+        alert: i32,
+    },
+    // like a basic monster, but shoots at the player from up to `range` away
+    Ranged {
+        range: i32,
+        alert: i32,
+    },
+    // one of a leader's pack, see the follower AI. `leader` is the leader's id, and
+    // `awake_ai` is what it does once it hunts
+    Follower {
+        leader: u32,
+        awake_ai: Box<Ai>,
+    },
+    // runs from the player for `turns` more turns, then goes on as `then`
+    Fleeing {
+        turns: i32,
+        then: Box<Ai>,
+    },
+    // stumbles around at random, then goes back to `previous_ai`
+    Confused {
+        previous_ai: Box<Ai>,
+        num_turns: i32,
+    },
+}
+
+// the kinds of things that can be picked up and carried
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Item {
+    Heal,
+    Regen,
+    Lightning,
+    Confuse,
+    Fireball,
+    Sword,
+    Shield,
+    Greataxe,
+    // the quest item: using it wins the game
+    Amulet,
+}
+
+impl Item {
+    // potions and scrolls of a kind are all the same, so they share an inventory slot.
+    // equipment and the amulet each take their own
+    pub fn stacks(self) -> bool {
+        use Item::*;
+        match self {
+            Heal | Regen | Lightning | Confuse | Fireball => true,
+            Sword | Shield | Greataxe | Amulet => false,
+        }
+    }
+
+    // how heavy one of them is, see carry_capacity
+    pub fn weight(self) -> i32 {
+        use Item::*;
+        match self {
+            Heal | Regen => 2,
+            Lightning | Confuse | Fireball | Amulet => 1,
+            Sword => 10,
+            Shield => 15,
+            Greataxe => 25,
+        }
+    }
+
+    // what can be thrown, to shatter where it lands
+    pub fn is_potion(self) -> bool {
+        matches!(self, Item::Heal | Item::Regen)
+    }
+}
+
+// an object that can be equipped, yielding bonuses
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Equipment {
+    pub slot: Slot,
+    pub equipped: bool,
+    pub power_bonus: i32,
+    pub defense_bonus: i32,
+    pub max_hp_bonus: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Slot {
+    LeftHand,
+    RightHand,
+    // a two-handed weapon, which takes up both of the above
+    BothHands,
+    Head,
+}
+
+impl Slot {
+    // whether something in this slot and something in the other can't be worn together
+    pub fn overlaps(self, other: Slot) -> bool {
+        use Slot::*;
+        match (self, other) {
+            (BothHands, LeftHand | RightHand) | (LeftHand | RightHand, BothHands) => true,
+            _ => self == other,
+        }
+    }
+}
+
+impl std::fmt::Display for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Slot::LeftHand => write!(f, "left hand"),
+            Slot::RightHand => write!(f, "right hand"),
+            Slot::BothHands => write!(f, "both hands"),
+            Slot::Head => write!(f, "head"),
+        }
+    }
+}
+
+// a chest, opened by walking into it. what's inside is decided when it's placed, and
+// can be nothing at all
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Chest {
+    pub contents: Vec<Item>,
+    pub opened: bool,
+}
+
+// the id the next object gets. ids are never reused, so one that is remembered can't
+// end up naming some other object later; 0 is left for objects from older saves
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Object {
+    // stays the same while the object's place in the list changes, see index_of
+    #[serde(default)]
+    pub id: u32,
+    pub x: i32,
+    pub y: i32,
+    pub char: char,
+    #[serde(with = "crate::color_rgb")]
+    pub color: Color,
+    pub name: String,
+    pub blocks: bool,
+    pub alive: bool,
+    // drawn even outside of FOV once its tile has been explored, like stairs
+    pub always_visible: bool,
+    pub level: i32,
+    pub fighter: Option<Fighter>,
+    pub ai: Option<Ai>,
+    pub item: Option<Item>,
+    pub equipment: Option<Equipment>,
+    // an item the monster carries, left with its remains when it dies
+    pub loot: Option<Item>,
+    // the player's purse, the gold a monster carries, or the size of a pile of it
+    #[serde(default)]
+    pub gold: i32,
+    // a monster that won't go into deep water
+    #[serde(default)]
+    pub land_only: bool,
+    // how many of the item this is, for the ones that stack
+    #[serde(default = "one")]
+    pub count: i32,
+    pub statuses: Vec<Status>,
+    #[serde(default)]
+    pub chest: Option<Chest>,
+}
+fn one() -> i32 {
+    1
+}
+
+impl Object {
+    pub fn new(x: i32, y: i32, char: char, name: &str, color: Color, blocks: bool) -> Self {
+        Object {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            x,
+            y,
+            char,
+            color,
+            name: name.into(),
+            blocks,
+            alive: false,
+            always_visible: false,
+            level: 1,
+            fighter: None,
+            ai: None,
+            item: None,
+            equipment: None,
+            loot: None,
+            gold: 0,
+            land_only: false,
+            count: 1,
+            statuses: vec![],
+            chest: None,
+        }
+    }
+    pub fn pos(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+    pub fn set_pos(&mut self, x: i32, y: i32) {
+        self.x = x;
+        self.y = y;
+    }
+    // the name the player knows this by: potions and scrolls go by their looks until
+    // their kind is identified
+    pub fn display_name(&self, game: &Game) -> String {
+        if let Some(item) = self.item
+            && !game.identified.contains(&item)
+            && let Some((_, label)) = game.item_labels.iter().find(|(kind, _)| *kind == item)
+        {
+            return label.clone();
+        }
+        if self.chest.as_ref().is_some_and(|chest| chest.opened) {
+            return format!("open {}", self.name);
+        }
+        self.name.clone()
+    }
+    // the display name, with how many there are when it's a stack
+    pub fn stack_name(&self, game: &Game) -> String {
+        if self.count > 1 {
+            format!("{} (x{})", self.display_name(game), self.count)
+        } else {
+            self.display_name(game)
+        }
+    }
+    // what the whole stack weighs, nothing for what isn't an item
+    pub fn weight(&self) -> i32 {
+        self.item.map_or(0, |item| item.weight() * self.count)
+    }
+    fn is_player(&self) -> bool {
+        self.fighter.is_some_and(|fighter| fighter.on_death == DeathCallback::Player)
+    }
+    // return the distance to some coordinates
+    pub fn distance(&self, x: i32, y: i32) -> f32 {
+        (((x - self.x).pow(2) + (y - self.y).pow(2)) as f32).sqrt()
+    }
+    // return the distance to another object
+    pub fn distance_to(&self, other: &Object) -> f32 {
+        let dx = other.x - self.x;
+        let dy = other.y - self.y;
+        ((dx.pow(2) + dy.pow(2)) as f32).sqrt()
+    }
+    // apply damage if possible, hp never drops below zero.
+    // returns the xp the object was worth if this killed it
+    pub fn take_damage(&mut self, amount: i32, game: &mut Game) -> Option<i32> {
+        if game.god_mode && self.is_player() {
+            return None;
+        }
+        if let Some(fighter) = self.fighter.as_mut()
+            && amount > 0
+        {
+            fighter.hp = cmp::max(fighter.hp - amount, 0);
+            if fighter.on_death == DeathCallback::Player {
+                game.sounds.push(Sound::PlayerHurt);
+            }
+            // the other half goes on the map once the hit is over, see place_offspring
+            if let Some(Ability::Split { min_damage }) = fighter.ability
+                && amount >= min_damage
+                && let Some(offspring) = self.split()
+            {
+                game.offspring.push(offspring);
+            }
+        }
+        // check for death, call the death function
+        if let Some(fighter) = self.fighter
+            && fighter.hp <= 0
+            && self.alive
+        {
+            self.alive = false;
+            fighter.on_death.callback(self, game);
+            return Some(fighter.xp);
+        }
+        None
+    }
+    // halve the hp the monster has left, and make a copy of it with the other half. None
+    // when there's too little left to share
+    fn split(&mut self) -> Option<Object> {
+        let fighter = self.fighter.as_mut().filter(|fighter| fighter.hp >= 2)?;
+        let half = fighter.hp / 2;
+        fighter.hp -= half;
+        // the xp gets shared too, or splitting would be a way to farm experience
+        let offspring_xp = fighter.xp / 2;
+        fighter.xp -= offspring_xp;
+        let mut offspring = Object::new(self.x, self.y, self.char, &self.name, self.color, true);
+        offspring.alive = true;
+        offspring.level = self.level;
+        offspring.land_only = self.land_only;
+        offspring.ai = self.ai.clone();
+        offspring.fighter = Some(Fighter {
+            base_max_hp: half,
+            hp: half,
+            xp: offspring_xp,
+            ..*fighter
+        });
+        Some(offspring)
+    }
+    // heal by the given amount, without going over the maximum
+    pub fn heal(&mut self, amount: i32, game: &Game) {
+        let max_hp = self.max_hp(game);
+        if let Some(ref mut fighter) = self.fighter {
+            fighter.hp = cmp::min(fighter.hp + amount, max_hp);
+        }
+    }
+    // effective stats: the fighter's base values plus all equipment bonuses
+    pub fn power(&self, game: &Game) -> i32 {
+        let mut base_power = self.fighter.map_or(0, |f| f.base_power);
+        // monsters hit harder or softer depending on the difficulty
+        if !self.is_player() {
+            base_power = scale(base_power, game.difficulty.monster_power());
+        }
+        let bonus: i32 = self.get_all_equipped(game).iter().map(|e| e.power_bonus).sum();
+        base_power + bonus
+    }
+    pub fn defense(&self, game: &Game) -> i32 {
+        let base_defense = self.fighter.map_or(0, |f| f.base_defense);
+        let bonus: i32 = self.get_all_equipped(game).iter().map(|e| e.defense_bonus).sum();
+        base_defense + bonus
+    }
+    pub fn max_hp(&self, game: &Game) -> i32 {
+        let base_max_hp = self.fighter.map_or(0, |f| f.base_max_hp);
+        let bonus: i32 = self.get_all_equipped(game).iter().map(|e| e.max_hp_bonus).sum();
+        base_max_hp + bonus
+    }
+    // returns a list of equipped items
+    pub fn get_all_equipped(&self, game: &Game) -> Vec<Equipment> {
+        if self.name == "player" {
+            game.inventory
+                .iter()
+                .filter_map(|item| item.equipment)
+                .filter(|equipment| equipment.equipped)
+                .collect()
+        } else {
+            vec![] // other objects have no equipment
+        }
+    }
+    // equip object and show a message about it
+    pub fn equip(&mut self, messages: &mut Messages) {
+        if self.item.is_none() {
+            messages.add_message(
+                format!("Can't equip {:?} because it's not an Item.", self),
+                RED,
+            );
+            return;
+        };
+        if let Some(ref mut equipment) = self.equipment {
+            if !equipment.equipped {
+                equipment.equipped = true;
+                messages.add_message(
+                    format!("Equipped {} on {}.", self.name, equipment.slot),
+                    LIGHT_GREEN,
+                );
+            }
+        } else {
+            messages.add_message(
+                format!("Can't equip {:?} because it's not an Equipment.", self),
+                RED,
+            );
+        }
+    }
+    // dequip object and show a message about it
+    pub fn dequip(&mut self, messages: &mut Messages) {
+        if self.item.is_none() {
+            messages.add_message(
+                format!("Can't dequip {:?} because it's not an Item.", self),
+                RED,
+            );
+            return;
+        };
+        if let Some(ref mut equipment) = self.equipment {
+            if equipment.equipped {
+                equipment.equipped = false;
+                messages.add_message(
+                    format!("Dequipped {} from {}.", self.name, equipment.slot),
+                    LIGHT_YELLOW,
+                );
+            }
+        } else {
+            messages.add_message(
+                format!("Can't dequip {:?} because it's not an Equipment.", self),
+                RED,
+            );
+        }
+    }
+    // a simple formula for attack damage
+    pub fn attack(&mut self, target: &mut Object, game: &mut Game) {
+        self.hit(target, "attacks", game);
+    }
+    // a ranged attack: the same damage as up close, it just doesn't have to be adjacent
+    pub fn shoot(&mut self, target: &mut Object, game: &mut Game) {
+        self.hit(target, "shoots at", game);
+    }
+    fn hit(&mut self, target: &mut Object, verb: &str, game: &mut Game) {
+        let power = if game.god_mode && self.is_player() {
+            GOD_MODE_POWER
+        } else {
+            self.power(game)
+        };
+        let defense = target.defense(game);
+        let damage = power - defense;
+        if damage > 0 {
+            // make the target take some damage
+            game.messages.add_message(
+                format!(
+                    "{} {} {} for {} hit points.",
+                    self.name, verb, target.name, damage
+                ),
+                WHITE,
+            );
+            // the player getting hurt has a sound of its own
+            if target.fighter.is_some_and(|fighter| fighter.on_death != DeathCallback::Player) {
+                game.sounds.push(Sound::Hit);
+            }
+            if let Some(xp) = target.take_damage(damage, game)
+                && let Some(fighter) = self.fighter.as_mut()
+            {
+                // yield experience to the killer
+                fighter.xp += xp;
+            }
+            if let Some(Ability::Drain { percent }) = self.fighter.and_then(|f| f.ability) {
+                let drained = damage * percent / 100;
+                if drained > 0 && self.alive {
+                    game.messages
+                        .add_message(format!("{} drains {} hit points.", self.name, drained), RED);
+                    self.heal(drained, game);
+                }
+            }
+            if let Some(status) = self.fighter.and_then(|fighter| fighter.on_hit)
+                && target.alive
+            {
+                target.add_status(status, game);
+            }
+        } else {
+            game.messages.add_message(
+                format!(
+                    "{} {} {} but it has no effect!",
+                    self.name, verb, target.name
+                ),
+                WHITE,
+            );
+        }
+    }
+    // put a status on the creature. the same kind again doesn't stack, it lasts longer
+    pub fn add_status(&mut self, status: Status, game: &mut Game) {
+        if let Some(known) = self.statuses.iter_mut().find(|known| known.kind == status.kind) {
+            known.turns_left = cmp::max(known.turns_left, status.turns_left);
+            known.magnitude = cmp::max(known.magnitude, status.magnitude);
+        } else {
+            game.messages
+                .add_message(format!("{} is {}!", self.name, status.kind.name()), LIGHT_VIOLET);
+            self.statuses.push(status);
+        }
+    }
+    pub fn has_status(&self, kind: StatusKind) -> bool {
+        self.statuses.iter().any(|status| status.kind == kind)
+    }
+    // apply every status for one turn, then remove the ones that ran out
+    pub fn tick_statuses(&mut self, game: &mut Game) {
+        for i in 0..self.statuses.len() {
+            // the dead don't heal or suffer any further
+            if !self.alive {
+                break;
+            }
+            let status = self.statuses[i];
+            match status.kind {
+                StatusKind::Poison => {
+                    let text = format!(
+                        "The poison hurts {} for {} hit points.",
+                        self.name, status.magnitude
+                    );
+                    game.messages.add_message(text, LIGHT_VIOLET);
+                    self.take_damage(status.magnitude, game);
+                }
+                StatusKind::Regen => self.heal(status.magnitude, game),
+                // slow creatures skip turns instead, see Object::loses_turn
+                StatusKind::Slow => {}
+            }
+        }
+        if !self.alive {
+            self.statuses.clear();
+            return;
+        }
+        for status in self.statuses.iter_mut() {
+            status.turns_left -= 1;
+            if status.turns_left <= 0 {
+                let text = format!("{} is no longer {}.", self.name, status.kind.name());
+                game.messages.add_message(text, WHITE);
+            }
+        }
+        self.statuses.retain(|status| status.turns_left > 0);
+    }
+    // a slowed creature only gets every other round, see Game::tick
+    pub fn loses_turn(&self, tick: u32) -> bool {
+        self.has_status(StatusKind::Slow) && tick % 2 == 1
+    }
+    // draw the object where the camera shows its tile, if it's on screen at all, with the
+    // given glyph: its own char unless a tileset has another
+    pub fn draw(&self, glyph: char, con: &mut Offscreen, camera: &Camera) {
+        if let Some((x, y)) = camera.to_camera_coordinates(self.x, self.y) {
+            con.set_default_foreground(self.color);
+            con.put_char(x, y, glyph, BackgroundFlag::None);
+        }
+    }
+}
+
+pub fn is_blocked(x: i32, y: i32, map: &Map, objects: &[Object]) -> bool {
+    // first test the map tile, anything outside the map counts as blocked
+    if map.get(x, y).is_none_or(|tile| tile.blocked) {
+        return true;
+    }
+    // now check for any blocking objects
+    objects
+        .iter()
+        .any(|object| object.blocks && object.pos() == (x, y))
+}
+
+// whether the object can step onto the tile: it isn't blocked, and it's no deep water
+// for those who can't swim
+pub fn can_enter(id: usize, x: i32, y: i32, map: &Map, objects: &[Object]) -> bool {
+    let deep = map.get(x, y).is_some_and(|tile| tile.kind == TileKind::DeepWater);
+    let too_deep = deep && objects[id].land_only;
+    !is_blocked(x, y, map, objects) && !too_deep
+}
+
+// move by the given amount, if the destination can be entered
+pub fn move_by(id: usize, dx: i32, dy: i32, map: &Map, objects: &mut [Object]) {
+    let (x, y) = (objects[id].x + dx, objects[id].y + dy);
+    if can_enter(id, x, y, map, objects) {
+        objects[id].x = x;
+        objects[id].y = y;
+    }
+}
+
+// the things the player does something with by walking into them, rather than walking
+// there or attacking. a new kind of interactive thing gets a variant here, is found in
+// `at` and does its part in `on_bump`, and moving into it takes care of the rest
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interaction {
+    // a closed door, by its tile
+    Door(i32, i32),
+    // a closed chest, by its id
+    Chest(usize),
+}
+
+impl Interaction {
+    // whatever there is to interact with at the given position
+    pub fn at(x: i32, y: i32, game: &Game, objects: &[Object]) -> Option<Interaction> {
+        if game.map.get(x, y).is_some_and(|tile| tile.is_closed_door()) {
+            return Some(Interaction::Door(x, y));
+        }
+        objects
+            .iter()
+            .position(|object| {
+                object.pos() == (x, y) && object.chest.as_ref().is_some_and(|chest| !chest.opened)
+            })
+            .map(Interaction::Chest)
+    }
+
+    // the player walked into it, which takes the turn. true if that changed which tiles
+    // block sight or movement, so the FOV map has to catch up
+    pub fn on_bump(self, game: &mut Game, objects: &mut Vec<Object>) -> bool {
+        match self {
+            Interaction::Door(x, y) => {
+                if let Some(tile) = game.map.get_mut(x, y) {
+                    tile.open_door();
+                }
+                game.messages.add_message("You open the door.", WHITE);
+                true
+            }
+            Interaction::Chest(id) => {
+                open_chest(id, game, objects);
+                false
+            }
+        }
+    }
+}
+
+// move the player, or attack or interact with whatever is in the way. true if the map
+// changed on the way, see Interaction::on_bump
+pub fn player_move_or_attack(
+    dx: i32,
+    dy: i32,
+    game: &mut Game,
+    objects: &mut Vec<Object>,
+) -> bool {
+    // the coordinates the player is moving to/attacking
+    let (x, y) = (objects[PLAYER].x + dx, objects[PLAYER].y + dy);
+
+    if let Some(interaction) = Interaction::at(x, y, game, objects) {
+        return interaction.on_bump(game, objects);
+    }
+
+    // try to find an attackable object there
+    let target_id = objects.iter().position(|object| {
+        object.alive && object.blocks && object.fighter.is_some() && object.pos() == (x, y)
+    });
+
+    match target_id {
+        Some(target_id) if target_id != PLAYER => {
+            let (player, target) = mut_two(PLAYER, target_id, objects);
+            player.attack(target, game);
+            let (player_x, player_y) = objects[PLAYER].pos();
+            game.scent.emit(player_x, player_y, NOISE_FIGHT, &game.map);
+        }
+        _ => {
+            move_by(PLAYER, dx, dy, &game.map, objects);
+            if objects[PLAYER].pos() == (x, y) {
+                game.scent.emit(x, y, NOISE_MOVE, &game.map);
+                trigger_trap(PLAYER, game, objects);
+            }
+        }
+    }
+    false
+}
+
+// open a chest: it stops blocking the way, and what was inside falls out onto its tile
+pub fn open_chest(id: usize, game: &mut Game, objects: &mut Vec<Object>) {
+    let chest = &mut objects[id];
+    let Some(contents) = chest.chest.as_mut().map(|chest| {
+        chest.opened = true;
+        std::mem::take(&mut chest.contents)
+    }) else {
+        return;
+    };
+    chest.blocks = false;
+    // an open one is drawn darker, so it can be told apart from across the room
+    chest.color = DARKER_AMBER;
+    let (x, y) = chest.pos();
+    game.messages.add_message("You open the chest.", WHITE);
+    if contents.is_empty() {
+        game.messages.add_message("The chest is empty.", LIGHT_GREY);
+        return;
+    }
+    let items: Vec<Object> = contents.into_iter().map(|item| new_item(item, x, y)).collect();
+    let names: Vec<String> = items.iter().map(|item| item.display_name(game)).collect();
+    game.messages
+        .add_message(format!("Inside you find: {}.", names.join(", ")), LIGHT_VIOLET);
+    objects.extend(items);
+}
+
+// set off the trap under the object, if there is one: it gets revealed, and then it
+// hurts or teleports whoever stepped on it. spikes only come up once, a teleport trap
+// keeps working
+pub fn trigger_trap(id: usize, game: &mut Game, objects: &mut [Object]) {
+    let (x, y) = objects[id].pos();
+    let Some(tile) = game.map.get_mut(x, y) else {
+        return;
+    };
+    let Some(trap) = tile.trap.as_mut().filter(|trap| !trap.spent) else {
+        return;
+    };
+    trap.revealed = true;
+    match trap.kind {
+        TrapKind::Spike => {
+            trap.spent = true;
+            game.messages.add_message(
+                format!(
+                    "{} steps on a spike trap and takes {} damage!",
+                    objects[id].name, SPIKE_TRAP_DAMAGE
+                ),
+                RED,
+            );
+            objects[id].take_damage(SPIKE_TRAP_DAMAGE, game);
+        }
+        TrapKind::Teleport => {
+            // try a few random floor tiles, staying put if they're all taken. water and
+            // lava aren't floor, nobody gets dropped into those
+            for _ in 0..TELEPORT_TRAP_TRIES {
+                let x = game.rng.random_range(0..game.map.width);
+                let y = game.rng.random_range(0..game.map.height);
+                let floor = game.map.get(x, y).is_some_and(|tile| tile.kind == TileKind::Floor);
+                if floor && !is_blocked(x, y, &game.map, objects) {
+                    game.messages.add_message(
+                        format!("{} steps on a teleport trap and vanishes!", objects[id].name),
+                        LIGHT_BLUE,
+                    );
+                    objects[id].set_pos(x, y);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// move one step towards the target, if the way is not blocked
+fn move_towards(id: usize, target_x: i32, target_y: i32, map: &Map, objects: &mut [Object]) {
+    // vector from this object to the target, and distance
+    let dx = target_x - objects[id].x;
+    let dy = target_y - objects[id].y;
+    let distance = ((dx.pow(2) + dy.pow(2)) as f32).sqrt();
+    if distance == 0.0 {
+        return;
+    }
+
+    // normalize it to length 1 (preserving direction), then round it and
+    // convert to integer so the movement is restricted to the map grid
+    let dx = (dx as f32 / distance).round() as i32;
+    let dy = (dy as f32 / distance).round() as i32;
+    move_by(id, dx, dy, map, objects);
+}
+
+// step towards the target along an A* path, going around walls and other creatures
+pub fn move_astar(id: usize, target_id: usize, map: &Map, objects: &mut [Object]) {
+    // walls are impassable, and so is deep water for those who can't swim. everything
+    // else is walkable
+    // 1.41 is the normal diagonal cost of moving
+    let mut path = AStar::new(map.width, map.height, 1.41);
+    for y in 0..map.height {
+        for x in 0..map.width {
+            let Some(tile) = map.get(x, y) else {
+                continue;
+            };
+            let too_deep = tile.kind == TileKind::DeepWater && objects[id].land_only;
+            path.set_walkable(x, y, !tile.blocked && !too_deep);
+        }
+    }
+
+    // other blocking objects are obstacles too, except for the mover and its target
+    for (other_id, object) in objects.iter().enumerate() {
+        if object.blocks && other_id != id && other_id != target_id {
+            path.set_walkable(object.x, object.y, false);
+        }
+    }
+
+    let (target_x, target_y) = objects[target_id].pos();
+    path.find(objects[id].pos(), (target_x, target_y));
+
+    // keep the path short, so the monster doesn't run around the map if
+    // there's an alternative path really far away
+    if !path.is_empty() && path.len() < MAX_ASTAR_PATH {
+        if let Some((x, y)) = path.walk_one_step() {
+            let (dx, dy) = (x - objects[id].x, y - objects[id].y);
+            move_by(id, dx, dy, map, objects);
+        }
+    } else {
+        // no path: fall back to heading straight for the target
+        move_towards(id, target_x, target_y, map, objects);
+    }
+}
+
+// step to the free neighbouring tile that's closest to the goal of the distance map, if
+// any is closer than where the object stands. when the best tiles are taken by others,
+// the next best ones still get used, which spreads a group out along the way
+pub fn move_downhill(id: usize, distances: &DijkstraMap, map: &Map, objects: &mut [Object]) {
+    let (x, y) = objects[id].pos();
+    let Some(current) = distances.get(x, y) else {
+        return;
+    };
+    let mut best = None;
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let Some(distance) = distances.get(x + dx, y + dy) else {
+                continue;
+            };
+            let improves = best.is_none_or(|(best_distance, _)| distance < best_distance);
+            if distance < current && improves && can_enter(id, x + dx, y + dy, map, objects) {
+                best = Some((distance, (dx, dy)));
+            }
+        }
+    }
+    if let Some((_, (dx, dy))) = best {
+        move_by(id, dx, dy, map, objects);
+    }
+}
+
+// the opposite of move_downhill: step to the free neighbouring tile that's furthest from
+// the goal, to get away from it
+pub fn move_away(id: usize, distances: &DijkstraMap, map: &Map, objects: &mut [Object]) {
+    let (x, y) = objects[id].pos();
+    let Some(current) = distances.get(x, y) else {
+        return;
+    };
+    let mut best = None;
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let Some(distance) = distances.get(x + dx, y + dy) else {
+                continue;
+            };
+            let improves = best.is_none_or(|(best_distance, _)| distance > best_distance);
+            if distance > current && improves && can_enter(id, x + dx, y + dy, map, objects) {
+                best = Some((distance, (dx, dy)));
+            }
+        }
+    }
+    if let Some((_, (dx, dy))) = best {
+        move_by(id, dx, dy, map, objects);
+    }
+}
+
+// step to the loudest neighbouring tile, if it's louder than where the monster stands
+pub fn move_uphill(id: usize, scent: &ScentMap, map: &Map, objects: &mut [Object]) {
+    let (x, y) = objects[id].pos();
+    let current = scent.get(x, y);
+    let mut best = None;
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let loudness = scent.get(x + dx, y + dy);
+            let improves = best.is_none_or(|(best_loudness, _)| loudness > best_loudness);
+            if loudness > current && improves && can_enter(id, x + dx, y + dy, map, objects) {
+                best = Some((loudness, (dx, dy)));
+            }
+        }
+    }
+    if let Some((_, (dx, dy))) = best {
+        move_by(id, dx, dy, map, objects);
+    }
+}
+
+// where the object with the given id is in the list right now, if it's still there.
+// removing objects moves the ones after them, so anything that has to remember an object
+// across turns keeps its id rather than its index
+pub fn index_of(id: u32, objects: &[Object]) -> Option<usize> {
+    objects.iter().position(|object| object.id == id)
+}
+
+// after loading a game: new objects must not get any of the loaded ids, and objects from
+// a save that had no ids yet get one now
+pub fn continue_ids(objects: &mut [Object], inventory: &mut [Object]) {
+    let highest = objects.iter().chain(inventory.iter()).map(|object| object.id).max();
+    NEXT_ID.fetch_max(highest.unwrap_or(0) + 1, Ordering::Relaxed);
+    for object in objects.iter_mut().chain(inventory.iter_mut()) {
+        if object.id == 0 {
+            object.id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// mutably borrow two *separate* elements from the given slice.
+// panics when the indexes are equal or out of bounds.
+pub fn mut_two<T>(first_index: usize, second_index: usize, items: &mut [T]) -> (&mut T, &mut T) {
+    assert!(first_index != second_index);
+    let split_at_index = cmp::max(first_index, second_index);
+    let (first_slice, second_slice) = items.split_at_mut(split_at_index);
+    if first_index < second_index {
+        (&mut first_slice[first_index], &mut second_slice[0])
+    } else {
+        (&mut second_slice[0], &mut first_slice[second_index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{Tile, Trap, new_chest};
+    use crate::tests::{fighter, game};
+
+    #[test]
+    fn moving_off_the_map_stays_put() {
+        let map = Map::new(10, 10, Tile::empty());
+        let mut objects = vec![Object::new(0, 0, '@', "player", WHITE, true)];
+        move_by(0, -1, 0, &map, &mut objects);
+        move_by(0, 0, -1, &map, &mut objects);
+        assert_eq!(objects[0].pos(), (0, 0));
+    }
+
+    #[test]
+    fn attack_deals_power_minus_defense() {
+        let mut game = game();
+        let mut player = fighter("player", 0, 0, 30, 2, 5);
+        let mut orc = fighter("orc", 1, 0, 10, 1, 3);
+        player.attack(&mut orc, &mut game);
+        assert_eq!(orc.fighter.unwrap().hp, 6);
+        // the orc hits for 3, less the player's 2 of defense
+        orc.attack(&mut player, &mut game);
+        assert_eq!(player.fighter.unwrap().hp, 29);
+    }
+
+    #[test]
+    fn attack_never_heals() {
+        let mut game = game();
+        let mut player = fighter("player", 0, 0, 30, 2, 5);
+        let mut rat = fighter("rat", 1, 0, 4, 0, 1);
+        rat.attack(&mut player, &mut game);
+        assert_eq!(player.fighter.unwrap().hp, 30);
+        // not even when the defense is far above the power
+        player.fighter.as_mut().unwrap().base_defense = 10;
+        rat.attack(&mut player, &mut game);
+        assert_eq!(player.fighter.unwrap().hp, 30);
+    }
+
+    #[test]
+    fn damage_stops_at_zero() {
+        let mut player = fighter("player", 0, 0, 10, 0, 3);
+        player.take_damage(25, &mut game());
+        assert_eq!(player.fighter.unwrap().hp, 0);
+    }
+
+    #[test]
+    fn dead_monster_becomes_a_corpse() {
+        let mut game = game();
+        let mut orc = fighter("orc", 1, 0, 10, 0, 3);
+        orc.take_damage(10, &mut game);
+        assert!(!orc.alive);
+        assert!(!orc.blocks);
+        assert_eq!(orc.char, '%');
+        assert_eq!(orc.name, "remains of orc");
+        assert!(orc.fighter.is_none() && orc.ai.is_none());
+    }
+
+    #[test]
+    fn spike_trap_hurts_once() {
+        let mut game = game();
+        game.map.get_mut(1, 1).unwrap().trap = Some(Trap {
+            kind: TrapKind::Spike,
+            revealed: false,
+            spent: false,
+        });
+        let mut objects = vec![fighter("orc", 1, 1, 30, 0, 3)];
+        trigger_trap(0, &mut game, &mut objects);
+        assert_eq!(objects[0].fighter.unwrap().hp, 30 - SPIKE_TRAP_DAMAGE);
+        let trap = game.map.get(1, 1).unwrap().trap.unwrap();
+        assert!(trap.revealed && trap.spent);
+        // stepping on it again does nothing
+        trigger_trap(0, &mut game, &mut objects);
+        assert_eq!(objects[0].fighter.unwrap().hp, 30 - SPIKE_TRAP_DAMAGE);
+    }
+
+    #[test]
+    fn teleport_trap_only_lands_on_floor() {
+        let mut game = game();
+        game.map = Map::new(10, 10, Tile::new(TileKind::Lava));
+        let mut floor = Tile::empty();
+        floor.trap = Some(Trap {
+            kind: TrapKind::Teleport,
+            revealed: false,
+            spent: false,
+        });
+        *game.map.get_mut(1, 1).unwrap() = floor;
+        let mut objects = vec![fighter("orc", 1, 1, 30, 0, 3)];
+        // the only floor tile is taken by the orc itself, so it has nowhere to go
+        trigger_trap(0, &mut game, &mut objects);
+        assert_eq!(objects[0].pos(), (1, 1));
+        assert!(game.map.get(1, 1).unwrap().trap.unwrap().revealed);
+    }
+
+    #[test]
+    fn poison_lasts_its_turns() {
+        let mut game = game();
+        let mut orc = fighter("orc", 1, 1, 30, 0, 3);
+        let poison = Status {
+            kind: StatusKind::Poison,
+            turns_left: 3,
+            magnitude: 2,
+        };
+        orc.add_status(poison, &mut game);
+        for turn in 1..=3 {
+            orc.tick_statuses(&mut game);
+            assert_eq!(orc.fighter.unwrap().hp, 30 - 2 * turn);
+        }
+        assert!(!orc.has_status(StatusKind::Poison));
+        orc.tick_statuses(&mut game);
+        assert_eq!(orc.fighter.unwrap().hp, 24);
+    }
+
+    #[test]
+    fn slow_skips_every_other_turn() {
+        let mut game = game();
+        let mut orc = fighter("orc", 1, 1, 30, 0, 3);
+        assert!(!orc.loses_turn(1));
+        let slow = Status {
+            kind: StatusKind::Slow,
+            turns_left: 4,
+            magnitude: 0,
+        };
+        orc.add_status(slow, &mut game);
+        let lost: Vec<_> = (0..4).map(|turn| orc.loses_turn(turn)).collect();
+        assert_eq!(lost, [false, true, false, true]);
+    }
+
+    #[test]
+    fn hits_queue_their_sounds() {
+        let mut game = game();
+        let mut player = fighter("player", 0, 0, 30, 0, 5);
+        let mut orc = fighter("orc", 1, 0, 10, 0, 3);
+        player.attack(&mut orc, &mut game);
+        assert_eq!(game.sounds, [Sound::Hit]);
+        game.sounds.clear();
+        // the player being hit only makes the hurt sound
+        orc.attack(&mut player, &mut game);
+        assert_eq!(game.sounds, [Sound::PlayerHurt]);
+    }
+
+    #[test]
+    fn land_only_monsters_stay_out_of_deep_water() {
+        // a river between the orc and the player
+        let mut map = Map::new(7, 3, Tile::empty());
+        for y in 0..3 {
+            *map.get_mut(3, y).unwrap() = Tile::new(TileKind::DeepWater);
+        }
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5), fighter("orc", 5, 1, 10, 0, 3)];
+        objects[1].land_only = true;
+        for _ in 0..5 {
+            move_astar(1, PLAYER, &map, &mut objects);
+            assert_ne!(objects[1].x, 3);
+        }
+        // one that can swim goes straight across
+        objects[1].land_only = false;
+        for _ in 0..5 {
+            move_astar(1, PLAYER, &map, &mut objects);
+        }
+        assert_eq!(objects[1].pos(), (2, 1));
+    }
+
+    #[test]
+    fn ids_survive_removing_objects() {
+        let mut objects = vec![
+            fighter("player", 1, 1, 30, 2, 5),
+            new_item(Item::Heal, 2, 2),
+            fighter("orc captain", 3, 3, 20, 1, 4),
+            fighter("orc", 4, 3, 10, 0, 3),
+        ];
+        let leader = objects[2].id;
+        objects[3].ai = Some(Ai::Follower {
+            leader,
+            awake_ai: Box::new(Ai::Basic { alert: 0 }),
+        });
+        // the potion gets picked up, and everything after it shifts down
+        objects.remove(1);
+        let Some(Ai::Follower { leader, .. }) = objects[2].ai else {
+            panic!("the orc lost its ai");
+        };
+        assert_eq!(objects[index_of(leader, &objects).unwrap()].name, "orc captain");
+        assert_eq!(index_of(u32::MAX, &objects), None);
+    }
+
+    #[test]
+    fn bumping_a_door_opens_it() {
+        let mut game = game();
+        *game.map.get_mut(2, 1).unwrap() = Tile::closed_door();
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5)];
+        // opening it takes the turn, and tells the caller the map changed
+        assert!(player_move_or_attack(1, 0, &mut game, &mut objects));
+        assert_eq!(objects[PLAYER].pos(), (1, 1));
+        let door = game.map.get(2, 1).unwrap();
+        assert!(!door.is_closed_door() && !door.blocked && !door.block_sight);
+        // an open door is walked through like any floor
+        assert!(!player_move_or_attack(1, 0, &mut game, &mut objects));
+        assert_eq!(objects[PLAYER].pos(), (2, 1));
+    }
+
+    #[test]
+    fn bumping_a_chest_opens_it() {
+        let mut game = game();
+        let loot = vec![Item::Heal, Item::Sword];
+        let mut objects = vec![fighter("player", 1, 1, 30, 2, 5), new_chest(loot, 2, 1)];
+        // the chest's tile never blocked sight, so the FOV map is left alone
+        assert!(!player_move_or_attack(1, 0, &mut game, &mut objects));
+        assert_eq!(objects[PLAYER].pos(), (1, 1));
+        let chest = &objects[1];
+        assert!(chest.chest.as_ref().unwrap().opened && !chest.blocks);
+        assert_eq!(chest.display_name(&game), "open chest");
+        // the loot lies where the chest is
+        let items: Vec<_> = objects[2..].iter().map(|item| (item.item, item.pos())).collect();
+        assert_eq!(items, [(Some(Item::Heal), (2, 1)), (Some(Item::Sword), (2, 1))]);
+    }
+
+    #[test]
+    fn slime_splits_when_hit_hard_enough() {
+        let mut game = game();
+        let mut slime = fighter("slime", 2, 1, 16, 0, 3);
+        slime.fighter.as_mut().unwrap().ability = Some(Ability::Split { min_damage: 3 });
+        // too weak a hit only hurts it
+        slime.take_damage(2, &mut game);
+        assert!(game.offspring.is_empty());
+        slime.take_damage(4, &mut game);
+        assert!(slime.alive);
+        assert_eq!(game.offspring.len(), 1);
+        // the 10 hp it had left are shared between the two
+        assert_eq!(slime.fighter.unwrap().hp, 5);
+        assert_eq!(game.offspring[0].fighter.unwrap().hp, 5);
+        assert_eq!(game.offspring[0].pos(), (2, 1));
+        // and so is the xp for killing it, both halves together give what it did
+        let xp = slime.fighter.unwrap().xp + game.offspring[0].fighter.unwrap().xp;
+        assert_eq!(xp, 10);
+    }
+
+    #[test]
+    fn vampire_heals_by_what_it_drains() {
+        let mut game = game();
+        let mut player = fighter("player", 1, 1, 30, 1, 5);
+        let mut vampire = fighter("vampire", 2, 1, 18, 1, 7);
+        vampire.fighter.as_mut().unwrap().ability = Some(Ability::Drain { percent: 50 });
+        vampire.fighter.as_mut().unwrap().hp = 10;
+        vampire.attack(&mut player, &mut game);
+        // it hits for 6, and gets half of that back
+        assert_eq!(player.fighter.unwrap().hp, 24);
+        assert_eq!(vampire.fighter.unwrap().hp, 13);
+    }
+}
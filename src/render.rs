@@ -0,0 +1,897 @@
+use serde::{Deserialize, Serialize};
+use std::cmp;
+use std::time::{Duration, Instant};
+
+use crate::{
+    BAR_WIDTH, CAMERA_HEIGHT, CAMERA_WIDTH, CONFIRM_WIDTH, Game, INVENTORY_WIDTH, MAX_INVENTORY,
+    MINIMAP_MAX_HEIGHT, MINIMAP_MAX_WIDTH, MSG_X, PANEL_HEIGHT, PANEL_Y, PLAYER, SCREEN_HEIGHT,
+    SCREEN_WIDTH, RUBBLE_SIGHT_RANGE,
+    TORCH_FLICKER_BRIGHTNESS, TORCH_FLICKER_RADIUS, TORCH_FLICKER_SPEED, TORCH_RADIUS, Tcod,
+    carried_weight, carry_capacity, compute_fov, level_up_xp, wrap_text,
+};
+use crate::backend::{Input, Key, KeyCode, Mouse};
+use crate::colors::*;
+use crate::console::*;
+use crate::fov::FovMap;
+use crate::keys::Action;
+use crate::line::Line;
+use crate::map::{Map, Tile, TileKind, TrapKind};
+use crate::noise::Noise;
+use crate::object::Object;
+
+// the colors that matter most for telling things apart: walls from floors, and how hurt
+// something is
+pub struct Palette {
+    dark_wall: Color,
+    light_wall: Color,
+    dark_ground: Color,
+    light_ground: Color,
+    hp_bar: Color,
+    hp_bar_back: Color,
+    healthy: Color,
+    hurt: Color,
+}
+
+const CLASSIC_PALETTE: Palette = Palette {
+    dark_wall: Color { r: 0, g: 0, b: 100 },
+    light_wall: Color {
+        r: 130,
+        g: 110,
+        b: 50,
+    },
+    dark_ground: Color {
+        r: 50,
+        g: 50,
+        b: 150,
+    },
+    light_ground: Color {
+        r: 200,
+        g: 180,
+        b: 50,
+    },
+    hp_bar: LIGHT_RED,
+    hp_bar_back: DARKER_RED,
+    healthy: DARK_GREEN,
+    hurt: DARK_RED,
+};
+
+// walls and floors differ in brightness as well as in hue, and nothing relies on telling
+// red from green. the hues are from the Okabe-Ito colorblind-safe set
+const HIGH_CONTRAST_PALETTE: Palette = Palette {
+    dark_wall: Color {
+        r: 90,
+        g: 85,
+        b: 25,
+    },
+    light_wall: Color {
+        r: 240,
+        g: 228,
+        b: 66,
+    },
+    dark_ground: Color { r: 0, g: 30, b: 55 },
+    light_ground: Color {
+        r: 0,
+        g: 114,
+        b: 178,
+    },
+    hp_bar: Color {
+        r: 230,
+        g: 159,
+        b: 0,
+    },
+    hp_bar_back: Color { r: 80, g: 55, b: 0 },
+    healthy: Color {
+        r: 86,
+        g: 180,
+        b: 233,
+    },
+    hurt: Color {
+        r: 213,
+        g: 94,
+        b: 0,
+    },
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum PaletteKind {
+    #[default]
+    Classic,
+    HighContrast,
+}
+
+impl PaletteKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            PaletteKind::Classic => "classic",
+            PaletteKind::HighContrast => "high contrast",
+        }
+    }
+
+    // the one after this, for cycling through them in the menu
+    pub fn next(self) -> Self {
+        match self {
+            PaletteKind::Classic => PaletteKind::HighContrast,
+            PaletteKind::HighContrast => PaletteKind::Classic,
+        }
+    }
+
+    fn colors(self) -> &'static Palette {
+        match self {
+            PaletteKind::Classic => &CLASSIC_PALETTE,
+            PaletteKind::HighContrast => &HIGH_CONTRAST_PALETTE,
+        }
+    }
+}
+
+// the part of the map shown on screen, given by the map position of its top-left corner
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Camera {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Camera {
+    // center on the given position, but stop at the map edges instead of scrolling past them
+    pub fn center_on(&mut self, x: i32, y: i32, map: &Map) {
+        let max_x = cmp::max(map.width - CAMERA_WIDTH, 0);
+        let max_y = cmp::max(map.height - CAMERA_HEIGHT, 0);
+        self.x = (x - CAMERA_WIDTH / 2).clamp(0, max_x);
+        self.y = (y - CAMERA_HEIGHT / 2).clamp(0, max_y);
+    }
+
+    // convert map coordinates to coordinates on the map console, None if they're off screen
+    pub fn to_camera_coordinates(self, x: i32, y: i32) -> Option<(i32, i32)> {
+        let (x, y) = (x - self.x, y - self.y);
+        if x < 0 || y < 0 || x >= CAMERA_WIDTH || y >= CAMERA_HEIGHT {
+            None
+        } else {
+            Some((x, y))
+        }
+    }
+
+    // the other way around, e.g. for the mouse. None outside of the map window
+    pub fn to_map_coordinates(self, x: i32, y: i32) -> Option<(i32, i32)> {
+        if x < 0 || y < 0 || x >= CAMERA_WIDTH || y >= CAMERA_HEIGHT {
+            None
+        } else {
+            Some((x + self.x, y + self.y))
+        }
+    }
+}
+
+const COLOR_DARK_WATER: Color = DARKER_BLUE;
+const COLOR_LIGHT_WATER: Color = BLUE;
+const COLOR_LOOK_CURSOR: Color = LIGHT_GREY;
+const COLOR_DARK_DEEP_WATER: Color = DARKEST_BLUE;
+const COLOR_LIGHT_DEEP_WATER: Color = DARK_BLUE;
+const COLOR_DARK_LAVA: Color = DARKER_RED;
+const COLOR_LIGHT_LAVA: Color = ORANGE;
+const COLOR_DARK_RUBBLE: Color = DARKER_SEPIA;
+const COLOR_LIGHT_RUBBLE: Color = SEPIA;
+const COLOR_DOOR: Color = LIGHT_SEPIA;
+const COLOR_TRAP: Color = LIGHT_RED;
+
+// how the torch burns this frame: how far its full light reaches and how bright it is
+#[derive(Clone, Copy, Debug)]
+struct Flicker {
+    radius: f32,
+    brightness: f32,
+}
+
+impl Flicker {
+    // sample the noise by wall-clock time rather than by frame, so a higher fps doesn't
+    // make the torch flicker faster
+    fn at(noise: &Noise, elapsed: Duration) -> Self {
+        let time = elapsed.as_secs_f32() * TORCH_FLICKER_SPEED;
+        // two unrelated points on the same noise, both going from 0 to 1
+        let reach = (noise.get(time) + 1.0) / 2.0;
+        let dim = (noise.get(time + 1000.0) + 1.0) / 2.0;
+        Flicker {
+            radius: TORCH_RADIUS as f32 - TORCH_FLICKER_RADIUS * reach,
+            brightness: 1.0 - TORCH_FLICKER_BRIGHTNESS * dim,
+        }
+    }
+
+    // the color of a tile in view at the given distance from the player. past the edge
+    // of the light it fades, but never all the way to the color of unseen tiles
+    fn light(self, dark: Color, light: Color, distance: f32) -> Color {
+        let fade = ((distance - self.radius) / TORCH_FLICKER_RADIUS).clamp(0.0, 1.0);
+        let level = self.brightness * (1.0 - fade / 2.0);
+        lerp(dark, light, level)
+    }
+}
+
+// draw non-blocking objects (corpses, items, stairs) first so anything standing on
+// them stays visible, and the player last. only what the player can see is drawn
+fn draw_all(objects: &[Object], tcod: &mut Tcod, game: &Game, palette: &Palette) {
+    let fov = &tcod.fov;
+    let visible = |object: &Object| {
+        let explored = game.map.get(object.x, object.y).is_some_and(|t| t.explored);
+        fov.is_in_fov(object.x, object.y) || (object.always_visible && explored)
+    };
+    let mut to_draw: Vec<_> = objects
+        .iter()
+        .skip(PLAYER + 1)
+        .filter(|object| visible(object))
+        .collect();
+    // a stable sort, so objects sharing the blocks flag keep their relative order
+    to_draw.sort_by_key(|object| object.blocks);
+    // by the name the player knows it by, so the tileset can't give away a potion
+    let tileset = &tcod.tileset;
+    let glyph = |object: &Object| tileset.glyph(&object.display_name(game)).unwrap_or(object.char);
+    for object in to_draw {
+        object.draw(glyph(object), &mut tcod.con, &tcod.camera);
+        draw_health_tint(object, &mut tcod.con, &tcod.camera, game, palette);
+    }
+    objects[PLAYER].draw(glyph(&objects[PLAYER]), &mut tcod.con, &tcod.camera);
+}
+
+// hurt monsters get a background going from green to red as their hp runs out, so the
+// player can tell how close they are to dying. unhurt ones are left alone
+fn draw_health_tint(
+    object: &Object,
+    con: &mut Offscreen,
+    camera: &Camera,
+    game: &Game,
+    palette: &Palette,
+) {
+    let Some(fighter) = object.fighter else {
+        return;
+    };
+    let max_hp = object.max_hp(game);
+    if !object.alive || max_hp <= 0 || fighter.hp >= max_hp {
+        return;
+    }
+    if let Some((x, y)) = camera.to_camera_coordinates(object.x, object.y) {
+        let health = fighter.hp as f32 / max_hp as f32;
+        let tint = lerp(palette.hurt, palette.healthy, health);
+        con.set_char_background(x, y, tint, BackgroundFlag::Set);
+    }
+}
+
+pub fn menu<T: AsRef<str>>(
+    header: &str,
+    options: &[T],
+    width: i32,
+    tcod: &mut Tcod,
+) -> Option<usize> {
+    let key = popup(header, options, width, tcod);
+
+    // convert the ASCII code to an index; if it corresponds to an option, return it
+    if key.printable.is_ascii_alphabetic() {
+        let index = key.printable.to_ascii_lowercase() as usize - 'a' as usize;
+        if index < options.len() {
+            Some(index)
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
+// draw a window in the middle of the screen with the header and lettered options, and
+// wait for a key
+fn popup<T: AsRef<str>>(header: &str, options: &[T], width: i32, tcod: &mut Tcod) -> Key {
+    assert!(
+        options.len() <= MAX_INVENTORY,
+        "Cannot have a menu with more than 26 options."
+    );
+
+    // calculate total height for the header (after auto-wrap) and one line per option
+    let header_height = tcod.screen.get_height_rect(width, SCREEN_HEIGHT, header);
+    let height = options.len() as i32 + header_height;
+
+    // create an off-screen console that represents the menu's window
+    let mut window = Offscreen::new(width, height);
+
+    // print the header, with auto-wrap
+    window.set_default_foreground(WHITE);
+    window.print_rect_ex(
+        0,
+        0,
+        width,
+        height,
+        BackgroundFlag::None,
+        TextAlignment::Left,
+        header,
+    );
+
+    // print all the options
+    for (index, option_text) in options.iter().enumerate() {
+        let menu_letter = (b'a' + index as u8) as char;
+        let text = format!("({}) {}", menu_letter, option_text.as_ref());
+        window.print_ex(
+            0,
+            header_height + index as i32,
+            BackgroundFlag::None,
+            TextAlignment::Left,
+            text,
+        );
+    }
+
+    // blit the contents of "window" to the screen
+    let x = SCREEN_WIDTH / 2 - width / 2;
+    let y = SCREEN_HEIGHT / 2 - height / 2;
+    blit(&window, (0, 0), (width, height), &mut tcod.screen, (x, y), 1.0, 0.7);
+
+    // present the screen to the player and wait for a key-press
+    tcod.backend.present(&tcod.screen);
+    tcod.replay.wait_key(&mut *tcod.backend)
+}
+
+pub fn msgbox(text: &str, width: i32, tcod: &mut Tcod) {
+    let options: &[&str] = &[];
+    menu(text, options, width, tcod);
+}
+
+// ask a yes or no question, and wait until it's answered. Escape, or the window closing,
+// count as a no
+pub fn confirm(question: &str, tcod: &mut Tcod) -> bool {
+    let options: &[&str] = &[];
+    let text = format!("{}\n\n(y) Yes   (n) No", question);
+    loop {
+        let key = popup(&text, options, CONFIRM_WIDTH, tcod);
+        match key.printable.to_ascii_lowercase() {
+            'y' => return true,
+            'n' => return false,
+            _ if key.code == KeyCode::Escape || tcod.backend.window_closed() => return false,
+            _ => {}
+        }
+    }
+}
+
+pub fn inventory_menu(
+    game: &Game,
+    player: &Object,
+    header: &str,
+    tcod: &mut Tcod,
+) -> Option<usize> {
+    let inventory = &game.inventory;
+    let header = format!(
+        "{}Carrying {} of {}.\n",
+        header,
+        carried_weight(game),
+        carry_capacity(player)
+    );
+    // show a menu with each item of the inventory as an option
+    let options = if inventory.is_empty() {
+        vec!["Your inventory is empty.".into()]
+    } else {
+        inventory
+            .iter()
+            .map(|item| {
+                // show additional information, in case it's equipped
+                match item.equipment {
+                    Some(equipment) if equipment.equipped => {
+                        format!("{} (on {})", item.display_name(game), equipment.slot)
+                    }
+                    _ => item.stack_name(game),
+                }
+            })
+            .collect()
+    };
+
+    let inventory_index = menu(&header, &options, INVENTORY_WIDTH, tcod);
+
+    // if an item was chosen, return it
+    if !inventory.is_empty() {
+        inventory_index
+    } else {
+        None
+    }
+}
+
+// the whole level at a glance, as far as it has been explored, shown over the map until
+// the next key press. each cell stands for a square of tiles as big as it takes to fit
+pub fn minimap(tcod: &mut Tcod, game: &Game, player: &Object) {
+    let palette = tcod.options.palette.colors();
+    let scale = cmp::max(
+        (game.map.width + MINIMAP_MAX_WIDTH - 1) / MINIMAP_MAX_WIDTH,
+        (game.map.height + MINIMAP_MAX_HEIGHT - 1) / MINIMAP_MAX_HEIGHT,
+    )
+    .max(1);
+    let width = (game.map.width + scale - 1) / scale;
+    let height = (game.map.height + scale - 1) / scale;
+    let mut window = Offscreen::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            // a cell with any explored floor in it shows as floor, so corridors don't
+            // vanish between their walls
+            let tiles = (0..scale * scale)
+                .filter_map(|i| game.map.get(x * scale + i % scale, y * scale + i / scale))
+                .filter(|tile| tile.explored);
+            let mut seen = false;
+            let mut floor = false;
+            for tile in tiles {
+                seen = true;
+                floor |= !tile.block_sight;
+            }
+            if seen {
+                let color = if floor { palette.light_ground } else { palette.dark_wall };
+                window.set_char_background(x, y, color, BackgroundFlag::Set);
+            }
+        }
+    }
+    window.set_default_foreground(WHITE);
+    window.put_char(player.x / scale, player.y / scale, '@', BackgroundFlag::None);
+
+    let x = SCREEN_WIDTH / 2 - width / 2;
+    let y = SCREEN_HEIGHT / 2 - height / 2;
+    blit(&window, (0, 0), (width, height), &mut tcod.screen, (x, y), 1.0, 1.0);
+    tcod.backend.present(&tcod.screen);
+    tcod.replay.wait_key(&mut *tcod.backend);
+}
+
+// every message of the game on the whole screen, newest at the bottom. the movement and
+// page keys scroll it, and Escape closes it again without taking a turn
+pub fn message_log(tcod: &mut Tcod, game: &Game) {
+    let lines: Vec<(String, Color)> = game
+        .messages
+        .history()
+        .iter()
+        .flat_map(|(text, color)| {
+            let lines = wrap_text(text, (SCREEN_WIDTH - 2) as usize);
+            lines.into_iter().map(move |line| (line, *color))
+        })
+        .collect();
+    // a row for the title and one for the help at the bottom
+    let page = (SCREEN_HEIGHT - 2) as usize;
+    let last_top = lines.len().saturating_sub(page);
+    let mut top = last_top;
+    while !tcod.backend.window_closed() {
+        tcod.screen.set_default_background(BLACK);
+        tcod.screen.clear();
+        tcod.screen.set_default_foreground(WHITE);
+        let title = format!("Message log ({} of {})", (top + page).min(lines.len()), lines.len());
+        tcod.screen.print_ex(1, 0, BackgroundFlag::None, TextAlignment::Left, title);
+        for (y, (line, color)) in lines.iter().skip(top).take(page).enumerate() {
+            tcod.screen.set_default_foreground(*color);
+            let y = y as i32 + 1;
+            tcod.screen.print_ex(1, y, BackgroundFlag::None, TextAlignment::Left, line);
+        }
+        tcod.screen.set_default_foreground(LIGHT_GREY);
+        tcod.screen.print_ex(
+            1,
+            SCREEN_HEIGHT - 1,
+            BackgroundFlag::None,
+            TextAlignment::Left,
+            "Up/Down, PageUp/PageDown, Home/End to scroll, Escape to close",
+        );
+        tcod.backend.present(&tcod.screen);
+
+        let key = tcod.replay.wait_key(&mut *tcod.backend);
+        let action = tcod.key_bindings.action_for(key);
+        let step = action.and_then(|action| action.direction()).map(|(_, dy)| dy);
+        match key.code {
+            KeyCode::Escape => break,
+            KeyCode::PageUp => top = top.saturating_sub(page),
+            KeyCode::PageDown => top = (top + page).min(last_top),
+            KeyCode::Home => top = 0,
+            KeyCode::End => top = last_top,
+            _ if action == Some(Action::MessageLog) => break,
+            _ => match step {
+                Some(-1) => top = top.saturating_sub(1),
+                Some(1) => top = (top + 1).min(last_top),
+                _ => {}
+            },
+        }
+    }
+}
+
+// something flying across the map, like an arrow or a spell. it only shows, what it
+// does to its target has already been worked out
+#[derive(Clone, Copy, Debug)]
+pub struct Projectile {
+    pub from: (i32, i32),
+    pub to: (i32, i32),
+    pub glyph: char,
+    pub color: Color,
+}
+
+// an arrow points the way it flies
+pub fn arrow_glyph(from: (i32, i32), to: (i32, i32)) -> char {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    if dy.abs() * 2 < dx.abs() {
+        '-'
+    } else if dx.abs() * 2 < dy.abs() {
+        '|'
+    } else if (dx > 0) == (dy > 0) {
+        '\\'
+    } else {
+        '/'
+    }
+}
+
+// fly the queued projectiles over the last frame shown, one after the other, before the
+// next frame shows what they did. each takes `projectile_ms` from start to end however
+// far it goes, and a key press skips the rest
+pub fn animate_projectiles(tcod: &mut Tcod, queue: &mut Vec<Projectile>) {
+    let duration = Duration::from_millis(tcod.options.projectile_ms);
+    if duration.is_zero() {
+        queue.clear();
+        return;
+    }
+    for projectile in queue.drain(..) {
+        let path: Vec<_> = Line::new(projectile.from, projectile.to).collect();
+        let start = Instant::now();
+        while !path.is_empty() && start.elapsed() < duration {
+            let elapsed = start.elapsed().as_millis();
+            let (x, y) = path[path.len() * elapsed as usize / duration.as_millis() as usize];
+            // only where the player can see, the rest of the way is guesswork
+            let cell = tcod
+                .camera
+                .to_camera_coordinates(x, y)
+                .filter(|_| tcod.fov.is_in_fov(x, y));
+            let under = cell.map(|(x, y)| {
+                let under = (tcod.screen.get_char(x, y), tcod.screen.get_char_foreground(x, y));
+                tcod.screen.set_char(x, y, projectile.glyph);
+                tcod.screen.set_char_foreground(x, y, projectile.color);
+                under
+            });
+            tcod.backend.present(&tcod.screen);
+            if let (Some((x, y)), Some((glyph, color))) = (cell, under) {
+                tcod.screen.set_char(x, y, glyph);
+                tcod.screen.set_char_foreground(x, y, color);
+            }
+            // the key is only for skipping, it doesn't do anything in the game
+            let skipped = matches!(tcod.backend.poll_event(), Some(Input::Key(_)));
+            if skipped || tcod.backend.window_closed() {
+                return;
+            }
+        }
+    }
+}
+
+// something that just moved to a neighbouring tile, to be shown getting there. like a
+// projectile it only shows, it's already standing where it went
+#[derive(Clone, Copy, Debug)]
+pub struct Step {
+    pub from: (i32, i32),
+    pub to: (i32, i32),
+    pub glyph: char,
+    pub color: Color,
+}
+
+// show the steps of a turn all at once, over the frame with everything where it ended up:
+// each fades out of the tile it left while it fades into the one it went to. it takes
+// `move_ms` however many frames that is, and the first input cuts it short and is
+// handed back, so the next turn isn't kept waiting
+pub fn animate_steps(
+    tcod: &mut Tcod,
+    game: &mut Game,
+    objects: &[Object],
+    steps: &[Step],
+) -> Option<Input> {
+    let duration = Duration::from_millis(tcod.options.move_ms);
+    if duration.is_zero() || steps.is_empty() {
+        return None;
+    }
+    tcod.con.clear();
+    render_all(tcod, game, objects, true);
+    // the cells the steps cross that the player can see, with what is drawn there now
+    let mut cells = vec![];
+    for step in steps {
+        for (fade_in, (x, y)) in [(false, step.from), (true, step.to)] {
+            let cell = tcod
+                .camera
+                .to_camera_coordinates(x, y)
+                .filter(|_| tcod.fov.is_in_fov(x, y));
+            if let Some((x, y)) = cell {
+                let under = (tcod.screen.get_char(x, y), tcod.screen.get_char_foreground(x, y));
+                cells.push((x, y, fade_in, step, under));
+            }
+        }
+    }
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        let progress = start.elapsed().as_secs_f32() / duration.as_secs_f32();
+        for &(x, y, fade_in, step, _) in &cells {
+            let background = tcod.screen.get_char_background(x, y);
+            let amount = if fade_in { progress } else { 1.0 - progress };
+            tcod.screen.set_char(x, y, step.glyph);
+            tcod.screen.set_char_foreground(x, y, lerp(background, step.color, amount));
+        }
+        tcod.backend.present(&tcod.screen);
+        for &(x, y, _, _, (glyph, color)) in &cells {
+            tcod.screen.set_char(x, y, glyph);
+            tcod.screen.set_char_foreground(x, y, color);
+        }
+        let input = tcod.replay.poll_event(&mut *tcod.backend, game.turn);
+        if input.is_some() || tcod.backend.window_closed() {
+            return input;
+        }
+    }
+    None
+}
+
+pub fn render_all(tcod: &mut Tcod, game: &mut Game, objects: &[Object], fov_recompute: bool) {
+    let palette = tcod.options.palette.colors();
+    if fov_recompute {
+        // recompute FOV if needed (the player moved or something)
+        let player = &objects[PLAYER];
+        // rubble can be seen through up close, but blocks the view further away
+        for y in 0..game.map.height {
+            for x in 0..game.map.width {
+                if game.map.get(x, y).is_some_and(|tile| tile.kind == TileKind::Rubble) {
+                    let near = player.distance(x, y) <= RUBBLE_SIGHT_RANGE;
+                    tcod.fov.set_transparent(x, y, near);
+                }
+            }
+        }
+        compute_fov(tcod, player.x, player.y);
+    }
+
+    // scroll the map window so the player stays in view, or the look cursor while there
+    // is one
+    let (center_x, center_y) = tcod.look.unwrap_or(objects[PLAYER].pos());
+    tcod.camera.center_on(center_x, center_y, &game.map);
+
+    // the torch flicker only changes colors, the field of view above stays the same
+    let flicker = if tcod.options.flicker {
+        Some(Flicker::at(&tcod.noise, tcod.started.elapsed()))
+    } else {
+        None
+    };
+    let player = &objects[PLAYER];
+
+    // go through all tiles, and set their background color
+    for y in 0..game.map.height {
+        for x in 0..game.map.width {
+            let visible = tcod.fov.is_in_fov(x, y);
+            let Some(tile) = game.map.get_mut(x, y) else {
+                continue;
+            };
+            if visible {
+                // since it's visible, explore it
+                tile.explored = true;
+            }
+            // tiles never explored stay black
+            if !tile.explored {
+                continue;
+            }
+            let wall = tile.block_sight;
+            // the colors outside and inside of field of view
+            let (dark, light) = match (tile.kind, wall) {
+                (TileKind::Water, _) => (COLOR_DARK_WATER, COLOR_LIGHT_WATER),
+                (TileKind::DeepWater, _) => (COLOR_DARK_DEEP_WATER, COLOR_LIGHT_DEEP_WATER),
+                (TileKind::Lava, _) => (COLOR_DARK_LAVA, COLOR_LIGHT_LAVA),
+                (TileKind::Rubble, _) => (COLOR_DARK_RUBBLE, COLOR_LIGHT_RUBBLE),
+                // floors and walls (closed doors look like walls)
+                (_, true) => (palette.dark_wall, palette.light_wall),
+                (_, false) => (palette.dark_ground, palette.light_ground),
+            };
+            let color = match (visible, flicker) {
+                (false, _) => dark,
+                (true, None) => light,
+                (true, Some(flicker)) => flicker.light(dark, light, player.distance(x, y)),
+            };
+            // only the tiles inside the camera window end up on screen
+            if let Some((x, y)) = tcod.camera.to_camera_coordinates(x, y) {
+                tcod.con.set_char_background(x, y, color, BackgroundFlag::Set);
+                let tile_glyph = tcod.tileset.glyph(ground_name(tile));
+                if let Some(door) = tile.door {
+                    let glyph = tile_glyph.unwrap_or(if door.open { '/' } else { '+' });
+                    tcod.con.set_default_foreground(COLOR_DOOR);
+                    tcod.con.put_char(x, y, glyph, BackgroundFlag::None);
+                } else if let Some(glyph) = tile_glyph {
+                    // in ASCII the rest of the tiles are only a background
+                    tcod.con.set_default_foreground(if visible { WHITE } else { GREY });
+                    tcod.con.put_char(x, y, glyph, BackgroundFlag::None);
+                }
+                // traps stay hidden until something sets them off
+                if let Some(trap) = tile.trap.filter(|trap| trap.revealed) {
+                    let glyph = tcod.tileset.glyph(trap_name(trap.kind)).unwrap_or('^');
+                    tcod.con.set_default_foreground(COLOR_TRAP);
+                    tcod.con.put_char(x, y, glyph, BackgroundFlag::None);
+                }
+            }
+        }
+    }
+
+    // draw all objects in the list, now that the explored tiles are up to date
+    draw_all(objects, tcod, game, palette);
+    if let Some((x, y)) = tcod.look.and_then(|(x, y)| tcod.camera.to_camera_coordinates(x, y)) {
+        tcod.con.set_char_background(x, y, COLOR_LOOK_CURSOR, BackgroundFlag::Set);
+    }
+
+    // blit the contents of "con" to the screen
+    blit(
+        &tcod.con,
+        (0, 0),
+        (CAMERA_WIDTH, CAMERA_HEIGHT),
+        &mut tcod.screen,
+        (0, 0),
+        1.0,
+        1.0,
+    );
+
+    // prepare to render the GUI panel
+    tcod.panel.set_default_background(BLACK);
+    tcod.panel.clear();
+
+    // print the game messages, one line at a time, the most recent at the bottom
+    for (y, (msg, color)) in game.messages.iter().enumerate() {
+        tcod.panel.set_default_foreground(*color);
+        tcod.panel.print_ex(MSG_X, y as i32 + 1, BackgroundFlag::None, TextAlignment::Left, msg);
+    }
+
+    // show the player's stats
+    if let Some(fighter) = objects[PLAYER].fighter {
+        render_bar(
+            &mut tcod.panel,
+            1,
+            1,
+            BAR_WIDTH,
+            "HP",
+            fighter.hp,
+            objects[PLAYER].max_hp(game),
+            palette.hp_bar,
+            palette.hp_bar_back,
+        );
+    }
+
+    if let Some(fighter) = objects[PLAYER].fighter {
+        tcod.panel.print_ex(
+            1,
+            3,
+            BackgroundFlag::None,
+            TextAlignment::Left,
+            format!(
+                "Level: {} (XP {}/{})",
+                objects[PLAYER].level,
+                fighter.xp,
+                level_up_xp(objects[PLAYER].level)
+            ),
+        );
+    }
+    tcod.panel.print_ex(
+        1,
+        4,
+        BackgroundFlag::None,
+        TextAlignment::Left,
+        format!("Dungeon level: {}", game.dungeon_level),
+    );
+    tcod.panel.print_ex(
+        1,
+        5,
+        BackgroundFlag::None,
+        TextAlignment::Left,
+        format!("Gold: {}", objects[PLAYER].gold),
+    );
+    tcod.panel.print_ex(
+        1,
+        6,
+        BackgroundFlag::None,
+        TextAlignment::Left,
+        format!("Turn: {}", game.turn),
+    );
+
+    // display names of objects under the mouse
+    tcod.panel.set_default_foreground(LIGHT_GREY);
+    tcod.panel.print_ex(
+        1,
+        0,
+        BackgroundFlag::None,
+        TextAlignment::Left,
+        match tcod.look {
+            Some((x, y)) => describe_tile(x, y, objects, &tcod.fov, game),
+            None => get_names_under_mouse(tcod.mouse, objects, &tcod.fov, &tcod.camera, game),
+        },
+    );
+
+    // blit the contents of `panel` to the screen
+    blit(
+        &tcod.panel,
+        (0, 0),
+        (SCREEN_WIDTH, PANEL_HEIGHT),
+        &mut tcod.screen,
+        (0, PANEL_Y),
+        1.0,
+        1.0,
+    );
+}
+
+// what the player knows of a tile, for look mode: what it is and what can be seen on it
+fn describe_tile(x: i32, y: i32, objects: &[Object], fov_map: &FovMap, game: &Game) -> String {
+    let Some(tile) = game.map.get(x, y).filter(|tile| tile.explored) else {
+        return "unexplored".to_string();
+    };
+    let mut names = vec![ground_name(tile).to_string()];
+    if let Some(trap) = tile.trap.filter(|trap| trap.revealed) {
+        names.push(trap_name(trap.kind).to_string());
+    }
+    names.extend(
+        objects
+            .iter()
+            .filter(|obj| obj.pos() == (x, y))
+            .filter(|obj| fov_map.is_in_fov(x, y) || obj.always_visible)
+            .map(|obj| obj.display_name(game)),
+    );
+    names.join(", ")
+}
+
+// what a tile is called, in look mode and in the tileset
+fn ground_name(tile: &Tile) -> &'static str {
+    match (tile.door, tile.kind) {
+        (Some(door), _) if door.open => "open door",
+        (Some(_), _) => "closed door",
+        (None, TileKind::Floor) => "floor",
+        (None, TileKind::Wall) => "wall",
+        (None, TileKind::Water) => "shallow water",
+        (None, TileKind::DeepWater) => "deep water",
+        (None, TileKind::Lava) => "lava",
+        (None, TileKind::Rubble) => "rubble",
+    }
+}
+
+fn trap_name(kind: TrapKind) -> &'static str {
+    match kind {
+        TrapKind::Spike => "spike trap",
+        TrapKind::Teleport => "teleport trap",
+    }
+}
+
+// return a string with the names of all objects under the mouse
+fn get_names_under_mouse(
+    mouse: Mouse,
+    objects: &[Object],
+    fov_map: &FovMap,
+    camera: &Camera,
+    game: &Game,
+) -> String {
+    let Some((x, y)) = camera.to_map_coordinates(mouse.cx as i32, mouse.cy as i32) else {
+        return String::new();
+    };
+
+    // create a list with the names of all objects at the mouse's coordinates and in FOV
+    let names = objects
+        .iter()
+        .filter(|obj| obj.pos() == (x, y) && fov_map.is_in_fov(obj.x, obj.y))
+        .map(|obj| obj.display_name(game))
+        .collect::<Vec<_>>();
+
+    names.join(", ") // join the names, separated by commas
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_bar(
+    panel: &mut Offscreen,
+    x: i32,
+    y: i32,
+    total_width: i32,
+    name: &str,
+    value: i32,
+    maximum: i32,
+    bar_color: Color,
+    back_color: Color,
+) {
+    // render a bar (HP, experience, etc). First calculate the width of the bar,
+    // kept within 0..=total_width so an empty or overfull value still draws sanely
+    let bar_width = if maximum > 0 {
+        (value as f32 / maximum as f32 * total_width as f32) as i32
+    } else {
+        0
+    };
+    let bar_width = bar_width.clamp(0, total_width);
+
+    // render the background first
+    panel.set_default_background(back_color);
+    panel.rect(x, y, total_width, 1, false, BackgroundFlag::Screen);
+
+    // now render the bar on top
+    panel.set_default_background(bar_color);
+    if bar_width > 0 {
+        panel.rect(x, y, bar_width, 1, false, BackgroundFlag::Screen);
+    }
+
+    // finally, some centered text with the values
+    panel.set_default_foreground(WHITE);
+    panel.print_ex(
+        x + total_width / 2,
+        y,
+        BackgroundFlag::None,
+        TextAlignment::Center,
+        format!("{}: {}/{}", name, value, maximum),
+    );
+}
@@ -0,0 +1,356 @@
+// recording and playing back the input of a game. together with the seed, the inputs
+// are all it takes to play the exact same game again, down to the same death, which
+// makes for bug reports that can be reproduced. a log looks like this:
+//
+//     seed 1234
+//     difficulty Normal
+//     0 key Up 0
+//     1 text 0 g
+//     1 mouse 40 12 1 0
+//
+// older logs don't have the difficulty, those were all played on normal. each input
+// starts with the turn it came in on: keys by name with their alt state, printable
+// characters by themselves, and mouse events by cell and button presses
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::time::Duration;
+
+use crate::Difficulty;
+use crate::backend::{Backend, Input, Key, KeyCode, Mouse};
+use crate::keys::{key_code, key_name};
+
+enum Mode {
+    Off,
+    // every new game starts the log over, so it holds the last one played
+    Record {
+        path: String,
+        log: Option<BufWriter<File>>,
+    },
+    Play {
+        inputs: VecDeque<(u32, Input)>,
+        difficulty: Difficulty,
+    },
+}
+
+pub struct Replay {
+    mode: Mode,
+    // the turn of the game being played, as of the last poll
+    turn: u32,
+    // why playback stopped, until the game picks it up
+    ended: Option<String>,
+}
+
+impl Replay {
+    pub fn off() -> Self {
+        Replay {
+            mode: Mode::Off,
+            turn: 0,
+            ended: None,
+        }
+    }
+
+    pub fn record(path: &str) -> Self {
+        Replay {
+            mode: Mode::Record {
+                path: path.to_string(),
+                log: None,
+            },
+            ..Replay::off()
+        }
+    }
+
+    // read a log for playback, along with the seed it was recorded with
+    pub fn play(path: &str) -> Result<(Self, u64), String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        let mut lines = text.lines().enumerate();
+        let seed = lines
+            .next()
+            .and_then(|(_, line)| line.strip_prefix("seed "))
+            .and_then(|seed| seed.parse().ok())
+            .ok_or_else(|| format!("{} line 1: expected `seed <number>`", path))?;
+        // then the difficulty, which older logs don't have
+        let mut lines = lines.peekable();
+        let mut difficulty = Difficulty::Normal;
+        if let Some((_, line)) = lines.next_if(|(_, line)| line.starts_with("difficulty ")) {
+            let name = line.trim_start_matches("difficulty ");
+            difficulty = Difficulty::ALL
+                .into_iter()
+                .find(|difficulty| difficulty.name() == name)
+                .ok_or_else(|| format!("{} line 2: unknown difficulty `{}`", path, name))?;
+        }
+        let mut inputs = VecDeque::new();
+        for (number, line) in lines {
+            let input = parse_input(line)
+                .ok_or_else(|| format!("{} line {}: unknown input `{}`", path, number + 1, line))?;
+            inputs.push_back(input);
+        }
+        let replay = Replay {
+            mode: Mode::Play { inputs, difficulty },
+            ..Replay::off()
+        };
+        Ok((replay, seed))
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self.mode, Mode::Play { .. })
+    }
+
+    // the difficulty the log being played was recorded on
+    pub fn difficulty(&self) -> Difficulty {
+        match self.mode {
+            Mode::Play { difficulty, .. } => difficulty,
+            _ => Difficulty::Normal,
+        }
+    }
+
+    // a new game began: start the log over, headed by the game's seed and difficulty
+    pub fn start(&mut self, seed: u64, difficulty: Difficulty) -> Result<(), String> {
+        if let Mode::Record { path, log } = &mut self.mode {
+            let mut file = BufWriter::new(File::create(&*path).map_err(|err| err.to_string())?);
+            writeln!(file, "seed {}", seed).map_err(|err| err.to_string())?;
+            writeln!(file, "difficulty {}", difficulty.name()).map_err(|err| err.to_string())?;
+            *log = Some(file);
+        }
+        self.turn = 0;
+        Ok(())
+    }
+
+    // a loaded game can't be replayed from its seed, so it isn't recorded
+    pub fn pause(&mut self) {
+        if let Mode::Record { log, .. } = &mut self.mode {
+            *log = None;
+        }
+    }
+
+    // the next input for the given turn, from the log or the player
+    pub fn poll_event(&mut self, backend: &mut dyn Backend, turn: u32) -> Option<Input> {
+        self.turn = turn;
+        // the backend is still polled during playback, for the frame rate and to keep
+        // the window responsive, but what the player does is ignored until the end
+        let input = backend.poll_event();
+        if self.is_playing() {
+            return self.next_input();
+        }
+        if let Some(input) = input {
+            self.write(input);
+        }
+        input
+    }
+
+    // like poll_event, but blocks until the player does something
+    pub fn wait_event(&mut self, backend: &mut dyn Backend, turn: u32) -> Option<Input> {
+        self.turn = turn;
+        // playback doesn't wait for the player, only for the frame rate
+        if self.is_playing() {
+            backend.poll_event();
+            return self.next_input();
+        }
+        let input = backend.wait_event();
+        self.write(input);
+        Some(input)
+    }
+
+    // like poll_event, but gives the player up to `timeout` to do something
+    pub fn wait_event_timeout(
+        &mut self,
+        backend: &mut dyn Backend,
+        turn: u32,
+        timeout: Duration,
+    ) -> Option<Input> {
+        self.turn = turn;
+        if self.is_playing() {
+            backend.poll_event();
+            return self.next_input();
+        }
+        let input = backend.wait_event_timeout(timeout);
+        if let Some(input) = input {
+            self.write(input);
+        }
+        input
+    }
+
+    // block until a key is pressed, or take the next one from the log
+    pub fn wait_key(&mut self, backend: &mut dyn Backend) -> Key {
+        if self.is_playing() {
+            match self.next_input() {
+                Some(Input::Key(key)) => return key,
+                Some(Input::Mouse(_)) => self.stop("the log has a mouse event where a key was"),
+                None => {}
+            }
+        }
+        let key = backend.wait_key();
+        self.write(Input::Key(key));
+        key
+    }
+
+    // why playback just ended, once
+    pub fn take_ended(&mut self) -> Option<String> {
+        self.ended.take()
+    }
+
+    fn next_input(&mut self) -> Option<Input> {
+        let Mode::Play { inputs, .. } = &mut self.mode else {
+            return None;
+        };
+        match inputs.front() {
+            None => {
+                self.stop("end of the log");
+                None
+            }
+            // the inputs only make sense on the turn they were recorded on
+            Some(&(turn, _)) if turn != self.turn => {
+                let reason = format!("out of sync, expected turn {} but it's {}", turn, self.turn);
+                self.stop(&reason);
+                None
+            }
+            Some(_) => inputs.pop_front().map(|(_, input)| input),
+        }
+    }
+
+    // hand control back to the player
+    fn stop(&mut self, reason: &str) {
+        self.mode = Mode::Off;
+        self.ended = Some(reason.to_string());
+    }
+
+    fn write(&mut self, input: Input) {
+        let Mode::Record { log: Some(log), .. } = &mut self.mode else {
+            return;
+        };
+        let line = match input {
+            Input::Key(key) => format_key(key),
+            Input::Mouse(mouse) => Some(format!(
+                "mouse {} {} {} {}",
+                mouse.cx, mouse.cy, mouse.lbutton_pressed as u8, mouse.rbutton_pressed as u8
+            )),
+        };
+        // a log that can't be written is only a lost bug report, so the game goes on
+        if let Some(line) = line {
+            let _ = writeln!(log, "{} {}", self.turn, line).and_then(|_| log.flush());
+        }
+    }
+}
+
+// keys without a name don't do anything in the game, so they are left out
+fn format_key(key: Key) -> Option<String> {
+    if key.code == KeyCode::Text {
+        Some(format!("text {} {}", key.alt as u8, key.printable))
+    } else {
+        key_name(key.code).map(|name| format!("key {} {}", name, key.alt as u8))
+    }
+}
+
+fn parse_input(line: &str) -> Option<(u32, Input)> {
+    let (turn, rest) = line.split_once(' ')?;
+    let turn = turn.parse().ok()?;
+    let flag = |value: &str| match value {
+        "0" => Some(false),
+        "1" => Some(true),
+        _ => None,
+    };
+    // the character goes last and is kept as it is, since it can be a space
+    if let Some(rest) = rest.strip_prefix("text ") {
+        let (alt, printable) = rest.split_once(' ')?;
+        let mut chars = printable.chars();
+        let (Some(printable), None) = (chars.next(), chars.next()) else {
+            return None;
+        };
+        let key = Key {
+            code: KeyCode::Text,
+            printable,
+            alt: flag(alt)?,
+        };
+        return Some((turn, Input::Key(key)));
+    }
+    let fields: Vec<&str> = rest.split(' ').collect();
+    let input = match fields[..] {
+        ["key", name, alt] => Input::Key(Key {
+            code: key_code(name)?,
+            printable: '\0',
+            alt: flag(alt)?,
+        }),
+        ["mouse", cx, cy, left, right] => Input::Mouse(Mouse {
+            cx: cx.parse().ok()?,
+            cy: cy.parse().ok()?,
+            lbutton_pressed: flag(left)?,
+            rbutton_pressed: flag(right)?,
+            ..Default::default()
+        }),
+        _ => return None,
+    };
+    Some((turn, input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, printable: char, alt: bool) -> Key {
+        Key {
+            code,
+            printable,
+            alt,
+        }
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        let keys = [
+            key(KeyCode::Up, '\0', false),
+            key(KeyCode::Enter, '\0', true),
+            key(KeyCode::Text, 'g', false),
+            key(KeyCode::Text, ' ', true),
+        ];
+        for key in keys {
+            let line = format!("7 {}", format_key(key).unwrap());
+            let Some((7, Input::Key(parsed))) = parse_input(&line) else {
+                panic!("`{}` doesn't parse back", line);
+            };
+            assert_eq!(parsed, key);
+        }
+        assert!(parse_input("7 key NoSuchKey 0").is_none());
+        assert!(parse_input("7 text 0 ab").is_none());
+    }
+
+    #[test]
+    fn mouse_parses() {
+        let Some((3, Input::Mouse(mouse))) = parse_input("3 mouse 40 12 1 0") else {
+            panic!("the mouse event doesn't parse");
+        };
+        assert_eq!((mouse.cx, mouse.cy), (40, 12));
+        assert!(mouse.lbutton_pressed && !mouse.rbutton_pressed);
+    }
+
+    fn playing(lines: &[&str]) -> Replay {
+        Replay {
+            mode: Mode::Play {
+                inputs: lines.iter().map(|line| parse_input(line).unwrap()).collect(),
+                difficulty: Difficulty::Normal,
+            },
+            ..Replay::off()
+        }
+    }
+
+    #[test]
+    fn out_of_sync_stops_playback() {
+        let mut replay = playing(&["0 text 0 h", "2 text 0 l"]);
+        assert!(replay.next_input().is_some());
+        // the next input was for turn 2, but the game is only at turn 1
+        replay.turn = 1;
+        assert!(replay.next_input().is_none());
+        assert!(!replay.is_playing());
+        let reason = replay.take_ended().unwrap();
+        assert!(reason.contains("out of sync"), "{}", reason);
+        assert!(replay.take_ended().is_none());
+    }
+
+    #[test]
+    fn end_of_log_stops_playback() {
+        let mut replay = playing(&["0 key Escape 0"]);
+        assert!(replay.next_input().is_some());
+        assert!(replay.next_input().is_none());
+        assert!(!replay.is_playing());
+        assert_eq!(replay.take_ended().as_deref(), Some("end of the log"));
+    }
+}
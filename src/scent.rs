@@ -0,0 +1,97 @@
+use crate::map::Map;
+
+// how loud each tile is. the player makes noise by moving and fighting, and every turn it
+// spreads to the open tiles around and fades, so monsters that can't see the player can
+// still follow it to where something happened. walls and closed doors muffle it
+#[derive(Clone, Debug, Default)]
+pub struct ScentMap {
+    width: i32,
+    height: i32,
+    values: Vec<f32>,
+}
+
+impl ScentMap {
+    pub fn get(&self, x: i32, y: i32) -> f32 {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            self.values[(y * self.width + x) as usize]
+        } else {
+            0.0
+        }
+    }
+
+    // make a noise of the given loudness. it doesn't add up with what is already there,
+    // so standing in one place and fighting doesn't get louder and louder
+    pub fn emit(&mut self, x: i32, y: i32, loudness: f32, map: &Map) {
+        self.fit(map);
+        if map.in_bounds(x, y) {
+            let value = &mut self.values[map.idx(x, y)];
+            *value = value.max(loudness);
+        }
+    }
+
+    // one turn of spreading: every open tile moves `diffusion` of the way towards the
+    // average of its open neighbours, then loses `decay` of what it has
+    pub fn spread(&mut self, map: &Map, decay: f32, diffusion: f32) {
+        self.fit(map);
+        let mut spread = vec![0.0; self.values.len()];
+        for y in 0..map.height {
+            for x in 0..map.width {
+                if map.get(x, y).is_none_or(|tile| tile.blocked) {
+                    continue;
+                }
+                let mut total = 0.0;
+                let mut neighbours = 0;
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let (nx, ny) = (x + dx, y + dy);
+                        if (dx, dy) != (0, 0) && map.get(nx, ny).is_some_and(|tile| !tile.blocked) {
+                            total += self.get(nx, ny);
+                            neighbours += 1;
+                        }
+                    }
+                }
+                let own = self.get(x, y);
+                let average = if neighbours > 0 { total / neighbours as f32 } else { own };
+                spread[map.idx(x, y)] = (own + (average - own) * diffusion) * (1.0 - decay);
+            }
+        }
+        self.values = spread;
+    }
+
+    // start over silent if the map changed size, like on a loaded game
+    fn fit(&mut self, map: &Map) {
+        if self.width != map.width || self.height != map.height {
+            *self = ScentMap {
+                width: map.width,
+                height: map.height,
+                values: vec![0.0; map.tiles.len()],
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Tile;
+
+    #[test]
+    fn noise_stops_at_walls() {
+        // two open halves, split by a solid wall
+        let mut map = Map::new(9, 5, Tile::empty());
+        for y in 0..5 {
+            *map.get_mut(4, y).unwrap() = Tile::wall();
+        }
+        let mut scent = ScentMap::default();
+        scent.emit(2, 2, 100.0, &map);
+        for _ in 0..20 {
+            scent.spread(&map, 0.05, 0.5);
+        }
+        assert!(scent.get(3, 2) > 0.0);
+        for y in 0..5 {
+            for x in 4..9 {
+                assert_eq!(scent.get(x, y), 0.0, "({}, {})", x, y);
+            }
+        }
+    }
+}
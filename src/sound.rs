@@ -0,0 +1,130 @@
+// short sound effects for game events. the game logic only queues them up in `Game`, and
+// the main loop plays the queue. without the "sound" feature nothing is ever played, so
+// headless builds don't need an audio library at all
+#[cfg(feature = "sound")]
+use std::collections::HashMap;
+#[cfg(feature = "sound")]
+use std::fs;
+#[cfg(feature = "sound")]
+use std::io::Cursor;
+#[cfg(feature = "sound")]
+use std::path::Path;
+
+#[cfg(feature = "sound")]
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Source};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Sound {
+    // a melee attack that did damage
+    Hit,
+    // the player lost hp, from anything
+    PlayerHurt,
+    Pickup,
+    Descend,
+    Death,
+}
+
+#[cfg(feature = "sound")]
+impl Sound {
+    const ALL: [Sound; 5] = [
+        Sound::Hit,
+        Sound::PlayerHurt,
+        Sound::Pickup,
+        Sound::Descend,
+        Sound::Death,
+    ];
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Sound::Hit => "hit.wav",
+            Sound::PlayerHurt => "hurt.wav",
+            Sound::Pickup => "pickup.wav",
+            Sound::Descend => "descend.wav",
+            Sound::Death => "death.wav",
+        }
+    }
+}
+
+#[cfg(feature = "sound")]
+pub struct Sounds {
+    // None without an audio device. the stream has to stay alive while anything plays
+    output: Option<(OutputStream, OutputStreamHandle)>,
+    // the wav files, only the ones that could be read and decoded
+    clips: HashMap<Sound, Vec<u8>>,
+}
+
+#[cfg(feature = "sound")]
+impl Sounds {
+    // open the audio device and read the sounds from the given folder. nothing here is
+    // fatal: the game just stays quiet where a sound is missing
+    pub fn load(dir: &str) -> Self {
+        let output = match OutputStream::try_default() {
+            Ok(output) => Some(output),
+            Err(err) => {
+                eprintln!("Warning: no sound, could not open an audio device: {}", err);
+                None
+            }
+        };
+        let mut clips = HashMap::new();
+        if output.is_some() {
+            for sound in Sound::ALL {
+                let path = Path::new(dir).join(sound.file_name());
+                let clip = fs::read(&path).map_err(|err| err.to_string()).and_then(|bytes| {
+                    // check that it decodes now, rather than on every play
+                    Decoder::new(Cursor::new(bytes.clone()))
+                        .map(|_| bytes)
+                        .map_err(|err| err.to_string())
+                });
+                match clip {
+                    Ok(bytes) => {
+                        clips.insert(sound, bytes);
+                    }
+                    Err(err) => eprintln!("Warning: could not load {}: {}", path.display(), err),
+                }
+            }
+        }
+        Sounds { output, clips }
+    }
+
+    // play all the queued sounds, and empty the queue
+    pub fn play_all(&self, queue: &mut Vec<Sound>) {
+        for sound in queue.drain(..) {
+            if let Some((_, handle)) = &self.output
+                && let Some(bytes) = self.clips.get(&sound)
+                && let Ok(source) = Decoder::new(Cursor::new(bytes.clone()))
+            {
+                // a sound that fails to play is simply not heard
+                let _ = handle.play_raw(source.convert_samples());
+            }
+        }
+    }
+}
+
+// without the feature, sounds are queued and dropped
+#[cfg(not(feature = "sound"))]
+pub struct Sounds;
+
+#[cfg(not(feature = "sound"))]
+impl Sounds {
+    pub fn load(_dir: &str) -> Self {
+        Sounds
+    }
+
+    pub fn play_all(&self, queue: &mut Vec<Sound>) {
+        queue.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_sounds_are_skipped() {
+        // no such folder, and maybe no audio device either: the queue still empties
+        let sounds = Sounds::load("no-such-folder");
+        let mut queue = vec![Sound::Hit, Sound::Death];
+        sounds.play_all(&mut queue);
+        assert!(queue.is_empty());
+    }
+}
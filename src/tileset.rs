@@ -0,0 +1,130 @@
+// an optional graphical tileset for the tcod window. tileset.json names a font sheet and
+// which of its cells to draw each kind of tile or object with, by the name the game shows
+// for it in look mode:
+//
+//     {
+//         "font": "assets/tiles16x16.png",
+//         "layout": "in_row",
+//         "glyphs": { "wall": 219, "floor": 250, "orc": 128, "healing potion": 173 }
+//     }
+//
+// the layout is "tcod" (like arial10x10.png), "in_row" or "in_col", and "columns" and
+// "rows" can be given for sheets that aren't 16x16 or 32x8. whatever has no glyph here
+// keeps its usual character, so without the file the game looks exactly as it always did
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+#[cfg(feature = "tcod")]
+use tcod::console::FontLayout;
+
+pub const TILESET_FILE: &str = "tileset.json";
+
+const DEFAULT_FONT: &str = "arial10x10.png";
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Layout {
+    #[default]
+    Tcod,
+    InRow,
+    InCol,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TilesetFile {
+    font: String,
+    #[serde(default)]
+    layout: Layout,
+    // 0 lets libtcod work it out from the layout
+    #[serde(default)]
+    columns: i32,
+    #[serde(default)]
+    rows: i32,
+    #[serde(default)]
+    glyphs: HashMap<String, u32>,
+}
+
+// the font sheet only matters to the tcod window, the glyphs to every backend
+#[cfg_attr(not(feature = "tcod"), allow(dead_code))]
+pub struct Tileset {
+    pub font: String,
+    layout: Layout,
+    pub columns: i32,
+    pub rows: i32,
+    glyphs: HashMap<String, char>,
+}
+
+impl Default for Tileset {
+    // the arial font and plain ASCII
+    fn default() -> Self {
+        Tileset {
+            font: DEFAULT_FONT.to_string(),
+            layout: Layout::Tcod,
+            columns: 0,
+            rows: 0,
+            glyphs: HashMap::new(),
+        }
+    }
+}
+
+impl Tileset {
+    // the tileset from the given file. without one it's the default font, and a broken
+    // one is reported but not fatal
+    pub fn load(path: &str) -> Tileset {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Tileset::default(),
+            Err(err) => {
+                eprintln!("Warning: could not read {}: {}", path, err);
+                return Tileset::default();
+            }
+        };
+        Tileset::parse(&text).unwrap_or_else(|err| {
+            eprintln!("Warning: using the default font, ignoring {}: {}", path, err);
+            Tileset::default()
+        })
+    }
+
+    fn parse(text: &str) -> Result<Tileset, String> {
+        let file: TilesetFile = serde_json::from_str(text).map_err(|err| err.to_string())?;
+        // libtcod gives up on the whole game when it can't load the font
+        if !Path::new(&file.font).is_file() {
+            return Err(format!("there is no font file \"{}\"", file.font));
+        }
+        if file.columns < 0 || file.rows < 0 {
+            return Err("columns and rows can't be negative".to_string());
+        }
+        let mut glyphs = HashMap::new();
+        for (name, code) in file.glyphs {
+            // the consoles only hold 256 characters, so that's as far as a sheet goes
+            let glyph = u8::try_from(code)
+                .map_err(|_| format!("the glyph of \"{}\" is {}, past 255", name, code))?;
+            glyphs.insert(name, char::from(glyph));
+        }
+        Ok(Tileset {
+            font: file.font,
+            layout: file.layout,
+            columns: file.columns,
+            rows: file.rows,
+            glyphs,
+        })
+    }
+
+    #[cfg(feature = "tcod")]
+    pub fn font_layout(&self) -> FontLayout {
+        match self.layout {
+            Layout::Tcod => FontLayout::Tcod,
+            Layout::InRow => FontLayout::AsciiInRow,
+            Layout::InCol => FontLayout::AsciiInCol,
+        }
+    }
+
+    // the glyph for a tile or object of the given name, if the tileset has one
+    pub fn glyph(&self, name: &str) -> Option<char> {
+        self.glyphs.get(name).copied()
+    }
+}